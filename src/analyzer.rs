@@ -0,0 +1,306 @@
+use crate::capture;
+use crate::config::Config;
+use crate::keys;
+use crate::network::UdpSockets;
+use crate::node::Node;
+use crate::protocol::{Frame, OpenError, PacketKey};
+use crate::state::{FileContent, MessageContent, NoiseLevel, ObfuscationPattern, SendMessagePayload, TransmitCommand};
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const NOISE_LEVELS: [NoiseLevel; 4] = [NoiseLevel::Off, NoiseLevel::Slow, NoiseLevel::Medium, NoiseLevel::Fast];
+/// Средний интервал между текстовыми сообщениями; интервалы экспоненциальные, как у живого собеседника.
+const MEAN_TEXT_INTERVAL_SECS: f64 = 1.0;
+const FILE_SIZE: usize = 64 * 1024;
+/// Сколько ждём после сценария, чтобы дошли досылки и подтверждения.
+const DRAIN: Duration = Duration::from_secs(2);
+
+/// Что на самом деле лежит в пакете. Наблюдатель этого не видит, а анализ знает ключ.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Kind {
+    Data,
+    Control,
+    Cover,
+    Unknown,
+}
+
+impl Kind {
+    fn is_real(self) -> bool {
+        matches!(self, Kind::Data | Kind::Control)
+    }
+}
+
+struct ObservedPacket {
+    /// Время захвата по системным часам: они могут шагнуть назад, поэтому разности насыщающие.
+    at_us: u64,
+    size: usize,
+    kind: Kind,
+    bytes: Vec<u8>,
+}
+
+/// `asemic_new analyze-traffic [seconds]`: поднимает два узла на loopback, гоняет между ними
+/// текст, файл и шум на каждом уровне и смотрит на трафик глазами наблюдателя: размеры,
+/// интервалы, случайность байтов и то, насколько простой классификатор отличает шум от данных.
+pub async fn run_traffic_analysis(seconds: u64) {
+    let base_dir = std::env::temp_dir().join(format!("asemic-analyze-{}", std::process::id()));
+    println!("Each scenario sends texts (about one per {} s) and a {} KiB file for {} s", MEAN_TEXT_INTERVAL_SECS, FILE_SIZE / 1024, seconds);
+    for level in NOISE_LEVELS {
+        let scenario_dir = base_dir.join(format!("{:?}", level).to_lowercase());
+        match run_scenario(&scenario_dir, level, Duration::from_secs(seconds)).await {
+            Ok(packets) => print_report(level, &packets),
+            Err(e) => eprintln!("Scenario with noise {:?} failed: {}", level, e),
+        }
+    }
+    if let Err(e) = std::fs::remove_dir_all(&base_dir) {
+        eprintln!("Failed to remove {:?}: {}", base_dir, e);
+    }
+}
+
+/// Сценарий на двух узлах; возвращает пакеты отправителя, пойманные на стороне получателя.
+async fn run_scenario(dir: &Path, level: NoiseLevel, duration: Duration) -> std::io::Result<Vec<ObservedPacket>> {
+    let capture_path = dir.join("capture.pcap");
+    std::fs::create_dir_all(dir)?;
+    let sender_config = Arc::new(Config::default());
+    let receiver_config = Arc::new(Config { capture_path: Some(capture_path.clone()), ..Config::default() });
+    let sender = Node::start(&dir.join("sender"), UdpSockets::bind_loopback(&sender_config)?, sender_config.clone()).await?;
+    let receiver = Node::start(&dir.join("receiver"), UdpSockets::bind_loopback(&receiver_config)?, receiver_config).await?;
+    let unbound = || std::io::Error::other("node has no socket");
+    let sender_addr = sender.sockets.local_addr().ok_or_else(unbound)?;
+    let target = receiver.sockets.local_addr().ok_or_else(unbound)?;
+
+    let key = keys::generate_key();
+    sender.add_key(&key, None).await;
+    receiver.add_key(&key, None).await;
+    let payload = |content: MessageContent| SendMessagePayload {
+        target_addr: target.to_string(),
+        key: key.clone(),
+        pattern: ObfuscationPattern::Starfall,
+        content,
+        redundancy: 0.0,
+        ttl_secs: None,
+    };
+    let closed = || std::io::Error::other("transmitter stopped");
+
+    sender.transmit_tx.send(TransmitCommand::SetNoiseLevel(level)).await.map_err(|_| closed())?;
+    let started = Instant::now();
+    let file_at = duration / 3;
+    let mut file_sent = false;
+    let mut next_text_at = Duration::ZERO;
+    loop {
+        let elapsed = started.elapsed();
+        if elapsed >= duration {
+            break;
+        }
+        if elapsed >= next_text_at {
+            sender.send_message(payload(MessageContent::Text(random_text())), target).await.ok_or_else(closed)?;
+            let gap = -MEAN_TEXT_INTERVAL_SECS * (1.0 - rand::thread_rng().gen::<f64>()).ln();
+            next_text_at = elapsed + Duration::from_secs_f64(gap);
+        }
+        if !file_sent && elapsed >= file_at {
+            let mut data = vec![0u8; FILE_SIZE];
+            rand::thread_rng().fill(&mut data[..]);
+            let file = FileContent { filename: "sample.bin".to_string(), data, id: None, status: None };
+            sender.send_message(payload(MessageContent::File(file)), target).await.ok_or_else(closed)?;
+            file_sent = true;
+        }
+        let wake_at = if file_sent { next_text_at } else { next_text_at.min(file_at) };
+        tokio::time::sleep(wake_at.min(duration).saturating_sub(started.elapsed())).await;
+    }
+    tokio::time::sleep(DRAIN).await;
+    sender.shutdown();
+    receiver.shutdown();
+    // Поток захвата дописывает очередь и закрывает файл
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let packet_key = PacketKey::new(key.as_bytes());
+    let packets = capture::read_pcap(&capture_path)?
+        .into_iter()
+        .filter(|datagram| datagram.sender == sender_addr)
+        .map(|datagram| {
+            let kind = match packet_key.try_open(&datagram.payload) {
+                Ok(Frame::Chunk(_)) => Kind::Data,
+                Ok(Frame::Control(_)) => Kind::Control,
+                // Шум шифруется тем же ключом, но внутри случайные байты, а не кадр
+                Err(OpenError::Malformed) => Kind::Cover,
+                Err(OpenError::Authentication) => Kind::Unknown,
+            };
+            ObservedPacket { at_us: datagram.timestamp_us, size: datagram.payload.len(), kind, bytes: datagram.payload }
+        })
+        .collect();
+    Ok(packets)
+}
+
+fn random_text() -> String {
+    let mut rng = rand::thread_rng();
+    let len = rng.gen_range(5..200);
+    (0..len).map(|_| rng.gen_range(b'a'..=b'z') as char).collect()
+}
+
+fn print_report(level: NoiseLevel, packets: &[ObservedPacket]) {
+    let kinds: Vec<Kind> = {
+        let mut kinds: Vec<Kind> = packets.iter().map(|p| p.kind).collect();
+        kinds.sort();
+        kinds.dedup();
+        kinds
+    };
+    let count = |kind: Kind| packets.iter().filter(|p| p.kind == kind).count();
+    let span = match (packets.first(), packets.last()) {
+        (Some(first), Some(last)) => last.at_us.saturating_sub(first.at_us) as f64 / 1_000_000.0,
+        _ => 0.0,
+    };
+    println!();
+    println!(
+        "=== Noise {:?}: {} packets over {:.1} s ({}) ===",
+        level,
+        packets.len(),
+        span,
+        kinds.iter().map(|&k| format!("{:?} {}", k, count(k))).collect::<Vec<_>>().join(", ")
+    );
+    if packets.is_empty() {
+        return;
+    }
+
+    // Размеры: по корзинам PMTU все виды должны выглядеть одинаково
+    let mut sizes: BTreeMap<usize, BTreeMap<Kind, usize>> = BTreeMap::new();
+    for packet in packets {
+        *sizes.entry(packet.size).or_default().entry(packet.kind).or_default() += 1;
+    }
+    println!("  Packet size share, %  {}", kinds.iter().map(|k| format!("{:>9}", format!("{:?}", k))).collect::<String>());
+    for (size, by_kind) in &sizes {
+        let shares: String = kinds
+            .iter()
+            .map(|&k| format!("{:>9.1}", 100.0 * by_kind.get(&k).copied().unwrap_or(0) as f64 / count(k) as f64))
+            .collect();
+        println!("    {:>5} bytes         {}", size, shares);
+    }
+
+    // Интервалы до предыдущего пакета того же вида: строгая периодичность видна по min ≈ max
+    println!("  Interval to previous packet of the same kind, ms");
+    println!("    {:<10} {:>8} {:>8} {:>8} {:>8} {:>8} {:>6}", "kind", "min", "p10", "median", "p90", "max", "CV");
+    for &kind in &kinds {
+        let times: Vec<u64> = packets.iter().filter(|p| p.kind == kind).map(|p| p.at_us).collect();
+        let mut gaps: Vec<f64> = times.windows(2).map(|w| w[1].saturating_sub(w[0]) as f64 / 1000.0).collect();
+        if gaps.is_empty() {
+            println!("    {:<10} {:>8}", format!("{:?}", kind), "—");
+            continue;
+        }
+        gaps.sort_by(f64::total_cmp);
+        let mean = gaps.iter().sum::<f64>() / gaps.len() as f64;
+        let deviation = (gaps.iter().map(|g| (g - mean).powi(2)).sum::<f64>() / gaps.len() as f64).sqrt();
+        println!(
+            "    {:<10} {:>8.2} {:>8.2} {:>8.2} {:>8.2} {:>8.2} {:>6.2}",
+            format!("{:?}", kind),
+            gaps[0],
+            percentile(&gaps, 0.1),
+            percentile(&gaps, 0.5),
+            percentile(&gaps, 0.9),
+            gaps[gaps.len() - 1],
+            if mean > 0.0 { deviation / mean } else { 0.0 }
+        );
+    }
+
+    // Байты пакетов должны быть неотличимы от равномерно случайных
+    println!("  Byte distribution      {:>10} {:>12} {:>8}", "bits/byte", "chi2 (255)", "p");
+    for &kind in &kinds {
+        let mut histogram = [0u64; 256];
+        for packet in packets.iter().filter(|p| p.kind == kind) {
+            for &byte in &packet.bytes {
+                histogram[byte as usize] += 1;
+            }
+        }
+        let (entropy, chi_square) = byte_statistics(&histogram);
+        println!("    {:<20} {:>10.4} {:>12.1} {:>8.3}", format!("{:?}", kind), entropy, chi_square, chi_square_p_value(chi_square, 255.0));
+    }
+
+    let real = packets.iter().filter(|p| p.kind.is_real()).count();
+    if real == 0 || real == packets.len() {
+        println!("  Classifier: nothing to tell apart, all packets are {}", if real == 0 { "cover" } else { "real" });
+        return;
+    }
+    let classifier = train_classifier(packets);
+    println!(
+        "  Classifier (threshold on {}): {:.1}% accuracy telling cover from real traffic, {:.1}% by always guessing the majority",
+        classifier.feature, classifier.accuracy * 100.0, classifier.baseline * 100.0
+    );
+}
+
+fn percentile(sorted: &[f64], q: f64) -> f64 {
+    sorted[((sorted.len() - 1) as f64 * q).round() as usize]
+}
+
+/// Энтропия Шеннона в битах на байт и статистика хи-квадрат против равномерного распределения.
+fn byte_statistics(histogram: &[u64; 256]) -> (f64, f64) {
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return (0.0, 0.0);
+    }
+    let expected = total as f64 / 256.0;
+    let mut entropy = 0.0;
+    let mut chi_square = 0.0;
+    for &observed in histogram {
+        if observed > 0 {
+            let p = observed as f64 / total as f64;
+            entropy -= p * p.log2();
+        }
+        chi_square += (observed as f64 - expected).powi(2) / expected;
+    }
+    (entropy, chi_square)
+}
+
+/// Верхний хвост хи-квадрат через приближение Уилсона — Хилферти: для 255 степеней свободы его хватает.
+fn chi_square_p_value(chi_square: f64, degrees: f64) -> f64 {
+    let scale = 2.0 / (9.0 * degrees);
+    let z = ((chi_square / degrees).cbrt() - (1.0 - scale)) / scale.sqrt();
+    0.5 * erfc(z / std::f64::consts::SQRT_2)
+}
+
+/// erfc по Абрамовицу и Стигану 7.1.26, точность около 1e-7.
+fn erfc(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.327_591_1 * x.abs());
+    let poly = t * (0.254_829_592 + t * (-0.284_496_736 + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let value = poly * (-x * x).exp();
+    if x >= 0.0 { value } else { 2.0 - value }
+}
+
+struct Classifier {
+    feature: &'static str,
+    accuracy: f64,
+    baseline: f64,
+}
+
+/// Лучший порог по одному признаку, который видит наблюдатель: размер пакета и паузы
+/// до и после него. Учится на чётных пакетах, проверяется на нечётных.
+fn train_classifier(packets: &[ObservedPacket]) -> Classifier {
+    let gap = |a: &ObservedPacket, b: &ObservedPacket| b.at_us.saturating_sub(a.at_us) as f64;
+    let features: [(&'static str, Vec<f64>); 3] = [
+        ("packet size", packets.iter().map(|p| p.size as f64).collect()),
+        ("gap before", (0..packets.len()).map(|i| if i == 0 { f64::MAX } else { gap(&packets[i - 1], &packets[i]) }).collect()),
+        ("gap after", (0..packets.len()).map(|i| packets.get(i + 1).map_or(f64::MAX, |next| gap(&packets[i], next))).collect()),
+    ];
+    let labels: Vec<bool> = packets.iter().map(|p| p.kind == Kind::Cover).collect();
+    let train: Vec<usize> = (0..packets.len()).step_by(2).collect();
+    let test: Vec<usize> = (1..packets.len()).step_by(2).collect();
+
+    let accuracy = |values: &[f64], rows: &[usize], threshold: f64, above_is_cover: bool| {
+        let correct = rows.iter().filter(|&&i| ((values[i] > threshold) == above_is_cover) == labels[i]).count();
+        correct as f64 / rows.len().max(1) as f64
+    };
+    let mut best = ("packet size", 0.0, f64::MIN, true);
+    for (name, values) in &features {
+        for &i in &train {
+            for above_is_cover in [true, false] {
+                let train_accuracy = accuracy(values, &train, values[i], above_is_cover);
+                if train_accuracy > best.1 {
+                    best = (name, train_accuracy, values[i], above_is_cover);
+                }
+            }
+        }
+    }
+    let (feature, _, threshold, above_is_cover) = best;
+    let values = &features.iter().find(|(name, _)| *name == feature).expect("feature exists").1;
+    let cover_in_test = test.iter().filter(|&&i| labels[i]).count() as f64 / test.len().max(1) as f64;
+    Classifier { feature, accuracy: accuracy(values, &test, threshold, above_is_cover), baseline: cover_in_test.max(1.0 - cover_in_test) }
+}
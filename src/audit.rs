@@ -0,0 +1,506 @@
+use crate::keys::KeyRing;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// `prev` первой записи журнала.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+/// Ключи короче этого вычищаются из записи, только если совпадают со строкой целиком:
+/// короткий ключ легко найти внутри хэша или имени файла.
+const MIN_REDACTED_SUBSTRING_LEN: usize = 8;
+/// Как часто проверяем срок хранения записей.
+const PRUNE_INTERVAL_HOURS: i64 = 24;
+pub const MAX_QUERY_LIMIT: usize = 1000;
+const DEFAULT_QUERY_LIMIT: usize = 100;
+
+/// Событие, важное для безопасности. Ключи в журнал не попадают, только их отпечатки.
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    KeyAdded {
+        key: String,
+        source: KeySource,
+    },
+    KeyRemoved {
+        key: String,
+    },
+    KeyRotationProposed {
+        old_key: String,
+        new_key: String,
+        peer: SocketAddr,
+        activate_at: DateTime<Utc>,
+        retire_at: DateTime<Utc>,
+        initiated_locally: bool,
+    },
+    /// Пользователь согласился на ротацию, предложенную узлом.
+    KeyRotationAccepted {
+        old_key: String,
+        new_key: String,
+        peer: SocketAddr,
+    },
+    /// Ротация отклонена или не состоялась; остаётся старый ключ.
+    KeyRotationAborted {
+        old_key: String,
+        new_key: String,
+        peer: SocketAddr,
+        reason: String,
+    },
+    KeyRotationActivated {
+        old_key: String,
+        new_key: String,
+    },
+    KeyRetired {
+        key: String,
+    },
+    /// Файл собран и лежит в карантине.
+    FileReceived {
+        file_id: Uuid,
+        filename: String,
+        size: u64,
+        sha256: String,
+        peer: SocketAddr,
+    },
+    /// Файл отброшен правилами приёма, не дойдя до диска.
+    FileDropped {
+        filename: String,
+        size: u64,
+        peer: SocketAddr,
+        reason: String,
+    },
+    FileSaved {
+        file_id: Uuid,
+        stored_as: String,
+        sha256: String,
+    },
+    FileRejected {
+        file_id: Uuid,
+    },
+    FileDeleted {
+        file_id: Uuid,
+    },
+    /// Сводка неудачных расшифровок за окно: по одной записи на пакет журнал бы не выдержал.
+    DecryptFailures {
+        authentication: u64,
+        malformed_frames: u64,
+        malformed_messages: u64,
+        window_secs: u64,
+    },
+    /// Чанки уже собранного сообщения пришли снова.
+    ReplayDetected {
+        peer: SocketAddr,
+        msg_id: u32,
+        key: String,
+    },
+    /// Записи старше срока хранения удалены; цепочка теперь начинается с `first_seq`,
+    /// чей `prev` равен `first_prev`. Без оставшихся записей начало — сама эта запись.
+    LogPruned {
+        removed: u64,
+        first_seq: Option<u64>,
+        first_prev: String,
+    },
+    /// При открытии в журнале не нашлось записей, которые в нём уже были по сохранённой вершине.
+    LogTruncated {
+        head_seq: u64,
+        head_hash: String,
+        last_seq: Option<u64>,
+    },
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    Manual,
+    Generated,
+    Imported,
+}
+
+/// Журнал аудита: JSON-строки, только дописываются. Каждая запись хранит хэш
+/// предыдущей (`prev`) и свой (`hash`), так что правку или удаление записи
+/// из середины видно при проверке цепочки. Номер и хэш последней записи
+/// дублируются в отдельном файле вершины: по нему видно и удаление записей с конца.
+///
+/// Записывающий не ждёт диска: события уходят в очередь, а пишет и чистит журнал
+/// отдельный поток. Очередь без ограничения — событие аудита не пропускаем.
+#[derive(Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+    queue: Sender<AuditEvent>,
+}
+
+impl AuditLog {
+    /// Открывает журнал (см. `AuditWriter::open`) и запускает поток записи.
+    pub fn open(path: PathBuf, retention_days: u32, key_ring: Arc<KeyRing>) -> io::Result<Self> {
+        let (queue, events) = mpsc::channel::<AuditEvent>();
+        let mut writer = AuditWriter::open(path.clone(), retention_days, key_ring);
+        std::thread::Builder::new().name("audit".to_string()).spawn(move || {
+            while let Ok(event) = events.recv() {
+                writer.record(event);
+            }
+        })?;
+        Ok(Self { path, queue })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Ставит событие в очередь на запись.
+    pub fn record(&self, event: AuditEvent) {
+        if let Err(mpsc::SendError(event)) = self.queue.send(event) {
+            warn!("Audit writer has stopped, dropping {:?}", event);
+        }
+    }
+}
+
+/// Поток записи журнала: ведёт цепочку и вершину, удаляет просроченные записи.
+struct AuditWriter {
+    path: PathBuf,
+    /// Записи старше этого удаляются; `None` — хранить всё.
+    retention: Option<chrono::Duration>,
+    /// Текущие ключи, чтобы вычистить их, если они всё же попали в событие.
+    key_ring: Arc<KeyRing>,
+    next_seq: u64,
+    last_hash: String,
+    next_prune_at: DateTime<Utc>,
+}
+
+impl AuditWriter {
+    /// Открывает журнал, удаляет просроченные записи и продолжает цепочку с последней.
+    fn open(path: PathBuf, retention_days: u32, key_ring: Arc<KeyRing>) -> Self {
+        let retention = (retention_days > 0).then(|| chrono::Duration::days(retention_days as i64));
+        let mut log = Self {
+            path,
+            retention,
+            key_ring,
+            next_seq: 0,
+            last_hash: GENESIS_HASH.to_string(),
+            next_prune_at: Utc::now(),
+        };
+        let head = read_head(&log.path);
+        let mut truncated = None;
+        match read_entries(&log.path) {
+            Ok(entries) => {
+                let chain = verify(&entries, head.as_ref());
+                if let Some(seq) = chain.first_invalid_seq {
+                    warn!("Audit log {:?} fails verification at entry {}", log.path, seq);
+                }
+                let last_seq = entries.last().and_then(|last| last.get("seq")?.as_u64());
+                if let Some(last) = entries.last() {
+                    log.next_seq = last_seq.map_or(0, |seq| seq + 1);
+                    log.last_hash = last.get("hash").and_then(Value::as_str).unwrap_or(GENESIS_HASH).to_string();
+                }
+                if chain.tail_missing {
+                    warn!("Audit log {:?} ends before its recorded head", log.path);
+                    // Вершина перезапишется первой же записью, поэтому пропажу фиксируем в самом журнале
+                    truncated = head.map(|head| AuditEvent::LogTruncated { head_seq: head.seq, head_hash: head.hash, last_seq });
+                }
+            }
+            Err(e) => warn!("Failed to read audit log {:?}: {}", log.path, e),
+        }
+        if let Some(event) = truncated {
+            log.append(Utc::now(), &event);
+        }
+        log.prune(Utc::now());
+        log
+    }
+
+    /// Дописывает запись. Ошибка записи не должна останавливать обработку, поэтому только логируем.
+    fn record(&mut self, event: AuditEvent) {
+        let now = Utc::now();
+        if now >= self.next_prune_at {
+            self.prune(now);
+        }
+        self.append(now, &event);
+    }
+
+    fn append(&mut self, ts: DateTime<Utc>, event: &AuditEvent) {
+        let result = serde_json::to_value(event).map_err(io::Error::other).and_then(|mut value| {
+            // Поля цепочки не трогаем: в hex-хэше может найтись что угодно
+            redact(&mut value, &self.key_ring);
+            let Value::Object(fields) = &mut value else {
+                return Err(io::Error::other("audit event is not an object"));
+            };
+            fields.insert("seq".to_string(), Value::from(self.next_seq));
+            fields.insert("ts".to_string(), Value::String(ts.to_rfc3339()));
+            fields.insert("prev".to_string(), Value::String(self.last_hash.clone()));
+            let hash = entry_hash(&value);
+            if let Value::Object(fields) = &mut value {
+                fields.insert("hash".to_string(), Value::String(hash.clone()));
+            }
+            let mut line = canonical_json(&value);
+            line.push('\n');
+            OpenOptions::new().create(true).append(true).open(&self.path)?.write_all(line.as_bytes())?;
+            Ok(hash)
+        });
+        match result {
+            Ok(hash) => {
+                let head = Head { seq: self.next_seq, hash: hash.clone() };
+                if let Err(e) = write_head(&self.path, &head) {
+                    warn!("Failed to update audit log head for {:?}: {}", self.path, e);
+                }
+                self.next_seq += 1;
+                self.last_hash = hash;
+            }
+            Err(e) => warn!("Failed to write audit entry {:?}: {}", event, e),
+        }
+    }
+
+    /// Удаляет записи старше срока хранения. Удаляется только начало журнала,
+    /// поэтому оставшаяся цепочка по-прежнему проверяется.
+    fn prune(&mut self, now: DateTime<Utc>) {
+        self.next_prune_at = now + chrono::Duration::hours(PRUNE_INTERVAL_HOURS);
+        let Some(retention) = self.retention else { return };
+        let cutoff = now - retention;
+        let lines = match read_lines(&self.path) {
+            Ok(lines) => lines,
+            Err(e) => {
+                warn!("Failed to read audit log {:?} for pruning: {}", self.path, e);
+                return;
+            }
+        };
+        let expired = lines.iter().take_while(|line| parse_entry(line).and_then(|e| entry_time(&e)).is_some_and(|ts| ts < cutoff)).count();
+        if expired == 0 {
+            return;
+        }
+        // Оставшиеся строки переписываем как есть, даже неразборчивые: это улики
+        let kept = &lines[expired..];
+        let mut text = String::new();
+        for line in kept {
+            text.push_str(line);
+            text.push('\n');
+        }
+        let tmp_path = self.path.with_extension("jsonl.tmp");
+        if let Err(e) = fs::write(&tmp_path, text).and_then(|_| fs::rename(&tmp_path, &self.path)) {
+            warn!("Failed to prune audit log {:?}: {}", self.path, e);
+            return;
+        }
+        info!("Pruned {} audit entries older than {}", expired, cutoff);
+        let first = kept.first().and_then(|line| parse_entry(line));
+        let first_seq = first.as_ref().and_then(|entry| entry.get("seq")?.as_u64());
+        // Без оставшихся записей цепочку продолжит сама запись об удалении, и её `prev` — последний удалённый хэш
+        let first_prev = match &first {
+            Some(entry) => entry.get("prev").and_then(Value::as_str).unwrap_or_default().to_string(),
+            None => self.last_hash.clone(),
+        };
+        self.append(now, &AuditEvent::LogPruned { removed: expired as u64, first_seq, first_prev });
+    }
+}
+
+/// Фильтр для `GET /audit`.
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    /// Только записи с номером не меньше этого.
+    pub since_seq: Option<u64>,
+    pub event: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Результат проверки цепочки хэшей.
+#[derive(Serialize, Debug, Clone)]
+pub struct ChainStatus {
+    pub valid: bool,
+    pub entries: usize,
+    /// Первая запись, чей хэш, ссылка на предыдущую или номер не сходятся.
+    pub first_invalid_seq: Option<u64>,
+    /// Журнал кончается раньше записи, сохранённой как вершина: записи удалены с конца.
+    pub tail_missing: bool,
+}
+
+/// Номер и хэш последней записанной записи; хранятся рядом с журналом, в `.head`.
+#[derive(Serialize, Deserialize, Debug)]
+struct Head {
+    seq: u64,
+    hash: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AuditPage {
+    pub chain: ChainStatus,
+    pub entries: Vec<Value>,
+}
+
+/// Читает журнал, проверяет цепочку и отбирает записи по фильтру.
+/// Читает файл целиком, поэтому вызывается вне блокировки состояния.
+pub fn query(path: &Path, filter: &AuditFilter) -> io::Result<AuditPage> {
+    // Вершину читаем раньше журнала: она пишется после записи и не может его обогнать
+    let head = read_head(path);
+    let entries = read_entries(path)?;
+    let chain = verify(&entries, head.as_ref());
+    let limit = filter.limit.unwrap_or(DEFAULT_QUERY_LIMIT).min(MAX_QUERY_LIMIT);
+    let entries = entries
+        .into_iter()
+        .filter(|entry| filter.since_seq.is_none_or(|since| entry.get("seq").and_then(Value::as_u64).is_some_and(|seq| seq >= since)))
+        .filter(|entry| filter.event.as_deref().is_none_or(|event| entry.get("event").and_then(Value::as_str) == Some(event)))
+        .take(limit)
+        .collect();
+    Ok(AuditPage { chain, entries })
+}
+
+/// Строки журнала по порядку. Недописанная последняя строка пропускается.
+fn read_lines(path: &Path) -> io::Result<Vec<String>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let complete = match text.rfind('\n') {
+        Some(end) => &text[..end],
+        None => "",
+    };
+    Ok(complete.lines().filter(|line| !line.trim().is_empty()).map(str::to_string).collect())
+}
+
+/// Записи журнала; строка, которая не разбирается, становится `null` и не пройдёт проверку.
+fn read_entries(path: &Path) -> io::Result<Vec<Value>> {
+    Ok(read_lines(path)?.iter().map(|line| parse_entry(line).unwrap_or(Value::Null)).collect())
+}
+
+fn parse_entry(line: &str) -> Option<Value> {
+    serde_json::from_str(line).ok()
+}
+
+fn head_path(path: &Path) -> PathBuf {
+    path.with_extension("head")
+}
+
+/// Сохранённая вершина; если её нет или она не читается, проверяем журнал без неё.
+fn read_head(path: &Path) -> Option<Head> {
+    let text = match fs::read_to_string(head_path(path)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Failed to read audit log head for {:?}: {}", path, e);
+            return None;
+        }
+    };
+    serde_json::from_str(&text).map_err(|e| warn!("Audit log head for {:?} is malformed: {}", path, e)).ok()
+}
+
+fn write_head(path: &Path, head: &Head) -> io::Result<()> {
+    let head_path = head_path(path);
+    let tmp_path = head_path.with_extension("head.tmp");
+    fs::write(&tmp_path, serde_json::to_string(head).map_err(io::Error::other)?)?;
+    fs::rename(&tmp_path, &head_path)
+}
+
+/// С чего должна начинаться цепочка: с нулевой записи после `GENESIS_HASH`,
+/// а после удаления по сроку — с записи, указанной в последнем `log_pruned`.
+fn chain_start(entries: &[Value]) -> (Option<u64>, Option<&str>) {
+    let pruned = entries.iter().rev().find(|entry| entry.get("event").and_then(Value::as_str) == Some("log_pruned"));
+    match pruned {
+        Some(pruned) => {
+            let first_seq = match pruned.get("first_seq") {
+                Some(Value::Null) | None => pruned.get("seq").and_then(Value::as_u64),
+                Some(seq) => seq.as_u64(),
+            };
+            (first_seq, pruned.get("first_prev").and_then(Value::as_str))
+        }
+        None => (Some(0), Some(GENESIS_HASH)),
+    }
+}
+
+/// Проверяет цепочку: хэш каждой записи, ссылку на предыдущую и номера подряд.
+/// Начало сверяется с `chain_start`, конец — с сохранённой вершиной.
+fn verify(entries: &[Value], head: Option<&Head>) -> ChainStatus {
+    let (start_seq, start_prev) = chain_start(entries);
+    let mut previous: Option<(u64, &str)> = None;
+    let mut first_invalid_seq = None;
+    for (index, entry) in entries.iter().enumerate() {
+        let hash = entry.get("hash").and_then(Value::as_str);
+        let prev = entry.get("prev").and_then(Value::as_str);
+        let seq = entry.get("seq").and_then(Value::as_u64);
+        let mut unsigned = entry.clone();
+        if let Value::Object(fields) = &mut unsigned {
+            fields.remove("hash");
+        }
+        let links = match previous {
+            Some((previous_seq, previous_hash)) => prev == Some(previous_hash) && seq == Some(previous_seq + 1),
+            None => start_prev.is_some() && prev == start_prev && seq.is_some() && seq == start_seq,
+        };
+        let (Some(seq), Some(hash)) = (seq, hash) else {
+            first_invalid_seq = Some(index as u64);
+            break;
+        };
+        if hash != entry_hash(&unsigned) || !links {
+            first_invalid_seq = Some(seq);
+            break;
+        }
+        previous = Some((seq, hash));
+    }
+    // Вершина может отстать от журнала (не успела записаться), но не обогнать его
+    let mut tail_missing = false;
+    if let (Some(head), None) = (head, first_invalid_seq) {
+        match entries.iter().find(|entry| entry.get("seq").and_then(Value::as_u64) == Some(head.seq)) {
+            Some(entry) if entry.get("hash").and_then(Value::as_str) != Some(head.hash.as_str()) => first_invalid_seq = Some(head.seq),
+            Some(_) => {}
+            None => tail_missing = previous.is_none_or(|(last_seq, _)| last_seq < head.seq),
+        }
+    }
+    ChainStatus { valid: first_invalid_seq.is_none() && !tail_missing, entries: entries.len(), first_invalid_seq, tail_missing }
+}
+
+fn entry_time(entry: &Value) -> Option<DateTime<Utc>> {
+    entry.get("ts")?.as_str()?.parse().ok()
+}
+
+/// SHA-256 канонического JSON записи без поля `hash`; `prev` входит в запись.
+fn entry_hash(unsigned: &Value) -> String {
+    crate::files::sha256_hex(canonical_json(unsigned).as_bytes())
+}
+
+/// JSON с ключами объектов по алфавиту: хэш не зависит от порядка полей при разборе.
+fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(fields) => {
+            let mut names: Vec<&String> = fields.keys().collect();
+            names.sort();
+            let fields: Vec<String> = names
+                .into_iter()
+                .map(|name| format!("{}:{}", Value::String(name.clone()), canonical_json(&fields[name])))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::Array(items) => format!("[{}]", items.iter().map(canonical_json).collect::<Vec<_>>().join(",")),
+        scalar => scalar.to_string(),
+    }
+}
+
+/// Заменяет ключи, случайно попавшие в строковые поля, их отпечатками.
+fn redact(value: &mut Value, key_ring: &KeyRing) {
+    let (_, keys) = key_ring.snapshot();
+    if !keys.is_empty() {
+        redact_strings(value, &|text: &mut String| {
+            for (key, _) in keys.iter() {
+                if text == key || (key.len() >= MIN_REDACTED_SUBSTRING_LEN && text.contains(key.as_str())) {
+                    *text = text.replace(key.as_str(), &format!("key:{}", key_fingerprint(key)));
+                }
+            }
+        });
+    }
+}
+
+fn redact_strings(value: &mut Value, redact: &dyn Fn(&mut String)) {
+    match value {
+        Value::String(text) => redact(text),
+        Value::Array(items) => items.iter_mut().for_each(|item| redact_strings(item, redact)),
+        Value::Object(fields) => fields.values_mut().for_each(|field| redact_strings(field, redact)),
+        _ => {}
+    }
+}
+
+/// Контекст отпечатка: без него отпечаток совпадал бы с началом ключа AEAD,
+/// который тоже SHA-256 от ключа пользователя.
+const FINGERPRINT_CONTEXT: &[u8] = b"asemic fingerprint v1:";
+
+/// Отпечаток ключа для журналов: первые 8 байт производного ключа в hex.
+pub fn key_fingerprint(key: &str) -> String {
+    crate::protocol::derive_subkey(FINGERPRINT_CONTEXT, key.as_bytes())[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,436 @@
+use crate::protocol::MAX_PACKET_SIZE;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tracing::{debug, info};
+
+/// Сколько датаграмм принимаем или отправляем одним системным вызовом.
+pub const BATCH_SIZE: usize = 32;
+
+/// Пул буферов под входящие датаграммы: приём не выделяет память на каждый пакет,
+/// а потоки расшифровки возвращают буферы, когда пакет больше не нужен.
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    buffer_size: usize,
+    capacity: usize,
+}
+
+impl BufferPool {
+    pub fn new(buffer_size: usize, capacity: usize) -> Self {
+        Self { buffers: Mutex::new(Vec::with_capacity(capacity)), buffer_size, capacity }
+    }
+
+    /// Буфер длиной `buffer_size`.
+    pub fn take(&self) -> Vec<u8> {
+        let mut buffer = self.buffers.lock().unwrap_or_else(|e| e.into_inner()).pop().unwrap_or_default();
+        buffer.resize(self.buffer_size, 0);
+        buffer
+    }
+
+    /// Память свободных буферов в пуле.
+    pub fn pooled_bytes(&self) -> usize {
+        self.buffers.lock().unwrap_or_else(|e| e.into_inner()).iter().map(Vec::capacity).sum()
+    }
+
+    pub fn put(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() < self.buffer_size {
+            return;
+        }
+        buffer.clear();
+        let mut buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+        if buffers.len() < self.capacity {
+            buffers.push(buffer);
+        }
+    }
+}
+
+/// Входящие датаграммы, отброшенные до расшифровки.
+#[derive(Default)]
+pub struct RejectedDatagrams {
+    /// Не поместились в буфер приёма.
+    truncated: AtomicU64,
+    /// Длиннее любого нашего пакета (включая обрезанные).
+    oversized: AtomicU64,
+}
+
+impl RejectedDatagrams {
+    /// Пропускает датаграмму длиной `datagram_len`, принятую в буфер длиной `buffer_len`.
+    /// Обрезанная датаграмма всё равно не прошла бы проверку AEAD и выглядела бы как шум.
+    fn admit(&self, sender: SocketAddr, buffer_len: usize, datagram_len: usize) -> bool {
+        if datagram_len <= MAX_PACKET_SIZE {
+            return true;
+        }
+        self.oversized.fetch_add(1, Ordering::Relaxed);
+        if datagram_len > buffer_len {
+            self.truncated.fetch_add(1, Ordering::Relaxed);
+            debug!("Dropped a truncated datagram of {} bytes from {} (buffer is {} bytes)", datagram_len, sender, buffer_len);
+        } else {
+            debug!("Dropped an oversized datagram of {} bytes from {}", datagram_len, sender);
+        }
+        false
+    }
+
+    pub fn truncated(&self) -> u64 {
+        self.truncated.load(Ordering::Relaxed)
+    }
+
+    pub fn oversized(&self) -> u64 {
+        self.oversized.load(Ordering::Relaxed)
+    }
+}
+
+/// UDP-сокет с пакетным приёмом и отправкой. На Linux — `recvmmsg`/`sendmmsg`
+/// и UDP GSO, где ядро его поддерживает; на остальных платформах — по одному пакету.
+pub struct BatchSocket {
+    socket: Arc<UdpSocket>,
+    gso: AtomicBool,
+    rejected: RejectedDatagrams,
+}
+
+impl BatchSocket {
+    pub fn new(socket: Arc<UdpSocket>) -> Self {
+        let gso = sys::gso_supported(&socket);
+        info!("UDP batch I/O: {}, GSO {}", sys::BACKEND, if gso { "enabled" } else { "unavailable" });
+        Self { socket, gso: AtomicBool::new(gso), rejected: RejectedDatagrams::default() }
+    }
+
+    /// Сокет без GSO, например чтобы сравнить в бенчмарке.
+    pub fn without_gso(socket: Arc<UdpSocket>) -> Self {
+        Self { socket, gso: AtomicBool::new(false), rejected: RejectedDatagrams::default() }
+    }
+
+    pub fn rejected(&self) -> &RejectedDatagrams {
+        &self.rejected
+    }
+
+    /// Сколько датаграмм ядро отбросило из-за переполненного буфера сокета,
+    /// если платформа это сообщает.
+    pub fn kernel_drops(&self) -> Option<u64> {
+        sys::kernel_drops(&self.socket)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn gso_enabled(&self) -> bool {
+        self.gso.load(Ordering::Relaxed)
+    }
+
+    /// Одиночная отправка для служебных пакетов, которым пачка не нужна.
+    pub async fn send_to(&self, packet: &[u8], target: SocketAddr) -> io::Result<usize> {
+        self.socket.send_to(packet, target).await
+    }
+
+    /// Ждёт хотя бы одну датаграмму и забирает все уже пришедшие, но не больше `BATCH_SIZE`.
+    /// Буферы берутся из `pool` и обрезаются до длины датаграммы. Датаграммы, которые не
+    /// могут быть нашими пакетами по длине, отбрасываются и учитываются в `rejected`.
+    pub async fn recv_batch(&self, pool: &BufferPool, out: &mut Vec<(Vec<u8>, SocketAddr)>) -> io::Result<()> {
+        loop {
+            self.socket.readable().await?;
+            match self.socket.try_io(tokio::io::Interest::READABLE, || sys::recv_batch(&self.socket, pool, &self.rejected, out)) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// Отправляет пакеты, дожидаясь места в буфере сокета.
+    /// Возвращает результат для каждого пакета в том же порядке.
+    pub async fn send_batch(&self, packets: &[(Vec<u8>, SocketAddr)]) -> Vec<io::Result<usize>> {
+        let mut results = Vec::with_capacity(packets.len());
+        while results.len() < packets.len() {
+            let pending = &packets[results.len()..];
+            if let Err(e) = self.socket.writable().await {
+                results.extend(pending.iter().map(|_| Err(io::Error::new(e.kind(), e.to_string()))));
+                break;
+            }
+            let sent = self.socket.try_io(tokio::io::Interest::WRITABLE, || sys::send_batch(&self.socket, &self.gso, pending));
+            match sent {
+                Ok(sent) => results.extend(sent),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                // Ошибка относится к первому пакету, остальные пробуем отправить дальше
+                Err(e) => results.push(Err(e)),
+            }
+        }
+        results
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use super::{BufferPool, RejectedDatagrams, BATCH_SIZE};
+    use std::io;
+    use std::mem;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+    use std::os::fd::AsRawFd;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::net::UdpSocket;
+    use tracing::warn;
+
+    pub const BACKEND: &str = "recvmmsg/sendmmsg";
+    /// Предел ядра на число сегментов в одной GSO-отправке.
+    const MAX_GSO_SEGMENTS: usize = 64;
+    /// Суммарный размер GSO-отправки не может превышать размер одной UDP-датаграммы.
+    const MAX_GSO_BYTES: usize = 65_000;
+
+    pub fn gso_supported(socket: &UdpSocket) -> bool {
+        let mut value: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_UDP,
+                libc::UDP_SEGMENT,
+                &mut value as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        result == 0
+    }
+
+    /// Данные ядра о сокете из `/proc/net/udp` (или `udp6`): строку ищем по inode сокета,
+    /// счётчик отброшенных датаграмм — последний столбец.
+    pub fn kernel_drops(socket: &UdpSocket) -> Option<u64> {
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        if unsafe { libc::fstat(socket.as_raw_fd(), &mut stat) } != 0 {
+            return None;
+        }
+        let inode = stat.st_ino.to_string();
+        ["/proc/net/udp", "/proc/net/udp6"].iter().find_map(|path| {
+            let table = std::fs::read_to_string(path).ok()?;
+            table.lines().skip(1).find_map(|line| {
+                let columns: Vec<&str> = line.split_whitespace().collect();
+                (columns.get(9) == Some(&inode.as_str())).then(|| columns.last()?.parse().ok()).flatten()
+            })
+        })
+    }
+
+    pub fn recv_batch(socket: &UdpSocket, pool: &BufferPool, rejected: &RejectedDatagrams, out: &mut Vec<(Vec<u8>, SocketAddr)>) -> io::Result<()> {
+        let mut buffers: Vec<Vec<u8>> = (0..BATCH_SIZE).map(|_| pool.take()).collect();
+        let mut iovecs: [libc::iovec; BATCH_SIZE] = unsafe { mem::zeroed() };
+        let mut addrs: [libc::sockaddr_storage; BATCH_SIZE] = unsafe { mem::zeroed() };
+        let mut headers: [libc::mmsghdr; BATCH_SIZE] = unsafe { mem::zeroed() };
+        for i in 0..BATCH_SIZE {
+            iovecs[i] = libc::iovec { iov_base: buffers[i].as_mut_ptr() as *mut libc::c_void, iov_len: buffers[i].len() };
+            headers[i].msg_hdr.msg_name = &mut addrs[i] as *mut libc::sockaddr_storage as *mut libc::c_void;
+            headers[i].msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            headers[i].msg_hdr.msg_iov = &mut iovecs[i];
+            headers[i].msg_hdr.msg_iovlen = 1;
+        }
+        // С MSG_TRUNC ядро сообщает настоящую длину датаграммы, даже если она не поместилась
+        let received = unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                headers.as_mut_ptr(),
+                BATCH_SIZE as libc::c_uint,
+                libc::MSG_DONTWAIT | libc::MSG_TRUNC,
+                std::ptr::null_mut(),
+            )
+        };
+        if received < 0 {
+            let error = io::Error::last_os_error();
+            buffers.into_iter().for_each(|b| pool.put(b));
+            return Err(error);
+        }
+        let received = received as usize;
+        for (i, mut buffer) in buffers.drain(..).enumerate() {
+            let datagram_len = headers[i].msg_len as usize;
+            match (i < received).then(|| to_socket_addr(&addrs[i])).flatten() {
+                Some(sender) if rejected.admit(sender, buffer.len(), datagram_len) => {
+                    buffer.truncate(datagram_len);
+                    out.push((buffer, sender));
+                }
+                _ => pool.put(buffer),
+            }
+        }
+        Ok(())
+    }
+
+    /// Отправляет сколько получится из `packets`. Подряд идущие пакеты одного размера
+    /// одному узлу уходят одной GSO-отправкой, остальные — одним `sendmmsg`.
+    /// `WouldBlock` возвращается, только если не ушло ни одного пакета.
+    pub fn send_batch(socket: &UdpSocket, gso: &AtomicBool, packets: &[(Vec<u8>, SocketAddr)]) -> io::Result<Vec<io::Result<usize>>> {
+        if gso.load(Ordering::Relaxed) {
+            let run = gso_run_len(packets);
+            if run > 1 {
+                match send_gso(socket, &packets[..run]) {
+                    Ok(()) => return Ok(packets[..run].iter().map(|(p, _)| Ok(p.len())).collect()),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Err(e),
+                    Err(e) => {
+                        // EIO — у интерфейса нет аппаратной контрольной суммы; дальше без GSO
+                        if matches!(e.raw_os_error(), Some(libc::EIO | libc::EINVAL | libc::ENOPROTOOPT)) {
+                            warn!("UDP GSO send failed ({}), falling back to sendmmsg", e);
+                            gso.store(false, Ordering::Relaxed);
+                        }
+                    }
+                }
+            }
+        }
+        send_mmsg(socket, packets)
+    }
+
+    fn gso_run_len(packets: &[(Vec<u8>, SocketAddr)]) -> usize {
+        let (first, target) = &packets[0];
+        let max_segments = MAX_GSO_SEGMENTS.min(MAX_GSO_BYTES / first.len().max(1));
+        packets
+            .iter()
+            .take(max_segments)
+            .take_while(|(packet, addr)| addr == target && packet.len() == first.len())
+            .count()
+    }
+
+    fn send_gso(socket: &UdpSocket, packets: &[(Vec<u8>, SocketAddr)]) -> io::Result<()> {
+        let segment_size = packets[0].0.len() as u16;
+        let (mut addr, addr_len) = from_socket_addr(packets[0].1);
+        let mut iovecs: Vec<libc::iovec> = packets
+            .iter()
+            .map(|(packet, _)| libc::iovec { iov_base: packet.as_ptr() as *mut libc::c_void, iov_len: packet.len() })
+            .collect();
+        let space = unsafe { libc::CMSG_SPACE(mem::size_of::<u16>() as libc::c_uint) } as usize;
+        let mut control = vec![0u8; space];
+        let mut header: libc::msghdr = unsafe { mem::zeroed() };
+        header.msg_name = &mut addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+        header.msg_namelen = addr_len;
+        header.msg_iov = iovecs.as_mut_ptr();
+        header.msg_iovlen = iovecs.len() as _;
+        header.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        header.msg_controllen = space as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&header);
+            (*cmsg).cmsg_level = libc::SOL_UDP;
+            (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as libc::c_uint) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment_size);
+        }
+        let sent = unsafe { libc::sendmsg(socket.as_raw_fd(), &header, libc::MSG_DONTWAIT) };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn send_mmsg(socket: &UdpSocket, packets: &[(Vec<u8>, SocketAddr)]) -> io::Result<Vec<io::Result<usize>>> {
+        let count = packets.len().min(BATCH_SIZE);
+        let mut addrs: Vec<(libc::sockaddr_storage, libc::socklen_t)> = packets[..count].iter().map(|(_, a)| from_socket_addr(*a)).collect();
+        let mut iovecs: Vec<libc::iovec> = packets[..count]
+            .iter()
+            .map(|(packet, _)| libc::iovec { iov_base: packet.as_ptr() as *mut libc::c_void, iov_len: packet.len() })
+            .collect();
+        let mut headers: Vec<libc::mmsghdr> = (0..count)
+            .map(|i| {
+                let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+                header.msg_hdr.msg_name = &mut addrs[i].0 as *mut libc::sockaddr_storage as *mut libc::c_void;
+                header.msg_hdr.msg_namelen = addrs[i].1;
+                header.msg_hdr.msg_iov = &mut iovecs[i];
+                header.msg_hdr.msg_iovlen = 1;
+                header
+            })
+            .collect();
+        let sent = unsafe { libc::sendmmsg(socket.as_raw_fd(), headers.as_mut_ptr(), count as libc::c_uint, libc::MSG_DONTWAIT) };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(headers[..sent as usize].iter().map(|h| Ok(h.msg_len as usize)).collect())
+    }
+
+    fn to_socket_addr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+        match storage.ss_family as libc::c_int {
+            libc::AF_INET => {
+                let addr = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+                let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+                Some(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(addr.sin_port))))
+            }
+            libc::AF_INET6 => {
+                let addr = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in6) };
+                let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+                Some(SocketAddr::V6(SocketAddrV6::new(ip, u16::from_be(addr.sin6_port), addr.sin6_flowinfo, addr.sin6_scope_id)))
+            }
+            _ => None,
+        }
+    }
+
+    fn from_socket_addr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let len = match addr {
+            SocketAddr::V4(v4) => {
+                let sin = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in) };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = v4.port().to_be();
+                sin.sin_addr = libc::in_addr { s_addr: u32::from(*v4.ip()).to_be() };
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(v6) => {
+                let sin6 = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6) };
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = v6.port().to_be();
+                sin6.sin6_flowinfo = v6.flowinfo();
+                sin6.sin6_addr = libc::in6_addr { s6_addr: v6.ip().octets() };
+                sin6.sin6_scope_id = v6.scope_id();
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+        (storage, len as libc::socklen_t)
+    }
+}
+
+/// Запасной путь: по системному вызову на пакет, но с буферами из пула.
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use super::{BufferPool, RejectedDatagrams, BATCH_SIZE};
+    use std::io;
+    use std::net::SocketAddr;
+    use std::sync::atomic::AtomicBool;
+    use tokio::net::UdpSocket;
+
+    pub const BACKEND: &str = "one packet per syscall";
+
+    pub fn gso_supported(_socket: &UdpSocket) -> bool {
+        false
+    }
+
+    pub fn kernel_drops(_socket: &UdpSocket) -> Option<u64> {
+        None
+    }
+
+    pub fn recv_batch(socket: &UdpSocket, pool: &BufferPool, rejected: &RejectedDatagrams, out: &mut Vec<(Vec<u8>, SocketAddr)>) -> io::Result<()> {
+        let started = out.len();
+        while out.len() - started < BATCH_SIZE {
+            let mut buffer = pool.take();
+            match socket.try_recv_from(&mut buffer) {
+                Ok((len, sender)) => {
+                    // Настоящую длину здесь не узнать: заполненный до конца буфер считаем обрезкой
+                    let datagram_len = if len == buffer.len() { len + 1 } else { len };
+                    if rejected.admit(sender, buffer.len(), datagram_len) {
+                        buffer.truncate(len);
+                        out.push((buffer, sender));
+                    } else {
+                        pool.put(buffer);
+                    }
+                }
+                Err(e) => {
+                    pool.put(buffer);
+                    if out.len() > started && e.kind() == io::ErrorKind::WouldBlock {
+                        break;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn send_batch(socket: &UdpSocket, _gso: &AtomicBool, packets: &[(Vec<u8>, SocketAddr)]) -> io::Result<Vec<io::Result<usize>>> {
+        let mut results = Vec::new();
+        for (packet, target) in packets {
+            match socket.try_send_to(packet, *target) {
+                Ok(sent) => results.push(Ok(sent)),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock && !results.is_empty() => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(results)
+    }
+}
@@ -0,0 +1,179 @@
+use crate::batch_io::{BatchSocket, BufferPool, BATCH_SIZE};
+use crate::keys;
+use crate::protocol::{self, ControlFrame, Frame, PacketKey};
+use rand::{Rng, RngCore};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+const PACKETS_PER_KIND: usize = 2000;
+/// Сколько времени считаем принятые пакеты в бенчмарке приёма.
+const RECEIVE_WINDOW: Duration = Duration::from_secs(1);
+
+/// `asemic_new bench-keys [N...]`: сравнивает выбор ключа для входящего пакета
+/// полной пробной расшифровкой каждым ключом и по подсказке в пакете.
+pub fn run_key_hint_benchmark(key_counts: &[usize]) {
+    println!("{} real and {} noise packets of {} bytes per run", PACKETS_PER_KIND, PACKETS_PER_KIND, protocol::DEFAULT_PACKET_SIZE);
+    println!("{:>6}  {:>10}  {:>14}  {:>14}  {:>8}", "keys", "traffic", "trial pkt/s", "hinted pkt/s", "speedup");
+    for &key_count in key_counts {
+        let packet_keys: Vec<PacketKey> = (0..key_count).map(|_| PacketKey::new(keys::generate_key().as_bytes())).collect();
+        let frame = protocol::encode_frame(&Frame::Control(ControlFrame::Ack { msg_id: 1, chunks: vec![0, 1, 2] }));
+
+        // Настоящие пакеты зашифрованы случайным из ключей, шум — просто случайные байты
+        let mut rng = rand::thread_rng();
+        let real: Vec<Vec<u8>> = (0..PACKETS_PER_KIND)
+            .map(|_| packet_keys[rng.gen_range(0..key_count)].seal(frame.clone(), protocol::DEFAULT_PACKET_SIZE))
+            .collect();
+        let noise: Vec<Vec<u8>> = (0..PACKETS_PER_KIND)
+            .map(|_| {
+                let mut packet = vec![0u8; protocol::DEFAULT_PACKET_SIZE];
+                rng.fill_bytes(&mut packet);
+                packet
+            })
+            .collect();
+
+        for (traffic, packets) in [("real", &real), ("noise", &noise)] {
+            let trial = measure(packets, |packet| packet_keys.iter().any(|k| k.open(packet).is_some()));
+            let hinted = measure(packets, |packet| packet_keys.iter().any(|k| k.matches(packet) && k.open(packet).is_some()));
+            let trial_rate = packets.len() as f64 / trial.as_secs_f64();
+            let hinted_rate = packets.len() as f64 / hinted.as_secs_f64();
+            println!(
+                "{:>6}  {:>10}  {:>14.0}  {:>14.0}  {:>7.1}x",
+                key_count, traffic, trial_rate, hinted_rate, hinted_rate / trial_rate
+            );
+        }
+    }
+}
+
+/// Время обработки всех пакетов. Проверяет, что настоящие пакеты действительно расшифрованы.
+fn measure(packets: &[Vec<u8>], mut decrypt: impl FnMut(&[u8]) -> bool) -> Duration {
+    let started = Instant::now();
+    let decrypted = packets.iter().filter(|p| decrypt(p)).count();
+    let elapsed = started.elapsed();
+    assert!(decrypted == 0 || decrypted == packets.len(), "only {} of {} packets decrypted", decrypted, packets.len());
+    elapsed
+}
+
+#[derive(Clone, Copy, Debug)]
+enum UdpMode {
+    PerPacket,
+    Batched,
+    Gso,
+}
+
+/// `asemic_new bench-udp [N]`: пакетов в секунду через loopback при отправке и приёме
+/// по одному пакету за системный вызов и пачками (`sendmmsg`/`recvmmsg`, GSO).
+pub async fn run_udp_benchmark(packet_count: usize) {
+    let probe = Arc::new(UdpSocket::bind("127.0.0.1:0").await.expect("Failed to bind benchmark socket"));
+    let gso_available = BatchSocket::new(probe).gso_enabled();
+    let mut modes = vec![UdpMode::PerPacket, UdpMode::Batched];
+    if gso_available {
+        modes.push(UdpMode::Gso);
+    }
+
+    println!("{} packets of {} bytes over loopback", packet_count, protocol::DEFAULT_PACKET_SIZE);
+    println!("{:>10}  {:>12}  {:>12}", "mode", "send pkt/s", "recv pkt/s");
+    for mode in modes {
+        let send_rate = measure_send(mode, packet_count).await;
+        // Приём через GSO не отличается от пакетного
+        let receive_rate = match mode {
+            UdpMode::Gso => None,
+            _ => Some(measure_receive(mode).await),
+        };
+        let receive_rate = receive_rate.map_or("—".to_string(), |rate| format!("{:.0}", rate));
+        println!("{:>10}  {:>12.0}  {:>12}", format!("{:?}", mode), send_rate, receive_rate);
+    }
+}
+
+/// Пачка случайных пакетов для `target`.
+fn benchmark_packets(target: SocketAddr) -> Vec<(Vec<u8>, SocketAddr)> {
+    let mut rng = rand::thread_rng();
+    (0..BATCH_SIZE)
+        .map(|_| {
+            let mut packet = vec![0u8; protocol::DEFAULT_PACKET_SIZE];
+            rng.fill_bytes(&mut packet);
+            (packet, target)
+        })
+        .collect()
+}
+
+fn benchmark_socket(socket: Arc<UdpSocket>, mode: UdpMode) -> BatchSocket {
+    match mode {
+        UdpMode::Gso => BatchSocket::new(socket),
+        _ => BatchSocket::without_gso(socket),
+    }
+}
+
+/// Отправка в сокет, который никто не читает: меряем только стоимость отправки.
+async fn measure_send(mode: UdpMode, packet_count: usize) -> f64 {
+    let sink = UdpSocket::bind("127.0.0.1:0").await.expect("Failed to bind benchmark socket");
+    let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.expect("Failed to bind benchmark socket"));
+    let packets = benchmark_packets(sink.local_addr().expect("Bound socket has an address"));
+    let batch_socket = benchmark_socket(Arc::clone(&socket), mode);
+
+    let started = Instant::now();
+    match mode {
+        UdpMode::PerPacket => {
+            for i in 0..packet_count {
+                let (packet, target) = &packets[i % packets.len()];
+                socket.send_to(packet, *target).await.ok();
+            }
+        }
+        UdpMode::Batched | UdpMode::Gso => {
+            for _ in 0..packet_count.div_ceil(BATCH_SIZE) {
+                batch_socket.send_batch(&packets).await;
+            }
+        }
+    }
+    packet_count as f64 / started.elapsed().as_secs_f64()
+}
+
+/// Приём, пока отдельная задача засыпает сокет пакетами так быстро, как может.
+async fn measure_receive(mode: UdpMode) -> f64 {
+    let receiver = Arc::new(UdpSocket::bind("127.0.0.1:0").await.expect("Failed to bind benchmark socket"));
+    let packets = benchmark_packets(receiver.local_addr().expect("Bound socket has an address"));
+    let blaster = BatchSocket::new(Arc::new(UdpSocket::bind("127.0.0.1:0").await.expect("Failed to bind benchmark socket")));
+    let stop = Arc::new(AtomicBool::new(false));
+    let blaster_stop = Arc::clone(&stop);
+    let blaster_task = tokio::spawn(async move {
+        while !blaster_stop.load(Ordering::Relaxed) {
+            blaster.send_batch(&packets).await;
+            tokio::task::yield_now().await;
+        }
+    });
+
+    let batch_socket = benchmark_socket(Arc::clone(&receiver), mode);
+    let pool = BufferPool::new(2048, BATCH_SIZE * 2);
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut buf = vec![0u8; 2048];
+    let mut received = 0usize;
+    let started = Instant::now();
+    while started.elapsed() < RECEIVE_WINDOW {
+        let receive = async {
+            match mode {
+                UdpMode::PerPacket => {
+                    // Так принимал пакеты старый приёмник: копия на каждую датаграмму
+                    let (len, _) = receiver.recv_from(&mut buf).await?;
+                    drop(buf[..len].to_vec());
+                    Ok::<usize, std::io::Error>(1)
+                }
+                UdpMode::Batched | UdpMode::Gso => {
+                    batch_socket.recv_batch(&pool, &mut batch).await?;
+                    let count = batch.len();
+                    batch.drain(..).for_each(|(packet, _)| pool.put(packet));
+                    Ok(count)
+                }
+            }
+        };
+        match tokio::time::timeout(RECEIVE_WINDOW, receive).await {
+            Ok(Ok(count)) => received += count,
+            _ => break,
+        }
+    }
+    let elapsed = started.elapsed();
+    stop.store(true, Ordering::Relaxed);
+    blaster_task.await.ok();
+    received as f64 / elapsed.as_secs_f64()
+}
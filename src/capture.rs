@@ -0,0 +1,260 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// pcap с микросекундами; порядок байт заголовков — порядок записавшей машины.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_SNAPLEN: u32 = 65_535;
+/// Пакеты без канального уровня, сразу с IP-заголовка: так пишем мы.
+const LINKTYPE_RAW: u32 = 101;
+/// Захват `tcpdump` с обычного интерфейса и с `any`: их тоже читаем.
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const IPPROTO_UDP: u8 = 17;
+/// Сколько датаграмм ждут записи на диск; если диск не успевает, лишние пропускаем.
+const CAPTURE_QUEUE: usize = 4096;
+
+/// Одна захваченная датаграмма.
+pub struct CapturedDatagram {
+    /// Микросекунды с эпохи Unix.
+    pub timestamp_us: u64,
+    pub sender: SocketAddr,
+    pub receiver: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+/// Запись входящих датаграмм в pcap. Сам захват не ждёт диска: датаграммы
+/// копируются в очередь, а пишет их отдельный поток.
+#[derive(Clone)]
+pub struct Capture {
+    queue: SyncSender<CapturedDatagram>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Capture {
+    pub fn start(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_global_header(&mut writer)?;
+        let (queue, records) = mpsc::sync_channel::<CapturedDatagram>(CAPTURE_QUEUE);
+        let path_for_thread = path.to_path_buf();
+        std::thread::Builder::new().name("capture".to_string()).spawn(move || {
+            // Сбрасываем буфер, когда очередь опустела, чтобы файл можно было читать на ходу
+            while let Ok(datagram) = records.recv() {
+                let mut result = write_record(&mut writer, &datagram);
+                while let (Ok(()), Ok(datagram)) = (&result, records.try_recv()) {
+                    result = write_record(&mut writer, &datagram);
+                }
+                if let Err(e) = result.and_then(|_| writer.flush()) {
+                    warn!("Stopping packet capture to {:?}: {}", path_for_thread, e);
+                    return;
+                }
+            }
+        })?;
+        info!("Capturing incoming datagrams to {:?}", path);
+        Ok(Self { queue, dropped: Arc::new(AtomicU64::new(0)) })
+    }
+
+    pub fn record(&self, payload: &[u8], sender: SocketAddr, receiver: SocketAddr) {
+        let timestamp_us = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as u64);
+        let datagram = CapturedDatagram { timestamp_us, sender, receiver, payload: payload.to_vec() };
+        if let Err(TrySendError::Full(_)) = self.queue.try_send(datagram) {
+            if self.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                warn!("Packet capture cannot keep up, some datagrams will be missing from it");
+            }
+        }
+    }
+}
+
+fn write_global_header(out: &mut impl Write) -> io::Result<()> {
+    out.write_all(&PCAP_MAGIC.to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&4u16.to_le_bytes())?;
+    out.write_all(&0i32.to_le_bytes())?;
+    out.write_all(&0u32.to_le_bytes())?;
+    out.write_all(&PCAP_SNAPLEN.to_le_bytes())?;
+    out.write_all(&LINKTYPE_RAW.to_le_bytes())
+}
+
+fn write_record(out: &mut impl Write, datagram: &CapturedDatagram) -> io::Result<()> {
+    let packet = ip_udp_packet(datagram.sender, datagram.receiver, &datagram.payload);
+    out.write_all(&((datagram.timestamp_us / 1_000_000) as u32).to_le_bytes())?;
+    out.write_all(&((datagram.timestamp_us % 1_000_000) as u32).to_le_bytes())?;
+    out.write_all(&(packet.len() as u32).to_le_bytes())?;
+    out.write_all(&(packet.len() as u32).to_le_bytes())?;
+    out.write_all(&packet)
+}
+
+/// IP- и UDP-заголовки вокруг датаграммы, чтобы захват открывался в Wireshark.
+/// Адрес получателя для сокета на `0.0.0.0`/`[::]` так и остаётся неуказанным.
+fn ip_udp_packet(sender: SocketAddr, receiver: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = (8 + payload.len()) as u16;
+    let (source, destination) = match (sender.ip(), receiver.ip()) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => (IpAddr::V4(source), IpAddr::V4(destination)),
+        (IpAddr::V4(source), IpAddr::V6(_)) => (IpAddr::V4(source), IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        (IpAddr::V6(source), IpAddr::V6(destination)) => (IpAddr::V6(source), IpAddr::V6(destination)),
+        (IpAddr::V6(source), IpAddr::V4(_)) => (IpAddr::V6(source), IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+    };
+
+    let mut udp = Vec::with_capacity(udp_len as usize);
+    udp.extend_from_slice(&sender.port().to_be_bytes());
+    udp.extend_from_slice(&receiver.port().to_be_bytes());
+    udp.extend_from_slice(&udp_len.to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(payload);
+
+    let mut packet = Vec::with_capacity(40 + udp.len());
+    let mut pseudo_header = Vec::with_capacity(40);
+    match (source, destination) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            let mut header = [0u8; 20];
+            header[0] = 0x45;
+            header[2..4].copy_from_slice(&(20 + udp_len).to_be_bytes());
+            // Don't Fragment: мы и отправляем с ним
+            header[6] = 0x40;
+            header[8] = 64;
+            header[9] = IPPROTO_UDP;
+            header[12..16].copy_from_slice(&source.octets());
+            header[16..20].copy_from_slice(&destination.octets());
+            let checksum = internet_checksum(&[&header]);
+            header[10..12].copy_from_slice(&checksum.to_be_bytes());
+            packet.extend_from_slice(&header);
+            pseudo_header.extend_from_slice(&source.octets());
+            pseudo_header.extend_from_slice(&destination.octets());
+            pseudo_header.extend_from_slice(&[0, IPPROTO_UDP]);
+            pseudo_header.extend_from_slice(&udp_len.to_be_bytes());
+        }
+        (IpAddr::V6(source), IpAddr::V6(destination)) => {
+            packet.extend_from_slice(&0x6000_0000u32.to_be_bytes());
+            packet.extend_from_slice(&udp_len.to_be_bytes());
+            packet.extend_from_slice(&[IPPROTO_UDP, 64]);
+            packet.extend_from_slice(&source.octets());
+            packet.extend_from_slice(&destination.octets());
+            pseudo_header.extend_from_slice(&source.octets());
+            pseudo_header.extend_from_slice(&destination.octets());
+            pseudo_header.extend_from_slice(&(udp_len as u32).to_be_bytes());
+            pseudo_header.extend_from_slice(&[0, 0, 0, IPPROTO_UDP]);
+        }
+        _ => unreachable!("address families are matched above"),
+    }
+    // Нулевая сумма в UDP означает «не считали», поэтому ноль передаётся как 0xffff
+    let checksum = match internet_checksum(&[&pseudo_header, &udp]) {
+        0 => 0xffff,
+        checksum => checksum,
+    };
+    udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(&udp);
+    packet
+}
+
+/// Контрольная сумма RFC 1071 по последовательности кусков.
+fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for part in parts {
+        let mut words = part.chunks_exact(2);
+        for word in &mut words {
+            sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+        }
+        if let [last] = words.remainder() {
+            sum += (*last as u32) << 8;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Читает UDP-датаграммы из pcap: нашего захвата или `tcpdump` (Ethernet, Linux cooked).
+/// Остальные пакеты, в том числе фрагменты IP, пропускаются.
+pub fn read_pcap(path: &Path) -> io::Result<Vec<CapturedDatagram>> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    if data.len() < 24 {
+        return Err(invalid("file is too short for a pcap header"));
+    }
+    let magic = u32::from_le_bytes(data[0..4].try_into().expect("4 bytes"));
+    let (little_endian, nanoseconds) = match magic {
+        0xa1b2_c3d4 => (true, false),
+        0xd4c3_b2a1 => (false, false),
+        0xa1b2_3c4d => (true, true),
+        0x4d3c_b2a1 => (false, true),
+        _ => return Err(invalid("not a pcap file (pcapng is not supported)")),
+    };
+    let read_u32 = |bytes: &[u8]| {
+        let bytes: [u8; 4] = bytes.try_into().expect("4 bytes");
+        if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) }
+    };
+    let linktype = read_u32(&data[20..24]);
+    let link_header = match linktype {
+        LINKTYPE_RAW => 0,
+        LINKTYPE_ETHERNET => 14,
+        LINKTYPE_LINUX_SLL => 16,
+        _ => return Err(invalid(&format!("unsupported link type {}", linktype))),
+    };
+
+    let mut datagrams = Vec::new();
+    let mut offset = 24;
+    while offset + 16 <= data.len() {
+        let seconds = read_u32(&data[offset..offset + 4]) as u64;
+        let fraction = read_u32(&data[offset + 4..offset + 8]) as u64;
+        let captured_len = read_u32(&data[offset + 8..offset + 12]) as usize;
+        let start = offset + 16;
+        let Some(frame) = data.get(start..start + captured_len) else {
+            warn!("Capture {:?} ends with a truncated record", path);
+            break;
+        };
+        offset = start + captured_len;
+        let timestamp_us = seconds * 1_000_000 + if nanoseconds { fraction / 1000 } else { fraction };
+        if link_header > 0 {
+            // Тип протокола лежит в последних двух байтах канального заголовка
+            let ethertype = frame.get(link_header - 2..link_header).map(|b| u16::from_be_bytes([b[0], b[1]]));
+            if !matches!(ethertype, Some(ETHERTYPE_IPV4 | ETHERTYPE_IPV6)) {
+                continue;
+            }
+        }
+        if let Some((sender, receiver, payload)) = parse_ip_udp(&frame[link_header.min(frame.len())..]) {
+            datagrams.push(CapturedDatagram { timestamp_us, sender, receiver, payload: payload.to_vec() });
+        }
+    }
+    Ok(datagrams)
+}
+
+fn parse_ip_udp(packet: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let (source, destination, udp) = match packet.first()? >> 4 {
+        4 => {
+            let header_len = ((packet[0] & 0x0f) as usize) * 4;
+            let fragment = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]);
+            // Фрагмент или продолжение фрагментированного пакета
+            if packet.get(9)? != &IPPROTO_UDP || fragment & 0x3fff != 0 {
+                return None;
+            }
+            let source: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let destination: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            (IpAddr::from(source), IpAddr::from(destination), packet.get(header_len..)?)
+        }
+        6 => {
+            // Заголовки расширений не разбираем: наши датаграммы их не используют
+            if packet.get(6)? != &IPPROTO_UDP {
+                return None;
+            }
+            let source: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let destination: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            (IpAddr::from(source), IpAddr::from(destination), packet.get(40..)?)
+        }
+        _ => return None,
+    };
+    let source_port = u16::from_be_bytes([*udp.first()?, *udp.get(1)?]);
+    let destination_port = u16::from_be_bytes([*udp.get(2)?, *udp.get(3)?]);
+    let udp_len = u16::from_be_bytes([*udp.get(4)?, *udp.get(5)?]) as usize;
+    let payload = udp.get(8..udp_len.max(8))?;
+    Some((SocketAddr::new(source, source_port), SocketAddr::new(destination, destination_port), payload))
+}
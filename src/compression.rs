@@ -0,0 +1,112 @@
+use crate::files;
+use crate::state::{Compression, MessageContent};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::io::{self, Read, Write};
+
+/// Короткие сообщения не сжимаем: выигрыш меньше заголовка.
+const MIN_COMPRESS_LEN: usize = 256;
+/// Сколько байт файла пробуем сжать, чтобы понять, сжимается ли он вообще.
+const SAMPLE_LEN: usize = 64 * 1024;
+/// Если пробный фрагмент сжался хуже этого, файл считаем уже сжатым.
+const INCOMPRESSIBLE_RATIO: f64 = 0.95;
+const ZSTD_LEVEL: i32 = 3;
+/// Форматы, которые уже сжаты: для них не тратим время даже на пробу.
+const COMPRESSED_EXTENSIONS: [&str; 24] = [
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "avif", "mp3", "aac", "ogg", "opus", "flac", "mp4",
+    "mkv", "webm", "mov", "avi", "zip", "gz", "bz2", "xz", "zst", "7z", "rar",
+];
+
+/// Сжимает сериализованное сообщение, если это включено для ключа и имеет смысл.
+/// Возвращает данные для разбиения на чанки и фактически применённое сжатие.
+///
+/// Сжатые данные упаковываются как `[u32 BE длина][сжатые байты][нули]` и добиваются
+/// нулями по схеме Padmé: размер сжатого сообщения зависит от содержимого, и без
+/// округления число чанков выдавало бы больше, чем исходный размер.
+pub fn compress(content: &MessageContent, data: Vec<u8>, mode: Compression) -> (Vec<u8>, Compression) {
+    if mode.is_off() || data.len() < MIN_COMPRESS_LEN || is_precompressed(content) {
+        return (data, Compression::Off);
+    }
+    let compressed = match encode(&data, mode) {
+        Ok(compressed) => compressed,
+        Err(_) => return (data, Compression::Off),
+    };
+    let envelope_len = padme(4 + compressed.len());
+    if envelope_len >= data.len() {
+        return (data, Compression::Off);
+    }
+    let mut envelope = Vec::with_capacity(envelope_len);
+    envelope.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+    envelope.extend_from_slice(&compressed);
+    // Нули, а не случайные байты: одинаковое содержимое даёт одинаковый transfer_id для докачки
+    envelope.resize(envelope_len, 0);
+    (envelope, mode)
+}
+
+/// Распаковывает собранное сообщение. `limit` ограничивает размер результата,
+/// чтобы небольшой пакет не развернулся в гигабайты.
+pub fn decompress(envelope: &[u8], mode: Compression, limit: u64) -> io::Result<Vec<u8>> {
+    if mode.is_off() {
+        return Ok(envelope.to_vec());
+    }
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "truncated compressed envelope");
+    let len_bytes: [u8; 4] = envelope.get(..4).ok_or_else(invalid)?.try_into().map_err(|_| invalid())?;
+    let compressed_len = u32::from_be_bytes(len_bytes) as usize;
+    let compressed = envelope.get(4..4 + compressed_len).ok_or_else(invalid)?;
+
+    let mut decoded = Vec::new();
+    match mode {
+        Compression::Deflate => DeflateDecoder::new(compressed).take(limit + 1).read_to_end(&mut decoded)?,
+        Compression::Zstd => zstd::Decoder::new(compressed)?.take(limit + 1).read_to_end(&mut decoded)?,
+        Compression::Off => unreachable!(),
+    };
+    if decoded.len() as u64 > limit {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "decompressed message exceeds the size limit"));
+    }
+    Ok(decoded)
+}
+
+fn encode(data: &[u8], mode: Compression) -> io::Result<Vec<u8>> {
+    match mode {
+        Compression::Deflate => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        Compression::Zstd => zstd::encode_all(data, ZSTD_LEVEL),
+        Compression::Off => Ok(data.to_vec()),
+    }
+}
+
+/// Уже сжатые медиа и архивы: по расширению или по пробному сжатию начала файла.
+/// Проверяем исходные байты файла, а не JSON с Base64, который сжимается всегда.
+fn is_precompressed(content: &MessageContent) -> bool {
+    let MessageContent::File(file) = content else {
+        return false;
+    };
+    if files::extension(&file.filename).is_some_and(|e| COMPRESSED_EXTENSIONS.contains(&e.as_str())) {
+        return true;
+    }
+    let sample = &file.data[..file.data.len().min(SAMPLE_LEN)];
+    if sample.len() < MIN_COMPRESS_LEN {
+        return false;
+    }
+    let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+    match encoder.write_all(sample).and_then(|_| encoder.finish()) {
+        Ok(compressed) => compressed.len() as f64 / sample.len() as f64 > INCOMPRESSIBLE_RATIO,
+        Err(_) => true,
+    }
+}
+
+/// Padmé (Nikitin et al., 2019): округление длины вверх, при котором лишние байты
+/// не превышают ~12%, а наблюдатель узнаёт о длине только O(log log L) бит.
+fn padme(len: usize) -> usize {
+    if len < 2 {
+        return len;
+    }
+    let exponent = usize::BITS - 1 - len.leading_zeros();
+    let exponent_bits = u32::BITS - exponent.leading_zeros();
+    let low_bits = exponent - exponent_bits;
+    let mask = (1usize << low_bits) - 1;
+    (len + mask) & !mask
+}
@@ -0,0 +1,157 @@
+use crate::protocol::MAX_PACKET_SIZE;
+use crate::state::FilePolicy;
+use std::env;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tracing::warn;
+
+const MAX_DECRYPT_WORKERS: usize = 64;
+/// Самая длинная возможная UDP-датаграмма.
+const MAX_DATAGRAM_SIZE: usize = 65_535;
+
+/// Настройки узла, читаются из переменных окружения `ASEMIC_*` при запуске.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Нижняя граница скорости отправки чанков одному узлу, пакетов в секунду.
+    pub min_rate_pps: f64,
+    /// Верхняя граница скорости отправки чанков одному узлу, пакетов в секунду.
+    pub max_rate_pps: f64,
+    /// Стартовая скорость для узла, о котором ещё ничего не известно.
+    pub initial_rate_pps: f64,
+    /// Входящие файлы больше этого размера отбрасываются сразу после сборки.
+    pub max_incoming_file_bytes: u64,
+    /// Сколько байт всего может занимать каталог загрузок.
+    pub downloads_quota_bytes: u64,
+    /// Сколько байт всего могут занимать файлы в карантине.
+    pub quarantine_quota_bytes: u64,
+    /// Сколько файлов от одного узла может ждать решения в карантине.
+    pub max_pending_files_per_sender: usize,
+    /// Адреса, файлы от которых принимаются без подтверждения.
+    pub auto_accept_from: Vec<IpAddr>,
+    pub auto_accept_max_bytes: u64,
+    pub allowed_extensions: Vec<String>,
+    pub denied_extensions: Vec<String>,
+    /// Сколько потоков расшифровывают входящие пакеты.
+    pub decrypt_workers: usize,
+    /// Расшифровка в задачах рантайма вместо отдельных потоков. Нужна симулятору сети:
+    /// остановленные часы tokio не идут, пока жив хоть один блокирующий поток.
+    pub decrypt_in_tasks: bool,
+    /// Буфер под одну входящую датаграмму; всё, что длиннее, обрезается и отбрасывается.
+    pub receive_buffer_bytes: usize,
+    /// `SO_RCVBUF` сокета; 0 — оставить значение системы.
+    pub socket_recv_buffer_bytes: usize,
+    /// `SO_SNDBUF` сокета; 0 — оставить значение системы.
+    pub socket_send_buffer_bytes: usize,
+    /// Сколько дней хранить записи журнала аудита; 0 — хранить всё.
+    pub audit_retention_days: u32,
+    /// Если задан, входящие датаграммы пишутся в этот pcap (см. `asemic_new replay`).
+    pub capture_path: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            min_rate_pps: 20.0,
+            max_rate_pps: 20_000.0,
+            initial_rate_pps: 100.0,
+            max_incoming_file_bytes: 256 * 1024 * 1024,
+            downloads_quota_bytes: 2 * 1024 * 1024 * 1024,
+            quarantine_quota_bytes: 1024 * 1024 * 1024,
+            max_pending_files_per_sender: 16,
+            auto_accept_from: Vec::new(),
+            auto_accept_max_bytes: 16 * 1024 * 1024,
+            allowed_extensions: Vec::new(),
+            denied_extensions: ["exe", "bat", "cmd", "com", "scr", "msi", "ps1", "vbs", "js", "jar", "sh"]
+                .iter()
+                .map(|e| e.to_string())
+                .collect(),
+            decrypt_workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            decrypt_in_tasks: false,
+            receive_buffer_bytes: 2048,
+            socket_recv_buffer_bytes: 4 * 1024 * 1024,
+            socket_send_buffer_bytes: 1024 * 1024,
+            audit_retention_days: 90,
+            capture_path: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let defaults = Config::default();
+        let mut config = Config {
+            min_rate_pps: env_or("ASEMIC_MIN_RATE_PPS", defaults.min_rate_pps),
+            max_rate_pps: env_or("ASEMIC_MAX_RATE_PPS", defaults.max_rate_pps),
+            initial_rate_pps: env_or("ASEMIC_INITIAL_RATE_PPS", defaults.initial_rate_pps),
+            max_incoming_file_bytes: env_or("ASEMIC_MAX_FILE_BYTES", defaults.max_incoming_file_bytes),
+            downloads_quota_bytes: env_or("ASEMIC_DOWNLOADS_QUOTA_BYTES", defaults.downloads_quota_bytes),
+            quarantine_quota_bytes: env_or("ASEMIC_QUARANTINE_QUOTA_BYTES", defaults.quarantine_quota_bytes),
+            max_pending_files_per_sender: env_or("ASEMIC_MAX_PENDING_FILES_PER_SENDER", defaults.max_pending_files_per_sender),
+            auto_accept_from: env_list("ASEMIC_AUTO_ACCEPT_FROM").unwrap_or(defaults.auto_accept_from),
+            auto_accept_max_bytes: env_or("ASEMIC_AUTO_ACCEPT_MAX_BYTES", defaults.auto_accept_max_bytes),
+            allowed_extensions: env_list("ASEMIC_ALLOWED_EXTENSIONS").unwrap_or(defaults.allowed_extensions),
+            denied_extensions: env_list("ASEMIC_DENIED_EXTENSIONS").unwrap_or(defaults.denied_extensions),
+            decrypt_workers: env_or("ASEMIC_DECRYPT_WORKERS", defaults.decrypt_workers),
+            decrypt_in_tasks: defaults.decrypt_in_tasks,
+            receive_buffer_bytes: env_or("ASEMIC_RECV_BUFFER_BYTES", defaults.receive_buffer_bytes),
+            socket_recv_buffer_bytes: env_or("ASEMIC_SO_RCVBUF", defaults.socket_recv_buffer_bytes),
+            socket_send_buffer_bytes: env_or("ASEMIC_SO_SNDBUF", defaults.socket_send_buffer_bytes),
+            audit_retention_days: env_or("ASEMIC_AUDIT_RETENTION_DAYS", defaults.audit_retention_days),
+            capture_path: env::var_os("ASEMIC_CAPTURE_FILE").map(PathBuf::from),
+        };
+        if config.min_rate_pps <= 0.0 || config.min_rate_pps > config.max_rate_pps {
+            warn!("Invalid pacing limits {}..{} pps, using defaults", config.min_rate_pps, config.max_rate_pps);
+            config.min_rate_pps = defaults.min_rate_pps;
+            config.max_rate_pps = defaults.max_rate_pps;
+        }
+        config.initial_rate_pps = config.initial_rate_pps.clamp(config.min_rate_pps, config.max_rate_pps);
+        config.decrypt_workers = config.decrypt_workers.clamp(1, MAX_DECRYPT_WORKERS);
+        if !(MAX_PACKET_SIZE..=MAX_DATAGRAM_SIZE).contains(&config.receive_buffer_bytes) {
+            warn!(
+                "Receive buffer of {} bytes is outside {}..={}, clamping",
+                config.receive_buffer_bytes, MAX_PACKET_SIZE, MAX_DATAGRAM_SIZE
+            );
+            config.receive_buffer_bytes = config.receive_buffer_bytes.clamp(MAX_PACKET_SIZE, MAX_DATAGRAM_SIZE);
+        }
+        config
+    }
+
+    /// Начальные правила приёма файлов.
+    pub fn file_policy(&self) -> FilePolicy {
+        FilePolicy {
+            auto_accept_from: self.auto_accept_from.clone(),
+            auto_accept_max_bytes: self.auto_accept_max_bytes,
+            max_file_bytes: self.max_incoming_file_bytes,
+            allowed_extensions: self.allowed_extensions.clone(),
+            denied_extensions: self.denied_extensions.clone(),
+        }
+    }
+}
+
+/// Список через запятую; `None`, если переменная не задана.
+fn env_list<T: FromStr>(name: &str) -> Option<Vec<T>> {
+    let value = env::var(name).ok()?;
+    Some(
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .filter_map(|item| {
+                item.parse()
+                    .map_err(|_| warn!("Ignoring invalid item '{}' in {}", item, name))
+                    .ok()
+            })
+            .collect(),
+    )
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            warn!("Ignoring invalid value '{}' for {}", value, name);
+            default
+        }),
+        Err(_) => default,
+    }
+}
@@ -0,0 +1,133 @@
+use crate::state::{History, MessageContent, Reaction, WsNotification};
+use std::net::SocketAddr;
+use tracing::debug;
+use uuid::Uuid;
+
+/// Максимальная длина реакции в байтах: одна эмодзи с модификаторами.
+const MAX_REACTION_BYTES: usize = 32;
+
+/// Кто совершает действие над сообщением.
+#[derive(Clone, Copy, Debug)]
+pub enum Author {
+    Local,
+    Peer(SocketAddr),
+}
+
+/// Изменяемые поля записи истории, общие для входящих и исходящих сообщений.
+struct RecordMut<'a> {
+    id: Uuid,
+    content: &'a mut MessageContent,
+    edited: &'a mut bool,
+    deleted: &'a mut bool,
+    reactions: &'a mut Vec<Reaction>,
+}
+
+macro_rules! record_mut {
+    ($message:expr) => {
+        RecordMut {
+            id: $message.id,
+            content: &mut $message.content,
+            edited: &mut $message.edited,
+            deleted: &mut $message.deleted,
+            reactions: &mut $message.reactions,
+        }
+    };
+}
+
+/// Сообщение `msg_id`, написанное `author`. Узел сравниваем по IP: порт мог смениться.
+fn find_authored(history: &mut History, author: Author, msg_id: u32) -> Option<RecordMut<'_>> {
+    match author {
+        Author::Local => history.outgoing.iter_mut().rev().find(|m| m.msg_id == msg_id).map(|m| record_mut!(m)),
+        Author::Peer(peer) => history
+            .messages
+            .iter_mut()
+            .rev()
+            .find(|m| m.msg_id == msg_id && m.sender.ip() == peer.ip())
+            .map(|m| record_mut!(m)),
+    }
+}
+
+/// Сообщение `msg_id` из переписки с `author`, кто бы его ни написал.
+fn find_in_conversation(history: &mut History, author: Author, msg_id: u32) -> Option<RecordMut<'_>> {
+    let authored_exists = match author {
+        Author::Local => history.outgoing.iter().any(|m| m.msg_id == msg_id),
+        Author::Peer(peer) => history.messages.iter().any(|m| m.msg_id == msg_id && m.sender.ip() == peer.ip()),
+    };
+    if authored_exists {
+        return find_authored(history, author, msg_id);
+    }
+    match author {
+        Author::Local => history.messages.iter_mut().rev().find(|m| m.msg_id == msg_id).map(|m| record_mut!(m)),
+        Author::Peer(peer) => history
+            .outgoing
+            .iter_mut()
+            .rev()
+            .find(|m| m.msg_id == msg_id && m.target.ip() == peer.ip())
+            .map(|m| record_mut!(m)),
+    }
+}
+
+/// Применяет правку, удаление, реакцию или индикатор набора к истории.
+/// Возвращает событие для UI, если что-то изменилось.
+pub fn apply_action(history: &mut History, author: Author, action: &MessageContent) -> Option<WsNotification> {
+    let update = match action {
+        MessageContent::Edit { target, text } => {
+            // Править и удалять можно только свои сообщения
+            let record = find_authored(history, author, *target)?;
+            if *record.deleted {
+                return None;
+            }
+            match record.content {
+                MessageContent::Text(old) => *old = text.clone(),
+                MessageContent::Reply { text: old, .. } => *old = text.clone(),
+                _ => {
+                    debug!("Ignoring edit of non-text message {} by {:?}", target, author);
+                    return None;
+                }
+            }
+            *record.edited = true;
+            WsNotification::MessageEdited { id: record.id, content: record.content.clone() }
+        }
+        MessageContent::Delete { target } => {
+            let record = find_authored(history, author, *target)?;
+            if *record.deleted {
+                return None;
+            }
+            *record.content = MessageContent::Text(String::new());
+            *record.deleted = true;
+            record.reactions.clear();
+            WsNotification::MessageDeleted { id: record.id }
+        }
+        MessageContent::Reaction { target, emoji, remove } => {
+            if emoji.is_empty() || emoji.len() > MAX_REACTION_BYTES {
+                return None;
+            }
+            let record = find_in_conversation(history, author, *target)?;
+            if *record.deleted {
+                return None;
+            }
+            let reaction = Reaction {
+                emoji: emoji.clone(),
+                from: match author {
+                    Author::Local => None,
+                    Author::Peer(peer) => Some(peer),
+                },
+            };
+            let present = record.reactions.contains(&reaction);
+            match (remove, present) {
+                (false, false) => record.reactions.push(reaction),
+                (true, true) => record.reactions.retain(|r| r != &reaction),
+                _ => return None,
+            }
+            WsNotification::ReactionsUpdated { id: record.id, reactions: record.reactions.clone() }
+        }
+        MessageContent::Typing { active } => match author {
+            Author::Peer(peer) => WsNotification::Typing { peer, active: *active },
+            Author::Local => return None,
+        },
+        MessageContent::Text(_) | MessageContent::File(_) | MessageContent::Reply { .. } | MessageContent::KeyRotation { .. } => {
+            return None
+        }
+    };
+    Some(update)
+}
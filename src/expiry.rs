@@ -0,0 +1,107 @@
+use crate::library;
+use crate::state::{SharedState, WsNotification};
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+/// TTL больше месяца считаем ошибкой отправителя и урезаем.
+const MAX_TTL_SECS: u32 = 30 * 24 * 60 * 60;
+
+/// Момент, когда сообщение с таким TTL нужно стереть. 0 — не стирать.
+pub fn expires_at(ttl_secs: Option<u32>) -> Option<DateTime<Utc>> {
+    let ttl_secs = ttl_secs.filter(|&ttl| ttl > 0)?.min(MAX_TTL_SECS);
+    Some(Utc::now() + chrono::Duration::seconds(ttl_secs as i64))
+}
+
+fn is_expired(expires_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+    expires_at.is_some_and(|at| at <= now)
+}
+
+/// Раз в секунду стирает сообщения с истёкшим TTL вместе с их файлами.
+pub async fn run_sweeper(state: SharedState, ws_tx: broadcast::Sender<WsNotification>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        sweep(&state, &ws_tx).await;
+    }
+}
+
+async fn sweep(state: &SharedState, ws_tx: &broadcast::Sender<WsNotification>) {
+    let now = Utc::now();
+    let mut doomed_paths: Vec<PathBuf> = Vec::new();
+    {
+        let mut state_guard = state.lock().await;
+
+        let expired: Vec<_> = {
+            let mut history = state.history();
+            let expired = history
+                .messages
+                .iter()
+                .filter(|m| is_expired(m.expires_at, now))
+                .map(|m| m.id)
+                .chain(history.outgoing.iter().filter(|m| is_expired(m.expires_at, now)).map(|m| m.id))
+                .collect();
+            history.messages.retain(|m| !is_expired(m.expires_at, now));
+            history.outgoing.retain(|m| !is_expired(m.expires_at, now));
+            expired
+        };
+
+        // Файлы сверяем по своему сроку, а не по сообщению: карантин и приём идут
+        // в фоновых задачах и могут закончиться уже после того, как сообщение стёрто
+        let pending: Vec<_> = state_guard
+            .pending_files
+            .iter()
+            .filter(|(_, f)| is_expired(f.info.expires_at, now))
+            .map(|(id, _)| *id)
+            .collect();
+        let mut expired_files = Vec::new();
+        for id in pending {
+            if let Some(file) = state_guard.pending_files.remove(&id) {
+                state_guard.quarantine_usage.release(file.info.sender.ip(), file.info.size);
+                doomed_paths.push(file.path);
+                expired_files.push(id);
+            }
+        }
+
+        let stored: Vec<_> = state_guard
+            .received_files
+            .iter()
+            .filter(|(_, f)| is_expired(f.expires_at, now))
+            .map(|(id, _)| *id)
+            .collect();
+        if !stored.is_empty() {
+            for id in &stored {
+                if let Some(file) = state_guard.received_files.remove(id) {
+                    state_guard.downloads_used = state_guard.downloads_used.saturating_sub(file.size);
+                    doomed_paths.push(state_guard.downloads_path.join(&file.filename));
+                    expired_files.push(*id);
+                }
+            }
+            if let Err(e) = library::save(&state_guard).await {
+                warn!("Failed to update library index: {}", e);
+            }
+        }
+
+        if expired.is_empty() && expired_files.is_empty() {
+            return;
+        }
+        info!("Expired {} messages and {} files", expired.len(), expired_files.len());
+        for id in expired {
+            ws_tx.send(WsNotification::MessageExpired { id }).ok();
+        }
+        for id in expired_files {
+            ws_tx.send(WsNotification::FileDeleted { id }).ok();
+        }
+    }
+
+    for path in doomed_paths {
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove expired file {:?}: {}", path, e);
+            }
+        }
+    }
+}
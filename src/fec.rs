@@ -0,0 +1,144 @@
+use crate::protocol::FecParams;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Сколько исходных чанков объединяется в один блок Reed-Solomon.
+pub const FEC_BLOCK_SIZE: u32 = 32;
+/// Больше 100% избыточности смысла не имеет: проще отправить сообщение дважды.
+pub const MAX_REDUNDANCY: f32 = 1.0;
+
+impl FecParams {
+    /// Параметры для сообщения из `total_chunks` чанков по `chunk_size` байт.
+    /// `redundancy` — доля ремонтных чанков (0.25 = +25% трафика); 0 отключает FEC.
+    pub fn for_message(data_len: usize, chunk_size: usize, redundancy: f32) -> Option<Self> {
+        let redundancy = redundancy.clamp(0.0, MAX_REDUNDANCY);
+        let parity = (FEC_BLOCK_SIZE as f32 * redundancy).ceil() as u32;
+        if parity == 0 {
+            return None;
+        }
+        Some(FecParams {
+            block_size: FEC_BLOCK_SIZE,
+            parity,
+            chunk_size: chunk_size as u32,
+            data_len: data_len as u64,
+        })
+    }
+
+    fn block_count(&self, total_chunks: u32) -> u32 {
+        total_chunks.div_ceil(self.block_size)
+    }
+
+    /// Исходные чанки блока `block`.
+    fn data_range(&self, block: u32, total_chunks: u32) -> Range<u32> {
+        let start = block * self.block_size;
+        start..(start + self.block_size).min(total_chunks)
+    }
+
+    /// Число ремонтных чанков блока: у последнего, неполного блока их пропорционально меньше.
+    fn parity_for_block(&self, data_shards: u32) -> u32 {
+        (data_shards * self.parity).div_ceil(self.block_size).max(1)
+    }
+
+    /// Номера ремонтных чанков блока `block`.
+    fn parity_range(&self, block: u32, total_chunks: u32) -> Range<u32> {
+        let start = total_chunks + block * self.parity;
+        let data_shards = self.data_range(block, total_chunks).len() as u32;
+        start..start + self.parity_for_block(data_shards)
+    }
+
+    /// Сколько всего ремонтных чанков у сообщения.
+    pub fn repair_chunks(&self, total_chunks: u32) -> u32 {
+        (0..self.block_count(total_chunks))
+            .map(|block| self.parity_range(block, total_chunks).len() as u32)
+            .sum()
+    }
+
+    fn is_valid(&self, total_chunks: u32) -> bool {
+        self.block_size > 0
+            && self.parity > 0
+            && self.block_size + self.parity <= 256
+            && self.chunk_size > 0
+            && self.data_len.div_ceil(self.chunk_size as u64).max(1) == total_chunks as u64
+    }
+}
+
+/// Исходный чанк `chunk_num`, добитый нулями до размера шарда.
+fn data_shard(data: &[u8], chunk_num: u32, chunk_size: usize) -> Vec<u8> {
+    let start = (chunk_num as usize * chunk_size).min(data.len());
+    let end = (start + chunk_size).min(data.len());
+    let mut shard = data[start..end].to_vec();
+    shard.resize(chunk_size, 0);
+    shard
+}
+
+/// Строит ремонтные чанки. Возвращает их по порядку номеров, начиная с `total_chunks`.
+pub fn encode_repair(data: &[u8], total_chunks: u32, params: &FecParams) -> Vec<Vec<u8>> {
+    let chunk_size = params.chunk_size as usize;
+    let mut repair = Vec::with_capacity(params.repair_chunks(total_chunks) as usize);
+    for block in 0..params.block_count(total_chunks) {
+        let data_range = params.data_range(block, total_chunks);
+        let parity_count = params.parity_range(block, total_chunks).len();
+        let codec = ReedSolomon::new(data_range.len(), parity_count).expect("FEC parameters are validated on creation");
+
+        let mut shards: Vec<Vec<u8>> = data_range.map(|c| data_shard(data, c, chunk_size)).collect();
+        shards.extend((0..parity_count).map(|_| vec![0u8; chunk_size]));
+        codec.encode(&mut shards).expect("all shards have equal length");
+        repair.extend(shards.drain(shards.len() - parity_count..));
+    }
+    repair
+}
+
+/// Данные ремонтного чанка `chunk_num` (номер >= `total_chunks`).
+pub fn repair_chunk(repair: &[Vec<u8>], chunk_num: u32, total_chunks: u32) -> Option<&[u8]> {
+    repair.get((chunk_num.checked_sub(total_chunks)?) as usize).map(Vec::as_slice)
+}
+
+/// Проверяет, хватает ли полученных чанков, и собирает сообщение,
+/// восстанавливая недостающие исходные чанки по ремонтным.
+pub fn try_reconstruct(chunks: &HashMap<u32, Vec<u8>>, total_chunks: u32, params: &FecParams) -> Option<Vec<u8>> {
+    if !params.is_valid(total_chunks) {
+        return None;
+    }
+    let chunk_size = params.chunk_size as usize;
+    let blocks = params.block_count(total_chunks);
+
+    // Сначала дешёвая проверка, чтобы не кодировать на каждом пришедшем чанке
+    for block in 0..blocks {
+        let present = params
+            .data_range(block, total_chunks)
+            .chain(params.parity_range(block, total_chunks))
+            .filter(|c| chunks.contains_key(c))
+            .count();
+        if present < params.data_range(block, total_chunks).len() {
+            return None;
+        }
+    }
+
+    let mut message = Vec::with_capacity(total_chunks as usize * chunk_size);
+    for block in 0..blocks {
+        let data_range = params.data_range(block, total_chunks);
+        let parity_range = params.parity_range(block, total_chunks);
+        let data_shards = data_range.len();
+
+        let mut shards: Vec<Option<Vec<u8>>> = data_range
+            .chain(parity_range.clone())
+            .map(|c| {
+                chunks.get(&c).map(|chunk| {
+                    let mut shard = chunk.clone();
+                    shard.resize(chunk_size, 0);
+                    shard
+                })
+            })
+            .collect();
+        if shards[..data_shards].iter().any(Option::is_none) {
+            let codec = ReedSolomon::new(data_shards, parity_range.len()).ok()?;
+            codec.reconstruct_data(&mut shards).ok()?;
+        }
+        for shard in shards.into_iter().take(data_shards) {
+            message.extend_from_slice(&shard?);
+        }
+    }
+    message.truncate(params.data_len as usize);
+    Some(message)
+}
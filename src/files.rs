@@ -0,0 +1,203 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Максимальная длина имени файла в байтах (большинство ФС ограничивают 255).
+const MAX_FILENAME_BYTES: usize = 200;
+/// Имя, если от присланного ничего не осталось.
+const FALLBACK_FILENAME: &str = "file";
+/// Имена устройств Windows, которые нельзя использовать даже с расширением.
+const RESERVED_WINDOWS_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Превращает имя файла от узла в безопасное имя внутри каталога загрузок:
+/// без каталогов, управляющих символов, ведущих точек и зарезервированных имён.
+pub fn sanitize_filename(raw: &str) -> String {
+    // Берём только последний компонент пути, с любыми разделителями
+    let base = raw.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = base
+        .chars()
+        .map(|c| match c {
+            c if c.is_control() => '_',
+            ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.').trim_end_matches(['.', ' ']);
+
+    let mut name = truncate_utf8(cleaned, MAX_FILENAME_BYTES).to_string();
+    let stem = name.split('.').next().unwrap_or_default().to_ascii_uppercase();
+    if RESERVED_WINDOWS_NAMES.contains(&stem.as_str()) {
+        name.insert(0, '_');
+    }
+    if name.is_empty() {
+        name = FALLBACK_FILENAME.to_string();
+    }
+    name
+}
+
+fn truncate_utf8(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+    let mut end = max_bytes;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Кладёт файл `source` в `dir` под именем `filename`, не перезаписывая существующие:
+/// при совпадении добавляет " (1)", " (2)" и т.д. Возвращает итоговый путь.
+/// `filename` должен быть уже очищен через `sanitize_filename`.
+pub async fn move_unique(source: &Path, dir: &Path, filename: &str) -> io::Result<PathBuf> {
+    let (stem, extension) = match filename.rfind('.') {
+        Some(dot) if dot > 0 => (&filename[..dot], &filename[dot..]),
+        _ => (filename, ""),
+    };
+    for attempt in 0u32.. {
+        let candidate = if attempt == 0 {
+            filename.to_string()
+        } else {
+            format!("{} ({}){}", stem, attempt, extension)
+        };
+        let path = dir.join(candidate);
+        // hard_link атомарно проверяет отсутствие файла, поэтому гонок с другим сохранением нет
+        match tokio::fs::hard_link(source, &path).await {
+            Ok(()) => {
+                tokio::fs::remove_file(source).await?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            // Другая файловая система: копируем в новый файл
+            Err(_) => match tokio::fs::OpenOptions::new().write(true).create_new(true).open(&path).await {
+                Ok(mut target) => {
+                    let mut source_file = tokio::fs::File::open(source).await?;
+                    tokio::io::copy(&mut source_file, &mut target).await?;
+                    tokio::fs::remove_file(source).await?;
+                    return Ok(path);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            },
+        }
+    }
+    unreachable!("u32 range of unique names exhausted")
+}
+
+/// SHA-256 содержимого в виде hex-строки.
+pub fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Расширение файла в нижнем регистре, без точки.
+pub fn extension(filename: &str) -> Option<String> {
+    let (stem, extension) = filename.rsplit_once('.')?;
+    (!stem.is_empty()).then(|| extension.to_ascii_lowercase())
+}
+
+/// Суммарный размер файлов в каталоге (без вложенных каталогов).
+pub async fn directory_size(dir: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if metadata.is_file() {
+            total += metadata.len();
+        }
+    }
+    Ok(total)
+}
+
+/// Значение заголовка `Content-Disposition` для скачивания: ASCII-имя для старых клиентов
+/// и полное имя в `filename*` (RFC 6266 / RFC 5987).
+pub fn content_disposition(filename: &str) -> String {
+    let ascii_fallback: String = filename
+        .chars()
+        .map(|c| if (c.is_ascii_graphic() && c != '"' && c != '\\') || c == ' ' { c } else { '_' })
+        .collect();
+    let mut encoded = String::with_capacity(filename.len() * 3);
+    for byte in filename.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", ascii_fallback, encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_strips_directories() {
+        assert_eq!(sanitize_filename("../../.bashrc"), "bashrc");
+        assert_eq!(sanitize_filename("/etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("C:\\Windows\\system32\\evil.exe"), "evil.exe");
+        assert_eq!(sanitize_filename("C:\\"), FALLBACK_FILENAME);
+        assert_eq!(sanitize_filename("C:evil.txt"), "C_evil.txt");
+    }
+
+    #[test]
+    fn sanitize_replaces_control_characters() {
+        assert_eq!(sanitize_filename("a\0b\nc\r.txt"), "a_b_c_.txt");
+        assert_eq!(sanitize_filename("\u{7f}name"), "_name");
+    }
+
+    #[test]
+    fn sanitize_escapes_reserved_names() {
+        assert_eq!(sanitize_filename("CON"), "_CON");
+        assert_eq!(sanitize_filename("nul.txt"), "_nul.txt");
+        assert_eq!(sanitize_filename("com1.tar.gz"), "_com1.tar.gz");
+        assert_eq!(sanitize_filename("console.txt"), "console.txt");
+    }
+
+    #[test]
+    fn sanitize_falls_back_on_empty_names() {
+        assert_eq!(sanitize_filename(""), FALLBACK_FILENAME);
+        assert_eq!(sanitize_filename("..."), FALLBACK_FILENAME);
+        assert_eq!(sanitize_filename("   "), FALLBACK_FILENAME);
+        assert_eq!(sanitize_filename("dir/"), FALLBACK_FILENAME);
+    }
+
+    #[test]
+    fn sanitize_truncates_on_char_boundary() {
+        let name = sanitize_filename(&"я".repeat(MAX_FILENAME_BYTES));
+        assert_eq!(name.len(), MAX_FILENAME_BYTES);
+        assert!(name.chars().all(|c| c == 'я'));
+    }
+
+    #[test]
+    fn content_disposition_escapes_quotes_and_crlf() {
+        let header = content_disposition("a\"b\r\nc.txt");
+        assert_eq!(header, "attachment; filename=\"a_b__c.txt\"; filename*=UTF-8''a%22b%0D%0Ac.txt");
+        assert!(!header.contains(['\r', '\n']));
+    }
+
+    #[test]
+    fn content_disposition_encodes_unicode() {
+        assert_eq!(content_disposition("отчёт.pdf"), "attachment; filename=\"_____.pdf\"; filename*=UTF-8''%D0%BE%D1%82%D1%87%D1%91%D1%82.pdf");
+    }
+
+    #[tokio::test]
+    async fn move_unique_numbers_colliding_names() {
+        let dir = std::env::temp_dir().join(format!("asemic-files-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        tokio::fs::write(dir.join("report.txt"), b"old").await.unwrap();
+        tokio::fs::write(dir.join("report (1).txt"), b"older").await.unwrap();
+        let source = dir.join("incoming");
+        tokio::fs::write(&source, b"new").await.unwrap();
+
+        let path = move_unique(&source, &dir, "report.txt").await.unwrap();
+
+        assert_eq!(path, dir.join("report (2).txt"));
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), b"new");
+        assert_eq!(tokio::fs::read(dir.join("report.txt")).await.unwrap(), b"old");
+        assert!(!source.exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,223 @@
+use crate::protocol::PacketKey;
+use base32::Alphabet;
+use qrcode::render::svg;
+use qrcode::QrCode;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// Формат экспорта: префикс с версией и Base32 от `[длина метки][метка][ключ][контрольная сумма]`.
+/// Base32 без строчных букв и знаков препинания удобно диктовать и хорошо ложится в QR-код.
+const EXPORT_PREFIX: &str = "ASEMIC-KEY-V1:";
+const EXPORT_PREFIX_ANY_VERSION: &str = "ASEMIC-KEY-V";
+const BASE32: Alphabet = Alphabet::Rfc4648 { padding: false };
+/// Первые байты SHA-256: ловят опечатки, а не подделку.
+const CHECKSUM_LEN: usize = 4;
+pub const MAX_LABEL_LEN: usize = 64;
+const GENERATED_KEY_BYTES: usize = 32;
+
+/// Ключ и метка из экспортированной строки.
+#[derive(Debug, PartialEq)]
+pub struct ExportedKey {
+    pub key: String,
+    pub label: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ImportError {
+    UnknownFormat,
+    UnsupportedVersion,
+    Encoding,
+    Checksum,
+    Malformed,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            ImportError::UnknownFormat => "not an exported Asemic key",
+            ImportError::UnsupportedVersion => "exported by a newer version",
+            ImportError::Encoding => "invalid Base32",
+            ImportError::Checksum => "checksum mismatch, the key was mistyped or damaged",
+            ImportError::Malformed => "malformed key data",
+        };
+        f.write_str(message)
+    }
+}
+
+/// Случайный 256-битный ключ в Base32.
+pub fn generate_key() -> String {
+    let mut bytes = [0u8; GENERATED_KEY_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base32::encode(BASE32, &bytes)
+}
+
+/// Обрезает метку до `MAX_LABEL_LEN` байт по границе символа.
+pub fn normalize_label(label: &str) -> String {
+    let label = label.trim();
+    let mut end = label.len().min(MAX_LABEL_LEN);
+    while !label.is_char_boundary(end) {
+        end -= 1;
+    }
+    label[..end].to_string()
+}
+
+pub fn export(key: &str, label: &str) -> String {
+    let label = normalize_label(label);
+    let mut body = Vec::with_capacity(1 + label.len() + key.len() + CHECKSUM_LEN);
+    body.push(label.len() as u8);
+    body.extend_from_slice(label.as_bytes());
+    body.extend_from_slice(key.as_bytes());
+    let checksum = Sha256::digest(&body);
+    body.extend_from_slice(&checksum[..CHECKSUM_LEN]);
+    format!("{}{}", EXPORT_PREFIX, base32::encode(BASE32, &body))
+}
+
+/// Разбирает строку экспорта. Пробелы и переносы строк игнорируются,
+/// регистр Base32 не важен.
+pub fn import(text: &str) -> Result<ExportedKey, ImportError> {
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let encoded = match text.strip_prefix(EXPORT_PREFIX) {
+        Some(encoded) => encoded,
+        None if text.starts_with(EXPORT_PREFIX_ANY_VERSION) => return Err(ImportError::UnsupportedVersion),
+        None => return Err(ImportError::UnknownFormat),
+    };
+    let body = base32::decode(BASE32, &encoded.to_ascii_uppercase()).ok_or(ImportError::Encoding)?;
+    if body.len() < 1 + CHECKSUM_LEN {
+        return Err(ImportError::Malformed);
+    }
+    let (payload, checksum) = body.split_at(body.len() - CHECKSUM_LEN);
+    if Sha256::digest(payload)[..CHECKSUM_LEN] != *checksum {
+        return Err(ImportError::Checksum);
+    }
+    let label_len = payload[0] as usize;
+    let label = payload.get(1..1 + label_len).ok_or(ImportError::Malformed)?;
+    let key = &payload[1 + label_len..];
+    if key.is_empty() {
+        return Err(ImportError::Malformed);
+    }
+    Ok(ExportedKey {
+        key: String::from_utf8(key.to_vec()).map_err(|_| ImportError::Malformed)?,
+        label: String::from_utf8(label.to_vec()).map_err(|_| ImportError::Malformed)?,
+    })
+}
+
+/// QR-код строки экспорта в SVG.
+pub fn qr_svg(text: &str) -> Result<String, qrcode::types::QrError> {
+    let code = QrCode::new(text.as_bytes())?;
+    Ok(code.render::<svg::Color>().min_dimensions(256, 256).build())
+}
+
+/// Подготовленные ключи в порядке списка ключей пользователя.
+pub type PreparedKeys = Arc<Vec<(String, Arc<PacketKey>)>>;
+
+/// Ключи для расшифровки входящих, отдельно от `AppState`: потоки расшифровки
+/// не ждут общую блокировку, а снимок перечитывают, только когда сменилось поколение.
+#[derive(Default)]
+pub struct KeyRing {
+    generation: AtomicU64,
+    keys: RwLock<PreparedKeys>,
+}
+
+impl KeyRing {
+    /// Публикует новый список ключей. Уже подготовленные ключи переиспользуются.
+    pub fn publish(&self, keys: &[String]) {
+        let mut current = self.keys.write().unwrap_or_else(|e| e.into_inner());
+        let mut prepared: HashMap<&str, &Arc<PacketKey>> = current.iter().map(|(k, p)| (k.as_str(), p)).collect();
+        let next: Vec<(String, Arc<PacketKey>)> = keys
+            .iter()
+            .map(|k| {
+                let packet_key = prepared.remove(k.as_str()).cloned().unwrap_or_else(|| Arc::new(PacketKey::new(k.as_bytes())));
+                (k.clone(), packet_key)
+            })
+            .collect();
+        *current = Arc::new(next);
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Текущие ключи вместе с их поколением.
+    pub fn snapshot(&self) -> (u64, PreparedKeys) {
+        let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+        (self.generation(), Arc::clone(&keys))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_body(body: &[u8]) -> String {
+        format!("{}{}", EXPORT_PREFIX, base32::encode(BASE32, body))
+    }
+
+    fn decode_body(exported: &str) -> Vec<u8> {
+        base32::decode(BASE32, exported.strip_prefix(EXPORT_PREFIX).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn import_round_trips_export() {
+        let key = generate_key();
+        let exported = export(&key, "  Алиса  ");
+        assert!(exported.starts_with(EXPORT_PREFIX));
+        assert_eq!(import(&exported), Ok(ExportedKey { key: key.clone(), label: "Алиса".to_string() }));
+        assert_eq!(import(&export(&key, "")), Ok(ExportedKey { key, label: String::new() }));
+    }
+
+    #[test]
+    fn import_ignores_whitespace_and_case() {
+        let key = generate_key();
+        let exported = export(&key, "bob");
+        let (prefix, encoded) = exported.split_at(EXPORT_PREFIX.len());
+        let chunks: Vec<String> = encoded.as_bytes().chunks(8).map(|c| String::from_utf8_lossy(c).to_lowercase()).collect();
+        let typed = format!("{}\n{}\n", prefix, chunks.join(" "));
+        assert_eq!(import(&typed), Ok(ExportedKey { key, label: "bob".to_string() }));
+    }
+
+    #[test]
+    fn import_rejects_corrupted_checksum() {
+        let mut body = decode_body(&export(&generate_key(), "bob"));
+        let last = body.len() - 1;
+        body[last] ^= 0x01;
+        assert_eq!(import(&encode_body(&body)), Err(ImportError::Checksum));
+    }
+
+    #[test]
+    fn import_rejects_corrupted_payload() {
+        let mut body = decode_body(&export(&generate_key(), "bob"));
+        body[2] ^= 0x20;
+        assert_eq!(import(&encode_body(&body)), Err(ImportError::Checksum));
+    }
+
+    #[test]
+    fn import_rejects_wrong_prefix() {
+        let exported = export(&generate_key(), "bob");
+        let encoded = &exported[EXPORT_PREFIX.len()..];
+        assert_eq!(import(encoded), Err(ImportError::UnknownFormat));
+        assert_eq!(import(&format!("ASEMIC-KEY:{}", encoded)), Err(ImportError::UnknownFormat));
+        assert_eq!(import(&format!("ASEMIC-KEY-V2:{}", encoded)), Err(ImportError::UnsupportedVersion));
+    }
+
+    #[test]
+    fn import_rejects_bad_padding() {
+        let exported = export(&generate_key(), "bob");
+        assert_eq!(import(&format!("{}====", exported)), Err(ImportError::Encoding));
+        assert_eq!(import(&format!("{}1", exported)), Err(ImportError::Encoding));
+    }
+
+    #[test]
+    fn import_rejects_truncated_body() {
+        assert_eq!(import(&encode_body(&[0, 1, 2])), Err(ImportError::Malformed));
+        // Метка длиннее самих данных
+        let mut body = vec![200, b'a'];
+        let checksum = Sha256::digest(&body);
+        body.extend_from_slice(&checksum[..CHECKSUM_LEN]);
+        assert_eq!(import(&encode_body(&body)), Err(ImportError::Malformed));
+    }
+}
@@ -0,0 +1,95 @@
+use crate::audit::AuditEvent;
+use crate::files;
+use crate::quarantine;
+use crate::state::{AppState, FileStatus, ReceivedFile, SharedState, WsNotification};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Индекс библиотеки лежит в каталоге загрузок. Ведущая точка гарантирует,
+/// что входящий файл его не перезапишет: `sanitize_filename` такие имена не выдаёт.
+const INDEX_FILENAME: &str = ".library.json";
+
+/// MIME-тип по расширению имени.
+pub fn mime_type(filename: &str) -> String {
+    mime_guess::from_path(filename).first_or_octet_stream().essence_str().to_string()
+}
+
+/// Читает индекс и отбрасывает записи, чьих файлов больше нет на диске.
+pub async fn load(downloads_path: &Path) -> HashMap<Uuid, ReceivedFile> {
+    let index_path = downloads_path.join(INDEX_FILENAME);
+    let entries: Vec<ReceivedFile> = match tokio::fs::read(&index_path).await {
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Ignoring corrupt library index {:?}: {}", index_path, e);
+                return HashMap::new();
+            }
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return HashMap::new(),
+        Err(e) => {
+            warn!("Failed to read library index {:?}: {}", index_path, e);
+            return HashMap::new();
+        }
+    };
+
+    let mut library = HashMap::new();
+    for entry in entries {
+        // Индекс мог быть отредактирован вручную: имя не должно выводить за пределы каталога
+        if files::sanitize_filename(&entry.filename) != entry.filename {
+            warn!("Skipping library entry with unsafe filename {:?}", entry.filename);
+            continue;
+        }
+        if tokio::fs::metadata(downloads_path.join(&entry.filename)).await.is_ok_and(|m| m.is_file()) {
+            library.insert(entry.id, entry);
+        } else {
+            info!("Dropping library entry for missing file '{}'", entry.filename);
+        }
+    }
+    info!("Loaded {} received files from library index", library.len());
+    library
+}
+
+/// Перезаписывает индекс. Вызывается под блокировкой состояния, чтобы записи
+/// от параллельных изменений не перепутались; индекс маленький.
+pub async fn save(state: &AppState) -> io::Result<()> {
+    let mut entries: Vec<&ReceivedFile> = state.received_files.values().collect();
+    entries.sort_by_key(|f| f.received_at);
+    let json = serde_json::to_vec_pretty(&entries).map_err(io::Error::other)?;
+
+    // Пишем во временный файл и переименовываем, чтобы не оставить обрезанный индекс
+    let index_path = state.downloads_path.join(INDEX_FILENAME);
+    let tmp_path = index_path.with_extension("json.tmp");
+    tokio::fs::write(&tmp_path, json).await?;
+    tokio::fs::rename(&tmp_path, &index_path).await
+}
+
+/// Удаляет принятый файл с диска и из индекса. Возвращает `false`, если такого файла нет.
+pub async fn delete_file(state: &SharedState, ws_tx: &broadcast::Sender<WsNotification>, id: Uuid) -> io::Result<bool> {
+    let mut state_guard = state.lock().await;
+    let Some(file) = state_guard.received_files.remove(&id) else {
+        return Ok(false);
+    };
+    let path = state_guard.downloads_path.join(&file.filename);
+    match tokio::fs::remove_file(&path).await {
+        Ok(()) => {}
+        // Файл уже удалили вручную: достаточно убрать запись
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => {
+            state_guard.received_files.insert(id, file);
+            return Err(e);
+        }
+    }
+    state_guard.downloads_used = state_guard.downloads_used.saturating_sub(file.size);
+    if let Err(e) = save(&state_guard).await {
+        warn!("Failed to update library index: {}", e);
+    }
+    quarantine::set_file_status(&mut state.history(), id, FileStatus::Deleted);
+    state_guard.audit.record(AuditEvent::FileDeleted { file_id: id });
+    info!("Deleted received file '{}'", file.filename);
+    ws_tx.send(WsNotification::FileDeleted { id }).ok();
+    Ok(true)
+}
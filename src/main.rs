@@ -1,21 +1,88 @@
 use std::env;
-use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::net::UdpSocket;
-use tokio::sync::{broadcast, mpsc, Mutex};
 use tower_http::services::ServeDir;
 use tracing::info;
 
+mod analyzer;
+mod audit;
+mod batch_io;
+mod bench;
+mod capture;
+mod config;
 mod state;
 mod protocol;
 mod network;
+mod node;
 mod processor;
+mod scheduler;
+#[cfg(test)]
+mod simnet;
+mod pacing;
+mod pipeline;
+mod pmtu;
+mod fec;
+mod keys;
+mod compression;
+mod conversation;
+mod expiry;
+mod files;
+mod library;
+mod metrics;
+mod quarantine;
+mod replay;
+mod resume;
+mod rotation;
 mod web;
 
-use state::{AppState, TransmitCommand, WsNotification};
+use config::Config;
 
 #[tokio::main]
 async fn main() {
+    // Служебные подкоманды работают без сети и веб-интерфейса
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some("bench-keys") = args.first().map(String::as_str) {
+        let counts: Vec<usize> = args[1..].iter().filter_map(|n| n.parse().ok()).filter(|&n| n > 0).collect();
+        bench::run_key_hint_benchmark(if counts.is_empty() { &[1, 10, 100, 500] } else { &counts });
+        return;
+    }
+    if let Some("bench-udp") = args.first().map(String::as_str) {
+        let packets = args.get(1).and_then(|n| n.parse().ok()).filter(|&n| n > 0).unwrap_or(200_000);
+        bench::run_udp_benchmark(packets).await;
+        return;
+    }
+    if let Some("analyze-traffic") = args.first().map(String::as_str) {
+        let seconds = args.get(1).and_then(|n| n.parse().ok()).filter(|&n| n > 0).unwrap_or(10);
+        analyzer::run_traffic_analysis(seconds).await;
+        return;
+    }
+    if let Some("replay") = args.first().map(String::as_str) {
+        // Ключи только из файла или stdin: в аргументах их видно в `ps` и истории оболочки
+        let (capture_path, keys_path) = match &args[1..] {
+            [capture_path] => (capture_path, None),
+            [capture_path, flag, keys_path] if flag == "--keys-file" => (capture_path, Some(keys_path)),
+            _ => {
+                eprintln!("Usage: asemic_new replay <capture.pcap> [--keys-file <path>]  (without a file, reads one key per line from stdin)");
+                std::process::exit(2);
+            }
+        };
+        let text = match keys_path {
+            Some(keys_path) => std::fs::read_to_string(keys_path),
+            None => std::io::read_to_string(std::io::stdin()),
+        };
+        let keys: Vec<String> = match text {
+            Ok(text) => text.lines().map(|k| k.trim().to_string()).filter(|k| !k.is_empty()).collect(),
+            Err(e) => {
+                eprintln!("Failed to read keys: {}", e);
+                std::process::exit(1);
+            }
+        };
+        if let Err(e) = replay::run(std::path::Path::new(capture_path), &keys) {
+            eprintln!("Failed to replay {}: {}", capture_path, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     tracing_subscriber::fmt()
         .with_env_filter("asemic_new=info,tower_http=debug")
         .init();
@@ -32,46 +99,21 @@ async fn main() {
     }
     let serve_dir = ServeDir::new(static_path);
 
-    // --- Путь для загруженных файлов ---
-    let downloads_path = base_dir.join("downloads");
-    if !downloads_path.exists() {
-        info!("'downloads' directory not found. Creating it at: {:?}", downloads_path);
-        tokio::fs::create_dir_all(&downloads_path)
-            .await
-            .expect("Failed to create downloads directory");
-    } else {
-        info!("Using existing downloads directory at: {:?}", downloads_path);
-    }
-    
     // --- Инициализация состояния и каналов ---
-    let shared_state = Arc::new(Mutex::new(AppState::new(downloads_path)));
-    let (packet_tx, packet_rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>(1024);
-    let (transmit_tx, transmit_rx) = mpsc::channel::<TransmitCommand>(128);
-    let (ws_tx, _) = broadcast::channel::<WsNotification>(128);
-    
-    // --- UDP сокет ---
-    let udp_socket = UdpSocket::bind("0.0.0.0:7070").await.expect("Failed to bind UDP socket");
-    info!("UDP socket listening on 0.0.0.0:7070");
-    let shared_socket = Arc::new(udp_socket);
+    let config = Arc::new(Config::from_env());
+    info!("Pacing limits: {}..{} packets/s (initial {})", config.min_rate_pps, config.max_rate_pps, config.initial_rate_pps);
+
+    // --- UDP сокеты (IPv4 и IPv6) ---
+    let sockets = network::UdpSockets::bind(7070, &config).expect("Failed to bind UDP socket");
+    let node = node::Node::start(base_dir, sockets, Arc::clone(&config)).await.expect("Failed to start node");
 
     // --- Запуск основных задач ---
-    let web_state = Arc::clone(&shared_state);
-    let web_task = tokio::spawn(web::run_web_server(web_state, transmit_tx.clone(), ws_tx.clone(), serve_dir));
-    
-    let receiver_socket = Arc::clone(&shared_socket);
-    let receiver_task = tokio::spawn(network::udp_receiver_task(receiver_socket, packet_tx));
-    
-    let transmitter_socket = Arc::clone(&shared_socket);
-    let transmitter_task = tokio::spawn(network::udp_transmitter_task(transmitter_socket, transmit_rx));
-    
-    let processor_state = Arc::clone(&shared_state);
-    let processor_task = tokio::spawn(processor::packet_processor_task(packet_rx, processor_state, ws_tx));
+    let web_task = tokio::spawn(web::run_web_server(Arc::clone(&node.state), node.transmit_tx.clone(), node.ws_tx.clone(), serve_dir, config));
 
     // --- Ожидание завершения задач ---
-    tokio::try_join!(
-        web_task,
-        receiver_task,
-        transmitter_task,
-        processor_task
-    ).expect("A critical task failed");
-}
\ No newline at end of file
+    tokio::select! {
+        result = web_task => result.expect("A critical task failed"),
+        result = node.run() => result.expect("A critical task failed"),
+    }
+    tracing::error!("A critical task exited, shutting down");
+}
@@ -0,0 +1,127 @@
+use crate::state::{AppState, AppStats};
+use std::fmt::Write;
+
+/// Тип содержимого текстового формата Prometheus.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Заполненность очередей, которых нет в `AppStats`.
+pub struct QueueDepths {
+    pub transmit: usize,
+    pub websocket: usize,
+}
+
+/// Метрики для `GET /metrics`. Счётчики приёма обновляются раз в секунду
+/// (см. `pipeline::run_stats_reporter`), так что значения могут отставать на секунду.
+pub fn render(state: &AppState, stats: &AppStats, queues: QueueDepths) -> String {
+    let mut out = Exposition::default();
+
+    out.counter("asemic_packets_sent_total", "Packets sent, including control frames.", stats.packets_sent);
+    out.counter("asemic_packets_received_total", "Datagrams accepted by the receive pipeline.", stats.packets_received);
+    out.counter("asemic_noise_packets_sent_total", "Noise packets sent.", stats.noise_packets_sent);
+    out.counter("asemic_retransmissions_total", "Chunks sent again after a timeout or loss.", stats.retransmissions);
+    out.counter("asemic_bytes_sent_total", "Bytes sent in all packets.", stats.bytes_sent);
+    out.counter("asemic_bytes_received_total", "Bytes received in accepted datagrams.", stats.bytes_received);
+    out.counter("asemic_messages_decrypted_total", "Messages assembled and delivered.", stats.messages_decrypted);
+
+    out.header("asemic_decrypt_success_total", "Packets decrypted, by frame class.", "counter");
+    out.sample("asemic_decrypt_success_total", &[("class", "chunk")], stats.decrypted_chunks);
+    out.sample("asemic_decrypt_success_total", &[("class", "control")], stats.decrypted_control_frames);
+
+    // Обрезанные датаграммы входят и в число слишком длинных, здесь классы не пересекаются
+    out.header("asemic_decrypt_failures_total", "Packets that did not decrypt, by failure class.", "counter");
+    out.sample("asemic_decrypt_failures_total", &[("class", "no_key")], stats.noise_packets_received);
+    out.sample("asemic_decrypt_failures_total", &[("class", "authentication")], stats.decrypt_auth_failures);
+    out.sample("asemic_decrypt_failures_total", &[("class", "malformed_frame")], stats.decrypt_malformed_frames);
+    out.sample(
+        "asemic_decrypt_failures_total",
+        &[("class", "oversized")],
+        stats.oversized_datagrams.saturating_sub(stats.truncated_datagrams),
+    );
+    out.sample("asemic_decrypt_failures_total", &[("class", "truncated")], stats.truncated_datagrams);
+    out.counter(
+        "asemic_malformed_messages_total",
+        "Assembled messages that failed to decode, decompress or parse.",
+        stats.malformed_messages,
+    );
+
+    out.gauge("asemic_reassembly_sessions", "Messages waiting for more chunks.", stats.reassembly_sessions as f64);
+    out.gauge("asemic_reassembly_buffer_bytes", "Bytes of chunks held for reassembly.", stats.reassembly_buffer_bytes as f64);
+    out.gauge("asemic_receive_pool_bytes", "Bytes held by idle receive buffers.", stats.receive_pool_bytes as f64);
+
+    out.header("asemic_queue_depth", "Items waiting in internal queues.", "gauge");
+    out.sample("asemic_queue_depth", &[("queue", "decrypt")], stats.decrypt_queue_depth);
+    out.sample("asemic_queue_depth", &[("queue", "reassembly")], stats.reassembly_queue_depth);
+    out.sample("asemic_queue_depth", &[("queue", "transmit")], queues.transmit);
+    out.sample("asemic_queue_depth", &[("queue", "websocket")], queues.websocket);
+    out.counter(
+        "asemic_backpressure_stalls_total",
+        "Times a pipeline stage waited for room in the next queue.",
+        stats.backpressure_stalls,
+    );
+    if let Some(kernel_drops) = stats.kernel_drops {
+        out.counter("asemic_kernel_drops_total", "Datagrams dropped by the kernel on full socket buffers.", kernel_drops);
+    }
+    out.gauge("asemic_throughput_bytes_per_second", "Outgoing throughput over the last interval.", stats.throughput_bps);
+
+    let mut peers: Vec<_> = state.peer_links.iter().collect();
+    peers.sort_by_key(|(addr, _)| **addr);
+    out.header("asemic_peer_rtt_seconds", "Smoothed round-trip time to the peer.", "gauge");
+    for (addr, link) in &peers {
+        if let Some(rtt_ms) = link.rtt_ms {
+            out.sample("asemic_peer_rtt_seconds", &[("peer", &addr.to_string())], rtt_ms / 1000.0);
+        }
+    }
+    out.header("asemic_peer_rate_pps", "Current send rate to the peer.", "gauge");
+    for (addr, link) in &peers {
+        out.sample("asemic_peer_rate_pps", &[("peer", &addr.to_string())], link.rate_pps);
+    }
+    out.header("asemic_peer_packet_size_bytes", "Packet size found by path MTU probing.", "gauge");
+    for (addr, link) in &peers {
+        out.sample("asemic_peer_packet_size_bytes", &[("peer", &addr.to_string())], link.packet_size);
+    }
+    out.header("asemic_peer_packets_acked_total", "Packets to the peer confirmed by acks.", "counter");
+    for (addr, link) in &peers {
+        out.sample("asemic_peer_packets_acked_total", &[("peer", &addr.to_string())], link.packets_acked);
+    }
+    out.header("asemic_peer_packets_lost_total", "Packets to the peer considered lost.", "counter");
+    for (addr, link) in &peers {
+        out.sample("asemic_peer_packets_lost_total", &[("peer", &addr.to_string())], link.packets_lost);
+    }
+
+    out.text
+}
+
+#[derive(Default)]
+struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    fn header(&mut self, name: &str, help: &str, kind: &str) {
+        writeln!(self.text, "# HELP {} {}", name, help).ok();
+        writeln!(self.text, "# TYPE {} {}", name, kind).ok();
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter().map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v))).collect();
+            write!(self.text, "{{{}}}", labels.join(",")).ok();
+        }
+        writeln!(self.text, " {}", value).ok();
+    }
+
+    fn counter(&mut self, name: &str, help: &str, value: u64) {
+        self.header(name, help, "counter");
+        self.sample(name, &[], value);
+    }
+
+    fn gauge(&mut self, name: &str, help: &str, value: f64) {
+        self.header(name, help, "gauge");
+        self.sample(name, &[], value);
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -1,18 +1,106 @@
 use crate::protocol;
+use crate::batch_io::{BatchSocket, BufferPool, BATCH_SIZE};
+use crate::capture::Capture;
+use crate::compression;
 // ИСПРАВЛЕНИЕ: Добавлены `ObfuscationPattern` и `MessageContent` в импорты.
-use crate::state::{NoiseLevel, TransmitCommand, ObfuscationPattern};
-use base64::{engine::general_purpose, Engine};
+use crate::config::Config;
+use crate::pacing::PeerPacer;
+use crate::pipeline::Dispatcher;
+use crate::pmtu::PathMtu;
+use crate::state::{
+    MessageStatus, NoiseLevel, ObfuscationPattern, PeerLinkStats, SharedState, TransmitCommand,
+    WsNotification,
+};
+use crate::scheduler::{ControlToSend, OutgoingTransfer, TransmitPriority, TransmitQueue};
 use rand::Rng;
-use std::net::SocketAddr;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::{HashMap, HashSet};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::net::UdpSocket;
-use tokio::sync::mpsc;
-use tracing::{error, info, warn};
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
+
+/// Минимальный интервал между событиями `TransmitProgress` для одного сообщения.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+/// Как часто проверяем неподтверждённые чанки на таймаут.
+const LOSS_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+/// Как часто проверяем, не пора ли отправить PMTU-зонд.
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+/// Как часто сбрасываем накопленную статистику отправки в `AppStats`.
+const STATS_INTERVAL: Duration = Duration::from_millis(500);
+/// За сколько времени можно «догнать» отставшие чанки одной пачкой.
+const MAX_SEND_BURST: Duration = Duration::from_millis(5);
+
+/// Трафик, ещё не учтённый в `AppStats`.
+#[derive(Default)]
+struct UnreportedTraffic {
+    packets: u64,
+    bytes: u64,
+    retransmissions: u64,
+}
+
+impl UnreportedTraffic {
+    fn is_empty(&self) -> bool {
+        self.packets == 0
+    }
+}
+
+async fn fail_transfer(
+    transfer: &OutgoingTransfer,
+    reason: &str,
+    state: &SharedState,
+    ws_tx: &broadcast::Sender<WsNotification>,
+    last_progress: &mut HashMap<(SocketAddr, u32), Instant>,
+) {
+    warn!("Giving up on message {} to {}: {}", transfer.msg_id, transfer.target_addr, reason);
+    last_progress.remove(&(transfer.target_addr, transfer.msg_id));
+    if state.history().advance_outgoing_status(transfer.target_addr, transfer.msg_id, MessageStatus::Failed) {
+        ws_tx.send(WsNotification::MessageStatus { target: transfer.target_addr, msg_id: transfer.msg_id, status: MessageStatus::Failed }).ok();
+    }
+}
+
+/// Учитывает потери на пути к `peer`. Если PMTU, похоже, уменьшился, спускается на корзину
+/// ниже и возвращает передачи, чьи чанки в новый размер не помещаются.
+fn on_path_loss(
+    peer: SocketAddr,
+    lost: usize,
+    now: Instant,
+    paths: &mut HashMap<SocketAddr, PathMtu>,
+    queue: &mut TransmitQueue,
+) -> Vec<OutgoingTransfer> {
+    let Some(size) = paths.get_mut(&peer).and_then(|path| path.on_loss(lost, now)) else { return Vec::new() };
+    warn!("Repeated losses without acks from {}: path MTU seems to have dropped, packets are now {} bytes", peer, size);
+    queue.remove_oversized(peer, protocol::chunk_size_for(size))
+}
+
+/// Шифрует и отправляет служебный кадр одним пакетом размера текущей корзины узла.
+async fn send_control(sockets: &UdpSockets, paths: &HashMap<SocketAddr, PathMtu>, state: &SharedState, control: ControlToSend) {
+    let plaintext_payload = protocol::encode_frame(&protocol::Frame::Control(control.frame));
+    let packet_size = paths.get(&control.target_addr).map_or(protocol::DEFAULT_PACKET_SIZE, |p| p.packet_size());
+    let final_packet = protocol::create_packet(plaintext_payload, control.key.as_bytes(), control.pattern, packet_size);
+    if final_packet.is_empty() {
+        error!("Control frame for {} does not fit into {} bytes and was dropped.", control.target_addr, packet_size);
+        return;
+    }
+    match sockets.send_to(&final_packet, control.target_addr).await {
+        Ok(sent) => {
+            let mut stats = state.stats();
+            stats.packets_sent += 1;
+            stats.bytes_sent += sent as u64;
+        }
+        Err(e) => error!("Failed to send control packet to {}: {}", control.target_addr, e),
+    }
+}
 
 pub async fn udp_transmitter_task(
-    socket: Arc<UdpSocket>,
+    sockets: Arc<UdpSockets>,
     mut command_receiver: mpsc::Receiver<TransmitCommand>,
+    state: SharedState,
+    ws_tx: broadcast::Sender<WsNotification>,
+    config: Arc<Config>,
 ) {
     info!("UDP transmitter task started.");
     
@@ -23,60 +111,125 @@ pub async fn udp_transmitter_task(
     // Теперь эта строка корректна, так как тип импортирован
     let mut last_pattern = ObfuscationPattern::Starfall; 
 
+    let mut queue = TransmitQueue::default();
+    let mut pacers: HashMap<SocketAddr, PeerPacer> = HashMap::new();
+    let mut paths: HashMap<SocketAddr, PathMtu> = HashMap::new();
+    let mut path_keys: HashMap<SocketAddr, (String, ObfuscationPattern)> = HashMap::new();
+    let mut probe_interval = tokio::time::interval(PROBE_INTERVAL);
+    let mut next_chunk_at = Instant::now();
+    let mut last_progress: HashMap<(SocketAddr, u32), Instant> = HashMap::new();
+    // Сообщения, у которых хотя бы один пакет первого прохода ушёл в сокет
+    let mut sent_any: HashSet<(SocketAddr, u32)> = HashSet::new();
+    let mut loss_check = tokio::time::interval(LOSS_CHECK_INTERVAL);
+    let mut stats_interval = tokio::time::interval(STATS_INTERVAL);
+    let mut stats_reported_at = Instant::now();
+    let mut link_active = false;
+    let mut unreported = UnreportedTraffic::default();
+
     loop {
         tokio::select! {
             Some(command) = command_receiver.recv() => {
                 match command {
-                    TransmitCommand::SendMessage { target_addr, key, pattern, content } => {
-                        info!("Transmitting message to {} using pattern {:?}", target_addr, pattern);
+                    TransmitCommand::SendMessage { msg_id, target_addr, key, pattern, content, redundancy, compression, ttl_secs } => {
+                        info!("Queueing message {} to {} using pattern {:?}", msg_id, target_addr, pattern);
                         
                         last_target = Some(target_addr);
                         last_key = Some(key.clone());
                         last_pattern = pattern;
+                        path_keys.insert(target_addr, (key.clone(), pattern));
 
-                        let data_to_chunk = match serde_json::to_vec(&content) {
+                        // TTL едет внутри зашифрованного сообщения, а не в заголовках чанков
+                        let envelope = protocol::MessageEnvelope { content, ttl_secs, pattern: Some(pattern) };
+                        let data_to_chunk = match serde_json::to_vec(&envelope) {
                             Ok(data) => data,
                             Err(e) => {
                                 error!("Failed to serialize message content to JSON: {}", e);
                                 continue;
                             }
                         };
-                        
-                        let msg_id: u32 = rand::thread_rng().gen();
-                        let chunks: Vec<&[u8]> = data_to_chunk.chunks(protocol::CHUNK_SIZE).collect();
-                        let total_chunks = chunks.len() as u32;
-
-                        info!("Splitting content ({} bytes) into {} chunks for message ID {}.", data_to_chunk.len(), total_chunks, msg_id);
-
-                        for (i, chunk) in chunks.into_iter().enumerate() {
-                            let data_b64 = general_purpose::STANDARD.encode(chunk);
-                            
-                            let asemic_packet = protocol::AsemicPacket {
-                                msg_id,
-                                chunk_num: i as u32,
-                                total_chunks,
-                                data: data_b64,
-                            };
-                            
-                            let json_payload = serde_json::to_vec(&asemic_packet).unwrap();
-                            
-                            let mut plaintext_payload = Vec::with_capacity(4 + json_payload.len());
-                            plaintext_payload.extend_from_slice(&(json_payload.len() as u32).to_be_bytes());
-                            plaintext_payload.extend_from_slice(&json_payload);
-                            
-                            let final_packet = protocol::create_packet(plaintext_payload, key.as_bytes(), pattern);
-
-                            if final_packet.is_empty() {
-                                error!("Generated packet for chunk {}/{} is too large and was dropped.", i + 1, total_chunks);
-                                continue;
-                            }
 
-                            if let Err(e) = socket.send_to(&final_packet, target_addr).await {
-                                error!("Failed to send data packet to {}: {}", target_addr, e);
+                        let (data_to_chunk, compression) = compression::compress(&envelope.content, data_to_chunk, compression);
+                        let priority = TransmitPriority::for_content(&envelope.content);
+                        let packet_size = paths.entry(target_addr).or_default().packet_size();
+                        let chunk_size = protocol::chunk_size_for(packet_size);
+                        let mut transfer = OutgoingTransfer::new(msg_id, target_addr, key, pattern, priority, data_to_chunk, chunk_size)
+                            .with_compression(compression)
+                            .with_fec(redundancy);
+                        if priority == TransmitPriority::File {
+                            transfer = transfer.resumable();
+                        }
+                        info!(
+                            "Message {} split into {} chunks + {} repair chunks (priority {:?}, compression {:?}).",
+                            msg_id, transfer.total_chunks, transfer.total_packets() - transfer.total_chunks, priority, compression
+                        );
+                        queue.push(transfer);
+                        for offer in queue.poll_resume_offers(Instant::now()) {
+                            send_control(&sockets, &paths, &state, offer).await;
+                        }
+                    }
+                    TransmitCommand::CancelMessage { target, msg_id } => {
+                        match queue.cancel(target, msg_id) {
+                            Some(transfer) => {
+                                last_progress.remove(&(target, msg_id));
+                                sent_any.remove(&(target, msg_id));
+                                info!("Cancelled message {} to {} after {}/{} chunks", msg_id, target, transfer.chunks_sent(), transfer.total_packets());
+                                if state.history().advance_outgoing_status(transfer.target_addr, msg_id, MessageStatus::Cancelled) {
+                                    ws_tx.send(WsNotification::MessageStatus { target: transfer.target_addr, msg_id, status: MessageStatus::Cancelled }).ok();
+                                }
+                            }
+                            None => warn!("Cancel requested for message {} to {}, but it is not in the transmit queue", msg_id, target),
+                        }
+                    }
+                    TransmitCommand::SendControl { target_addr, key, pattern, frame } => {
+                        let control = ControlToSend { target_addr, key, pattern, frame };
+                        send_control(&sockets, &paths, &state, control).await;
+                    }
+                    TransmitCommand::ResumeState { peer, msg_id, have } => {
+                        if let Some(skipped) = queue.on_resume_state(peer, msg_id, &have) {
+                            if skipped > 0 {
+                                info!("Resuming message {} to {}: {} chunks already delivered", msg_id, peer, skipped);
+                            }
+                        }
+                    }
+                    TransmitCommand::PeerAck { peer, msg_id, chunks } => {
+                        let now = Instant::now();
+                        let outcome = queue.on_ack(peer, msg_id, chunks.as_deref(), now);
+                        let pacer = pacers.entry(peer).or_insert_with(|| PeerPacer::new(&config));
+                        if let Some(sample) = outcome.rtt_sample {
+                            pacer.on_rtt_sample(sample);
+                        }
+                        if outcome.acked > 0 {
+                            pacer.on_ack(outcome.acked);
+                        }
+                        if outcome.acked > 0 || outcome.completed.is_some() {
+                            if let Some(path) = paths.get_mut(&peer) {
+                                path.on_ack();
+                            }
+                        }
+                        if outcome.lost > 0 {
+                            debug!("{} chunks of message {} to {} were lost", outcome.lost, msg_id, peer);
+                            pacer.on_loss(outcome.lost, now);
+                            for transfer in on_path_loss(peer, outcome.lost, now, &mut paths, &mut queue) {
+                                sent_any.remove(&(transfer.target_addr, transfer.msg_id));
+                                fail_transfer(&transfer, "path MTU dropped below its chunk size", &state, &ws_tx, &mut last_progress).await;
+                            }
+                        }
+                        if let Some(transfer) = outcome.completed {
+                            info!("All {} chunks of message {} acknowledged by {}", transfer.total_chunks, msg_id, peer);
+                            last_progress.remove(&(peer, msg_id));
+                            sent_any.remove(&(peer, msg_id));
+                        }
+                        if let Some(transfer) = outcome.failed {
+                            sent_any.remove(&(peer, msg_id));
+                            fail_transfer(&transfer, "too many retransmissions", &state, &ws_tx, &mut last_progress).await;
+                        }
+                    }
+                    TransmitCommand::ProbeAck { peer, probe_id, size } => {
+                        if let Some(path) = paths.get_mut(&peer) {
+                            if path.on_probe_ack(probe_id, size) {
+                                info!("Path MTU to {} raised: packets are now {} bytes", peer, size);
                             }
-                            tokio::time::sleep(Duration::from_millis(10)).await;
                         }
-                        info!("Finished sending all {} chunks for message {}", total_chunks, msg_id);
                     }
                     TransmitCommand::SetNoiseLevel(level) => {
                         noise_level = level;
@@ -91,15 +244,164 @@ pub async fn udp_transmitter_task(
                     }
                 }
             }
+            _ = tokio::time::sleep_until(next_chunk_at), if queue.has_pending_chunks() => {
+                let now = Instant::now();
+                // Таймер срабатывает с точностью до миллисекунды, а на высокой скорости чанки
+                // идут чаще: отправляем пачкой все, которым уже пора, но кредит копим не дольше MAX_SEND_BURST
+                let mut due_at = now.checked_sub(MAX_SEND_BURST).map_or(next_chunk_at, |floor| next_chunk_at.max(floor));
+                let mut chunks = Vec::with_capacity(BATCH_SIZE);
+                let mut packets = Vec::with_capacity(BATCH_SIZE);
+                while chunks.len() < BATCH_SIZE && due_at <= now {
+                    let Some(mut chunk) = queue.next_chunk(now) else { break };
+                    let pacer = pacers.entry(chunk.target_addr).or_insert_with(|| PeerPacer::new(&config));
+                    due_at += pacer.interval();
+
+                    let packet_size = paths.get(&chunk.target_addr).map_or(protocol::DEFAULT_PACKET_SIZE, |p| p.packet_size());
+                    let final_packet = protocol::create_packet(std::mem::take(&mut chunk.plaintext), chunk.key.as_bytes(), chunk.pattern, packet_size);
+                    if final_packet.is_empty() {
+                        error!("Generated packet for chunk {} of message {} is too large and was dropped.", chunk.chunk_num, chunk.msg_id);
+                        chunks.push((chunk, None));
+                    } else {
+                        packets.push((final_packet, chunk.target_addr));
+                        chunks.push((chunk, Some(packets.len() - 1)));
+                    }
+                }
+                if chunks.is_empty() {
+                    continue;
+                }
+                next_chunk_at = due_at;
+
+                let results = sockets.send_batch(&packets).await;
+                for (chunk, packet_index) in chunks {
+                    match packet_index.map(|i| &results[i]) {
+                        Some(Ok(sent)) => {
+                            sent_any.insert((chunk.target_addr, chunk.msg_id));
+                            unreported.packets += 1;
+                            unreported.bytes += *sent as u64;
+                            if chunk.is_retransmission {
+                                unreported.retransmissions += 1;
+                            }
+                        }
+                        Some(Err(e)) => error!("Failed to send data packet to {}: {}", chunk.target_addr, e),
+                        None => {}
+                    }
+
+                    if chunk.first_pass_done {
+                        info!("Finished first pass over message {}", chunk.msg_id);
+                        if !sent_any.remove(&(chunk.target_addr, chunk.msg_id)) {
+                            // Ни один пакет не ушёл: повторять нечего, сообщение не отправлено
+                            if let Some(transfer) = queue.cancel(chunk.target_addr, chunk.msg_id) {
+                                fail_transfer(&transfer, "every send failed", &state, &ws_tx, &mut last_progress).await;
+                            }
+                            continue;
+                        }
+                        if state.history().advance_outgoing_status(chunk.target_addr, chunk.msg_id, MessageStatus::Sent) {
+                            ws_tx.send(WsNotification::MessageStatus { target: chunk.target_addr, msg_id: chunk.msg_id, status: MessageStatus::Sent }).ok();
+                        }
+                    }
+
+                    // Прогресс отправляем не чаще PROGRESS_INTERVAL, чтобы не забивать канал WebSocket
+                    let Some(transfer) = queue.get(chunk.target_addr, chunk.msg_id) else { continue };
+                    let due = last_progress.get(&(chunk.target_addr, chunk.msg_id)).is_none_or(|last| now - *last >= PROGRESS_INTERVAL);
+                    if due || chunk.first_pass_done {
+                        last_progress.insert((chunk.target_addr, chunk.msg_id), now);
+                        ws_tx.send(WsNotification::TransmitProgress {
+                            target: chunk.target_addr,
+                            msg_id: chunk.msg_id,
+                            chunks_sent: transfer.chunks_sent(),
+                            total_chunks: transfer.total_packets(),
+                        }).ok();
+                    }
+                }
+            }
+            _ = loss_check.tick(), if !queue.is_empty() => {
+                let now = Instant::now();
+                for offer in queue.poll_resume_offers(now) {
+                    send_control(&sockets, &paths, &state, offer).await;
+                }
+                let reports = queue.detect_timeouts(now, |peer| {
+                    pacers.get(&peer).map_or(Duration::from_secs(1), |p| p.rto())
+                });
+                for report in reports {
+                    if let Some(pacer) = pacers.get_mut(&report.target_addr) {
+                        pacer.on_loss(report.lost, now);
+                    }
+                    for transfer in on_path_loss(report.target_addr, report.lost, now, &mut paths, &mut queue) {
+                        sent_any.remove(&(transfer.target_addr, transfer.msg_id));
+                        fail_transfer(&transfer, "path MTU dropped below its chunk size", &state, &ws_tx, &mut last_progress).await;
+                    }
+                    if let Some(transfer) = report.failed {
+                        sent_any.remove(&(transfer.target_addr, transfer.msg_id));
+                        fail_transfer(&transfer, "too many retransmissions", &state, &ws_tx, &mut last_progress).await;
+                    }
+                }
+            }
+            _ = probe_interval.tick(), if !paths.is_empty() => {
+                // Зонды шифруем последним ключом, которым писали узлу
+                let now = Instant::now();
+                for (peer, path) in paths.iter_mut() {
+                    let Some((key, pattern)) = path_keys.get(peer) else { continue };
+                    let Some((probe_id, size)) = path.poll_probe(now) else { continue };
+                    let frame = protocol::ControlFrame::Probe { probe_id, size };
+                    let probe_packet = protocol::create_packet(protocol::encode_frame(&protocol::Frame::Control(frame)), key.as_bytes(), *pattern, size);
+                    debug!("Probing path MTU to {} with {} bytes", peer, size);
+                    if let Err(e) = sockets.send_to(&probe_packet, *peer).await {
+                        // EMSGSIZE: пакет больше MTU локального интерфейса
+                        debug!("Probe of {} bytes to {} rejected locally: {}", size, peer, e);
+                        path.on_probe_rejected(probe_id, now);
+                    }
+                }
+            }
+            _ = stats_interval.tick() => {
+                let elapsed = stats_reported_at.elapsed().as_secs_f64();
+                stats_reported_at = Instant::now();
+                // После паузы отправляем ещё один отчёт, чтобы обнулить пропускную способность
+                if unreported.is_empty() && !link_active {
+                    continue;
+                }
+                link_active = !unreported.is_empty();
+
+                let stats = {
+                    let mut stats = state.stats();
+                    stats.packets_sent += unreported.packets;
+                    stats.retransmissions += unreported.retransmissions;
+                    stats.bytes_sent += unreported.bytes;
+                    stats.throughput_bps = unreported.bytes as f64 / elapsed.max(0.001);
+                    let rtts: Vec<f64> = pacers.values().filter_map(|p| p.srtt()).map(|d| d.as_secs_f64() * 1000.0).collect();
+                    if !rtts.is_empty() {
+                        stats.rtt_ms = rtts.iter().sum::<f64>() / rtts.len() as f64;
+                    }
+                    *stats
+                };
+                let mut state_guard = state.lock().await;
+                for (peer, pacer) in &pacers {
+                    state_guard.peer_links.insert(*peer, PeerLinkStats {
+                        rtt_ms: pacer.srtt().map(|d| d.as_secs_f64() * 1000.0),
+                        rate_pps: pacer.rate_pps(),
+                        packet_size: paths.get(peer).map_or(protocol::DEFAULT_PACKET_SIZE, |p| p.packet_size()),
+                        packets_acked: pacer.packets_acked,
+                        packets_lost: pacer.packets_lost,
+                    });
+                }
+                unreported = UnreportedTraffic::default();
+                drop(state_guard);
+                ws_tx.send(WsNotification::StatsUpdate(stats)).ok();
+            }
             _ = noise_interval.tick(), if noise_level != NoiseLevel::Off => {
                 if let (Some(target), Some(key)) = (last_target, last_key.as_ref()) {
                     let mut noise_payload: Vec<u8> = vec![0; rand::thread_rng().gen_range(50..200)];
                     rand::thread_rng().fill(&mut noise_payload[..]);
 
-                    let noise_packet = protocol::create_packet(noise_payload, key.as_bytes(), last_pattern);
+                    let packet_size = paths.get(&target).map_or(protocol::DEFAULT_PACKET_SIZE, |p| p.packet_size());
+                    let noise_packet = protocol::create_packet(noise_payload, key.as_bytes(), last_pattern, packet_size);
 
-                     if let Err(e) = socket.send_to(&noise_packet, target).await {
-                        error!("Failed to send noise packet: {}", e);
+                    match sockets.send_to(&noise_packet, target).await {
+                        Ok(sent) => {
+                            let mut stats = state.stats();
+                            stats.noise_packets_sent += 1;
+                            stats.bytes_sent += sent as u64;
+                        }
+                        Err(e) => error!("Failed to send noise packet: {}", e),
                     }
                 } else {
                      warn!("Noise tick: No target or key available to send noise.");
@@ -109,23 +411,243 @@ pub async fn udp_transmitter_task(
     }
 }
 
+/// Транспорт узла. Обычно это сокеты по семействам адресов: IPv4 и IPv6 слушают на одном
+/// порту, но отдельными сокетами, так адреса отправителей остаются родными, без IPv4-mapped
+/// форм `::ffff:a.b.c.d`. В тестах вместо сокетов — подключение к симулятору сети;
+/// приём, захват и отправка пачками для обоих вариантов общие.
+pub enum UdpSockets {
+    Os {
+        v4: Option<Arc<BatchSocket>>,
+        v6: Option<Arc<BatchSocket>>,
+    },
+    #[cfg(test)]
+    Simulated(crate::simnet::SimEndpoint),
+}
+
+impl UdpSockets {
+    /// Привязывает оба сокета. Узел без IPv6 (или без IPv4) работает с тем, что есть;
+    /// ошибка — только если не удалось ни одного.
+    pub fn bind(port: u16, config: &Config) -> std::io::Result<Self> {
+        let v4 = bind_batch_socket(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)), config);
+        let v6 = bind_batch_socket(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)), config);
+        match (v4, v6) {
+            (Err(e), Err(_)) => Err(e),
+            (v4, v6) => Ok(Self::Os { v4: v4.ok(), v6: v6.ok() }),
+        }
+    }
+
+    /// Один IPv4-сокет на свободном порту loopback: для узлов внутри одного процесса.
+    pub fn bind_loopback(config: &Config) -> std::io::Result<Self> {
+        let v4 = bind_batch_socket(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)), config)?;
+        Ok(Self::Os { v4: Some(v4), v6: None })
+    }
+
+    /// Адрес IPv4-сокета, а если его нет — IPv6.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Os { .. } => self.all().next().and_then(|socket| socket.local_addr().ok()),
+            #[cfg(test)]
+            Self::Simulated(endpoint) => Some(endpoint.local_addr()),
+        }
+    }
+
+    /// Сокеты ОС, если они есть: по ним считается статистика отброшенных датаграмм.
+    pub fn all(&self) -> impl Iterator<Item = &Arc<BatchSocket>> {
+        let (v4, v6) = match self {
+            Self::Os { v4, v6 } => (v4.as_ref(), v6.as_ref()),
+            #[cfg(test)]
+            Self::Simulated(_) => (None, None),
+        };
+        v4.into_iter().chain(v6)
+    }
+
+    fn for_target(&self, target: SocketAddr) -> std::io::Result<&BatchSocket> {
+        let socket = match self {
+            Self::Os { v4, .. } if target.is_ipv4() => v4.as_deref(),
+            Self::Os { v6, .. } => v6.as_deref(),
+            #[cfg(test)]
+            Self::Simulated(_) => None,
+        };
+        socket.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::Unsupported, format!("no {} socket", if target.is_ipv4() { "IPv4" } else { "IPv6" }))
+        })
+    }
+
+    pub async fn send_to(&self, packet: &[u8], target: SocketAddr) -> std::io::Result<usize> {
+        match self {
+            Self::Os { .. } => self.for_target(target)?.send_to(packet, target).await,
+            #[cfg(test)]
+            Self::Simulated(endpoint) => endpoint.send_to(packet, target),
+        }
+    }
+
+    /// Пачка обычно уходит одному узлу и целиком идёт через один сокет;
+    /// пачку с адресами разных семейств (и любую пачку в симуляторе) отправляем по одному пакету.
+    pub async fn send_batch(&self, packets: &[(Vec<u8>, SocketAddr)]) -> Vec<std::io::Result<usize>> {
+        let Some((_, first)) = packets.first() else { return Vec::new() };
+        if matches!(self, Self::Os { .. }) && packets.iter().all(|(_, target)| target.is_ipv4() == first.is_ipv4()) {
+            return match self.for_target(*first) {
+                Ok(socket) => socket.send_batch(packets).await,
+                Err(e) => packets.iter().map(|_| Err(std::io::Error::new(e.kind(), e.to_string()))).collect(),
+            };
+        }
+        let mut results = Vec::with_capacity(packets.len());
+        for (packet, target) in packets {
+            results.push(self.send_to(packet, *target).await);
+        }
+        results
+    }
+}
+
+fn bind_batch_socket(addr: SocketAddr, config: &Config) -> std::io::Result<Arc<BatchSocket>> {
+    match bind_udp(addr, config) {
+        Ok(socket) => {
+            info!("UDP socket listening on {}", socket.local_addr().unwrap_or(addr));
+            disable_fragmentation(&socket);
+            Ok(Arc::new(BatchSocket::new(Arc::new(socket))))
+        }
+        Err(e) => {
+            warn!("Failed to bind UDP socket on {}: {}", addr, e);
+            Err(e)
+        }
+    }
+}
+
+/// Разрешает адрес узла и выбирает из ответов первый, до которого есть маршрут.
+/// Порядок резолвера (RFC 6724) сохраняем, но пропускаем семейство, которым узел
+/// не может отправить, например IPv6 на машине только с IPv4.
+pub async fn resolve_peer(target: &str) -> std::io::Result<Option<SocketAddr>> {
+    let addresses: Vec<SocketAddr> = lookup_host(target).await?.map(canonical_addr).collect();
+    for addr in &addresses {
+        if has_route(*addr).await {
+            return Ok(Some(*addr));
+        }
+    }
+    Ok(addresses.first().copied())
+}
+
+/// IPv4-mapped IPv6 адрес превращает в обычный IPv4: IPv6-сокет отправлять на него не станет.
+fn canonical_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(v4) => SocketAddr::from((v4, v6.port())),
+            None => addr,
+        },
+        v4 => v4,
+    }
+}
+
+/// `connect` UDP-сокета ничего не отправляет, но проверяет, что есть стек и маршрут.
+async fn has_route(addr: SocketAddr) -> bool {
+    let local = if addr.is_ipv4() { SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)) } else { SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)) };
+    match tokio::net::UdpSocket::bind(local).await {
+        Ok(socket) => socket.connect(addr).await.is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Создаёт UDP-сокет и до привязки задаёт размеры его буферов в ядре.
+fn bind_udp(addr: SocketAddr, config: &Config) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        // IPv4 принимает свой сокет на том же порту
+        socket.set_only_v6(true)?;
+    }
+    if config.socket_recv_buffer_bytes > 0 {
+        if let Err(e) = socket.set_recv_buffer_size(config.socket_recv_buffer_bytes) {
+            warn!("Failed to set SO_RCVBUF to {} bytes: {}", config.socket_recv_buffer_bytes, e);
+        }
+    }
+    if config.socket_send_buffer_bytes > 0 {
+        if let Err(e) = socket.set_send_buffer_size(config.socket_send_buffer_bytes) {
+            warn!("Failed to set SO_SNDBUF to {} bytes: {}", config.socket_send_buffer_bytes, e);
+        }
+    }
+    // Linux удваивает запрошенное значение под служебные данные, но не выше net.core.rmem_max/wmem_max
+    let (recv_buffer, send_buffer) = (socket.recv_buffer_size()?, socket.send_buffer_size()?);
+    info!("Socket buffers for {}: SO_RCVBUF {} bytes, SO_SNDBUF {} bytes", addr, recv_buffer, send_buffer);
+    if recv_buffer < config.socket_recv_buffer_bytes {
+        warn!(
+            "Kernel limited SO_RCVBUF to {} bytes (requested {}); raise net.core.rmem_max to avoid drops under load",
+            recv_buffer, config.socket_recv_buffer_bytes
+        );
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Запрещает ядру фрагментировать наши датаграммы (флаг DF), иначе PMTU-зонды
+/// проходили бы всегда. Слишком большой пакет вернёт EMSGSIZE или потеряется в сети.
+#[cfg(target_os = "linux")]
+fn disable_fragmentation(socket: &UdpSocket) {
+    use std::os::fd::AsRawFd;
+    // *_PMTUDISC_PROBE: ставим DF, но не ограничиваем размер закэшированным PMTU ядра.
+    // IPv6-маршрутизаторы не фрагментируют и так, но ядро отправителя фрагментировало бы.
+    let (level, option, value, name) = match socket.local_addr() {
+        Ok(addr) if addr.is_ipv6() => (libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, libc::IPV6_PMTUDISC_PROBE, "IPV6_MTU_DISCOVER"),
+        _ => (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, libc::IP_PMTUDISC_PROBE, "IP_MTU_DISCOVER"),
+    };
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        warn!("Failed to set {}: {}", name, std::io::Error::last_os_error());
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn disable_fragmentation(_socket: &UdpSocket) {
+    warn!("Don't-fragment is not supported on this platform; path MTU probes may be fragmented.");
+}
+
 pub async fn udp_receiver_task(
-    socket: Arc<UdpSocket>,
-    packet_sender: mpsc::Sender<(Vec<u8>, SocketAddr)>,
+    sockets: Arc<UdpSockets>,
+    pool: Arc<BufferPool>,
+    dispatcher: Dispatcher,
+    capture: Option<Capture>,
 ) {
     info!("UDP receiver task started.");
-    let mut buf = vec![0u8; 2048];
-    loop {
-        match socket.recv_from(&mut buf).await {
-            Ok((len, sender_addr)) => {
-                let packet_data = buf[..len].to_vec();
-                if let Err(e) = packet_sender.send((packet_data, sender_addr)).await {
-                    error!("Failed to send packet to processor: {}", e);
-                }
-            }
-            Err(e) => {
-                error!("Error receiving from UDP socket: {}", e);
+    match &*sockets {
+        UdpSockets::Os { .. } => {
+            // Оба сокета кормят один конвейер
+            let receivers = sockets.all().map(|socket| receive_loop(socket, &pool, &dispatcher, capture.as_ref()));
+            futures_util::future::join_all(receivers).await;
+        }
+        #[cfg(test)]
+        UdpSockets::Simulated(endpoint) => {
+            while let Some((packet_data, sender_addr)) = endpoint.recv().await {
+                deliver(packet_data, sender_addr, endpoint.local_addr(), &dispatcher, capture.as_ref()).await;
             }
         }
     }
-}
\ No newline at end of file
+}
+
+async fn receive_loop(socket: &BatchSocket, pool: &BufferPool, dispatcher: &Dispatcher, capture: Option<&Capture>) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let local_addr = socket.local_addr().unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 0)));
+    loop {
+        if let Err(e) = socket.recv_batch(pool, &mut batch).await {
+            error!("Error receiving from UDP socket: {}", e);
+        }
+        for (packet_data, sender_addr) in batch.drain(..) {
+            deliver(packet_data, sender_addr, local_addr, dispatcher, capture).await;
+        }
+    }
+}
+
+/// Принятая датаграмма: в захват, если он включён, и в конвейер расшифровки.
+async fn deliver(packet_data: Vec<u8>, sender_addr: SocketAddr, local_addr: SocketAddr, dispatcher: &Dispatcher, capture: Option<&Capture>) {
+    if let Some(capture) = capture {
+        capture.record(&packet_data, sender_addr, local_addr);
+    }
+    if !dispatcher.dispatch(packet_data, sender_addr).await {
+        error!("Failed to send packet to processor: decrypt workers stopped");
+    }
+}
@@ -0,0 +1,134 @@
+use crate::audit::{self, KeySource};
+use crate::batch_io::BufferPool;
+use crate::capture::Capture;
+use crate::config::Config;
+use crate::expiry;
+use crate::files;
+use crate::keys::KeyRing;
+use crate::library;
+use crate::network::{self, UdpSockets};
+use crate::pipeline;
+use crate::processor;
+use crate::quarantine;
+use crate::resume::ResumeStore;
+use crate::rotation;
+use crate::state::{AppState, SendMessagePayload, Shared, SharedState, TransmitCommand, WsNotification};
+use crate::web;
+use rand::Rng;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Сколько свободных буферов держим про запас: примерно столько пакетов помещается в очереди конвейера.
+const RECEIVE_POOL_CAPACITY: usize = 4096;
+
+/// Запущенный узел без веб-интерфейса: приём, передача, сборка и фоновые задачи.
+pub struct Node {
+    pub state: SharedState,
+    pub transmit_tx: mpsc::Sender<TransmitCommand>,
+    pub ws_tx: broadcast::Sender<WsNotification>,
+    pub sockets: Arc<UdpSockets>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Node {
+    /// Запускает узел на готовых сокетах. Загрузки, карантин, недособранные передачи
+    /// и журнал аудита живут в `base_dir`.
+    pub async fn start(base_dir: &Path, sockets: UdpSockets, config: Arc<Config>) -> std::io::Result<Self> {
+        let downloads_path = base_dir.join("downloads");
+        if !downloads_path.exists() {
+            info!("'downloads' directory not found. Creating it at: {:?}", downloads_path);
+            tokio::fs::create_dir_all(&downloads_path).await?;
+        } else {
+            info!("Using existing downloads directory at: {:?}", downloads_path);
+        }
+
+        // --- Карантин для входящих файлов, ожидающих решения ---
+        let quarantine_path = base_dir.join("quarantine");
+        tokio::fs::create_dir_all(&quarantine_path).await?;
+        if let Err(e) = quarantine::clear_stale(&quarantine_path).await {
+            warn!("Failed to clear quarantine directory {:?}: {}", quarantine_path, e);
+        }
+
+        // --- Недособранные передачи, которые можно продолжить ---
+        let resume_store = ResumeStore::load(base_dir.join("partial"))?;
+
+        // --- Инициализация состояния и каналов ---
+        let key_ring = Arc::new(KeyRing::default());
+        let audit_log = audit::AuditLog::open(base_dir.join("audit.jsonl"), config.audit_retention_days, Arc::clone(&key_ring))?;
+        let mut app_state = AppState::new(downloads_path.clone(), quarantine_path, config.file_policy(), audit_log, Arc::clone(&key_ring));
+        app_state.received_files = library::load(&downloads_path).await;
+        app_state.downloads_used = files::directory_size(&downloads_path).await?;
+        let state = Arc::new(Shared::new(app_state));
+        let (transmit_tx, transmit_rx) = mpsc::channel::<TransmitCommand>(128);
+        let (ws_tx, _) = broadcast::channel::<WsNotification>(128);
+        // Расшифровка идёт в нескольких потоках, сборка — в одной задаче
+        let buffer_pool = Arc::new(BufferPool::new(config.receive_buffer_bytes, RECEIVE_POOL_CAPACITY));
+        let (dispatcher, decrypted_rx) = pipeline::start(config.decrypt_workers, config.decrypt_in_tasks, key_ring, Arc::clone(&buffer_pool), ws_tx.clone());
+        let sockets = Arc::new(sockets);
+
+        // --- Запуск основных задач ---
+        let capture = config.capture_path.as_deref().and_then(|path| match Capture::start(path) {
+            Ok(capture) => Some(capture),
+            Err(e) => {
+                warn!("Failed to start packet capture to {:?}: {}", path, e);
+                None
+            }
+        });
+        let pipeline_metrics = dispatcher.metrics();
+        let tasks = vec![
+            tokio::spawn(network::udp_receiver_task(Arc::clone(&sockets), buffer_pool, dispatcher.clone(), capture)),
+            tokio::spawn(network::udp_transmitter_task(
+                Arc::clone(&sockets),
+                transmit_rx,
+                Arc::clone(&state),
+                ws_tx.clone(),
+                Arc::clone(&config),
+            )),
+            tokio::spawn(expiry::run_sweeper(Arc::clone(&state), ws_tx.clone())),
+            tokio::spawn(rotation::run(Arc::clone(&state), ws_tx.clone())),
+            tokio::spawn(pipeline::run_stats_reporter(dispatcher, Arc::clone(&sockets), Arc::clone(&state), ws_tx.clone())),
+            tokio::spawn(processor::packet_processor_task(
+                decrypted_rx,
+                resume_store,
+                Arc::clone(&state),
+                ws_tx.clone(),
+                transmit_tx.clone(),
+                config,
+                pipeline_metrics,
+            )),
+        ];
+        Ok(Self { state, transmit_tx, ws_tx, sockets, tasks })
+    }
+
+    /// Добавляет ключ тем же путём, что и `POST /keys`: с меткой и записью в журнал аудита.
+    pub async fn add_key(&self, key: &str, label: Option<&str>) {
+        self.state.lock().await.add_key(&self.ws_tx, key.to_string(), label, KeySource::Manual);
+    }
+
+    /// Отправляет сообщение тем же путём, что и `POST /send`: с записью в историю исходящих.
+    pub async fn send_message(&self, payload: SendMessagePayload, target_addr: SocketAddr) -> Option<u32> {
+        let msg_id = rand::thread_rng().gen();
+        web::queue_message(&self.state, &self.transmit_tx, &self.ws_tx, payload, target_addr, msg_id).await.then_some(msg_id)
+    }
+
+    /// Ждёт, пока какая-нибудь из задач узла не завершится (штатно они не завершаются),
+    /// и останавливает остальные: узел без любой из них уже неработоспособен.
+    pub async fn run(self) -> Result<(), tokio::task::JoinError> {
+        let (result, _, rest) = futures_util::future::select_all(self.tasks).await;
+        for task in rest {
+            task.abort();
+        }
+        result
+    }
+
+    /// Останавливает задачи узла.
+    pub fn shutdown(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
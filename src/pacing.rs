@@ -0,0 +1,99 @@
+use crate::config::Config;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Нижняя граница таймаута повторной отправки.
+const MIN_RTO: Duration = Duration::from_millis(200);
+/// Таймаут до первого замера RTT.
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+/// Во сколько раз снижаем скорость при потере.
+const DECREASE_FACTOR: f64 = 0.5;
+
+/// Контроль скорости отправки чанков одному узлу (AIMD со стартовой фазой удвоения).
+/// Скорость меряется в пакетах в секунду, окно — это `rate * srtt`.
+pub struct PeerPacer {
+    rate_pps: f64,
+    min_rate_pps: f64,
+    max_rate_pps: f64,
+    slow_start: bool,
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    last_decrease: Option<Instant>,
+    pub packets_acked: u64,
+    pub packets_lost: u64,
+}
+
+impl PeerPacer {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            rate_pps: config.initial_rate_pps,
+            min_rate_pps: config.min_rate_pps,
+            max_rate_pps: config.max_rate_pps,
+            slow_start: true,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            last_decrease: None,
+            packets_acked: 0,
+            packets_lost: 0,
+        }
+    }
+
+    pub fn rate_pps(&self) -> f64 {
+        self.rate_pps
+    }
+
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    /// Пауза перед следующим чанком этому узлу.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.rate_pps)
+    }
+
+    /// Таймаут, после которого неподтверждённый чанк считается потерянным (RFC 6298).
+    pub fn rto(&self) -> Duration {
+        match self.srtt {
+            Some(srtt) => (srtt + 4 * self.rttvar).max(MIN_RTO),
+            None => INITIAL_RTO,
+        }
+    }
+
+    pub fn on_rtt_sample(&mut self, sample: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(sample);
+                self.rttvar = sample / 2;
+            }
+            Some(srtt) => {
+                let delta = srtt.abs_diff(sample);
+                self.rttvar = (self.rttvar * 3 + delta) / 4;
+                self.srtt = Some((srtt * 7 + sample) / 8);
+            }
+        }
+    }
+
+    /// Подтверждено `count` чанков: в стартовой фазе окно растёт на `count`,
+    /// дальше — примерно на один пакет за RTT.
+    pub fn on_ack(&mut self, count: usize) {
+        self.packets_acked += count as u64;
+        let srtt = self.srtt.unwrap_or(INITIAL_RTO).as_secs_f64().max(0.001);
+        let window = (self.rate_pps * srtt).max(1.0);
+        let window_growth = if self.slow_start { count as f64 } else { count as f64 / window };
+        self.rate_pps = (self.rate_pps + window_growth / srtt).min(self.max_rate_pps);
+    }
+
+    /// Потеря `count` чанков. Скорость снижается не чаще раза за RTT,
+    /// чтобы одна пачка потерь не обрушила её до минимума.
+    pub fn on_loss(&mut self, count: usize, now: Instant) {
+        self.packets_lost += count as u64;
+        self.slow_start = false;
+        let recently_decreased = self
+            .last_decrease
+            .is_some_and(|last| now - last < self.srtt.unwrap_or(INITIAL_RTO));
+        if !recently_decreased {
+            self.rate_pps = (self.rate_pps * DECREASE_FACTOR).max(self.min_rate_pps);
+            self.last_decrease = Some(now);
+        }
+    }
+}
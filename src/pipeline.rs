@@ -0,0 +1,351 @@
+use crate::audit::{key_fingerprint, AuditEvent};
+use crate::batch_io::BufferPool;
+use crate::network::UdpSockets;
+use crate::keys::{KeyRing, PreparedKeys};
+use crate::protocol::{Frame, OpenError};
+use crate::state::{AppStats, SharedState, WsNotification};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+/// Очередь каждого потока расшифровки, пакетов.
+const DECRYPT_QUEUE_CAPACITY: usize = 256;
+/// Очередь расшифрованных пакетов перед сборкой.
+const REASSEMBLY_QUEUE_CAPACITY: usize = 1024;
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+/// Неудачные расшифровки попадают в журнал аудита сводкой не чаще этого.
+const AUDIT_FAILURES_INTERVAL: Duration = Duration::from_secs(60);
+/// Паттерны, которые мы пробуем при дешифровке.
+/// Пакет, который подошёл к одному из ключей.
+pub struct DecryptedPacket {
+    pub frame: Frame,
+    pub key: String,
+    pub sender: SocketAddr,
+    pub packet_len: usize,
+}
+
+/// Счётчики конвейера. Обновляются без блокировки состояния и раз в секунду
+/// переносятся в `AppStats`.
+#[derive(Default)]
+pub struct PipelineMetrics {
+    packets_received: AtomicU64,
+    bytes_received: AtomicU64,
+    noise_packets_received: AtomicU64,
+    decrypted_chunks: AtomicU64,
+    decrypted_control_frames: AtomicU64,
+    auth_failures: AtomicU64,
+    malformed_frames: AtomicU64,
+    malformed_messages: AtomicU64,
+    backpressure_stalls: AtomicU64,
+    reassembly_sessions: AtomicU64,
+    reassembly_buffer_bytes: AtomicU64,
+}
+
+impl PipelineMetrics {
+    /// Собранное сообщение не удалось декодировать, распаковать или разобрать.
+    pub fn record_malformed_message(&self) {
+        self.malformed_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_reassembly(&self, sessions: usize, buffered_bytes: usize) {
+        self.reassembly_sessions.store(sessions as u64, Ordering::Relaxed);
+        self.reassembly_buffer_bytes.store(buffered_bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/// Почему пакет не расшифровался.
+pub enum DecryptFailure {
+    /// Подсказка не подошла ни к одному ключу: чужой трафик или шум.
+    NoKey,
+    Open(OpenError),
+}
+
+/// Входная точка конвейера: раскладывает пакеты по потокам расшифровки.
+/// Пакеты одного отправителя всегда попадают в один поток и не переупорядочиваются.
+#[derive(Clone)]
+pub struct Dispatcher {
+    workers: Vec<mpsc::Sender<(Vec<u8>, SocketAddr)>>,
+    reassembly: mpsc::Sender<DecryptedPacket>,
+    pool: Arc<BufferPool>,
+    metrics: Arc<PipelineMetrics>,
+}
+
+impl Dispatcher {
+    pub fn metrics(&self) -> Arc<PipelineMetrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Ставит пакет в очередь его потока. Если очередь полна, ждёт (и тем самым
+    /// притормаживает приём из сокета). Возвращает `false`, если конвейер остановлен.
+    pub async fn dispatch(&self, packet: Vec<u8>, sender: SocketAddr) -> bool {
+        let worker = &self.workers[shard_for(sender, self.workers.len())];
+        match worker.try_send((packet, sender)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(item)) => {
+                self.metrics.backpressure_stalls.fetch_add(1, Ordering::Relaxed);
+                worker.send(item).await.is_ok()
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    fn decrypt_queue_depth(&self) -> usize {
+        self.workers.iter().map(|w| w.max_capacity() - w.capacity()).sum()
+    }
+
+    fn reassembly_queue_depth(&self) -> usize {
+        self.reassembly.max_capacity() - self.reassembly.capacity()
+    }
+}
+
+fn shard_for(sender: SocketAddr, shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    sender.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+/// Запускает `worker_count` потоков расшифровки. Возвращает вход конвейера
+/// и очередь расшифрованных пакетов для стадии сборки. С `in_tasks` расшифровка идёт
+/// в задачах рантайма: так её видят остановленные часы tokio в симуляторе сети.
+pub fn start(
+    worker_count: usize,
+    in_tasks: bool,
+    key_ring: Arc<KeyRing>,
+    pool: Arc<BufferPool>,
+    ws_tx: broadcast::Sender<WsNotification>,
+) -> (Dispatcher, mpsc::Receiver<DecryptedPacket>) {
+    info!("Starting {} decrypt workers{}.", worker_count, if in_tasks { " as runtime tasks" } else { "" });
+    let metrics = Arc::new(PipelineMetrics::default());
+    let (reassembly_tx, reassembly_rx) = mpsc::channel(REASSEMBLY_QUEUE_CAPACITY);
+    let workers = (0..worker_count)
+        .map(|_| {
+            let (worker_tx, worker_rx) = mpsc::channel(DECRYPT_QUEUE_CAPACITY);
+            let worker = DecryptWorker::new(Arc::clone(&key_ring), Arc::clone(&pool), ws_tx.clone(), Arc::clone(&metrics));
+            let output = reassembly_tx.clone();
+            if in_tasks {
+                tokio::spawn(worker.run_async(worker_rx, output));
+            } else {
+                tokio::task::spawn_blocking(move || worker.run_blocking(worker_rx, output));
+            }
+            worker_tx
+        })
+        .collect();
+    (Dispatcher { workers, reassembly: reassembly_tx, pool, metrics }, reassembly_rx)
+}
+
+/// Поток расшифровки: не трогает общее состояние, ключи берёт из `KeyRing`.
+struct DecryptWorker {
+    key_ring: Arc<KeyRing>,
+    generation: u64,
+    keys: PreparedKeys,
+    pool: Arc<BufferPool>,
+    ws_tx: broadcast::Sender<WsNotification>,
+    metrics: Arc<PipelineMetrics>,
+}
+
+impl DecryptWorker {
+    fn new(key_ring: Arc<KeyRing>, pool: Arc<BufferPool>, ws_tx: broadcast::Sender<WsNotification>, metrics: Arc<PipelineMetrics>) -> Self {
+        let (generation, keys) = key_ring.snapshot();
+        Self { key_ring, generation, keys, pool, ws_tx, metrics }
+    }
+
+    fn run_blocking(mut self, mut input: mpsc::Receiver<(Vec<u8>, SocketAddr)>, output: mpsc::Sender<DecryptedPacket>) {
+        while let Some((packet, sender)) = input.blocking_recv() {
+            let Some(decrypted) = self.process(packet, sender) else { continue };
+            let delivered = match output.try_send(decrypted) {
+                Ok(()) => true,
+                Err(TrySendError::Full(decrypted)) => {
+                    self.metrics.backpressure_stalls.fetch_add(1, Ordering::Relaxed);
+                    output.blocking_send(decrypted).is_ok()
+                }
+                Err(TrySendError::Closed(_)) => false,
+            };
+            if !delivered {
+                break;
+            }
+        }
+    }
+
+    async fn run_async(mut self, mut input: mpsc::Receiver<(Vec<u8>, SocketAddr)>, output: mpsc::Sender<DecryptedPacket>) {
+        while let Some((packet, sender)) = input.recv().await {
+            let Some(decrypted) = self.process(packet, sender) else { continue };
+            let delivered = match output.try_send(decrypted) {
+                Ok(()) => true,
+                Err(TrySendError::Full(decrypted)) => {
+                    self.metrics.backpressure_stalls.fetch_add(1, Ordering::Relaxed);
+                    output.send(decrypted).await.is_ok()
+                }
+                Err(TrySendError::Closed(_)) => false,
+            };
+            if !delivered {
+                break;
+            }
+        }
+    }
+
+    /// Расшифровывает пакет и возвращает буфер в пул. `None` — шум или ошибка, она уже учтена.
+    fn process(&mut self, packet: Vec<u8>, sender: SocketAddr) -> Option<DecryptedPacket> {
+        let metrics = &self.metrics;
+        metrics.packets_received.fetch_add(1, Ordering::Relaxed);
+        metrics.bytes_received.fetch_add(packet.len() as u64, Ordering::Relaxed);
+        if self.key_ring.generation() != self.generation {
+            (self.generation, self.keys) = self.key_ring.snapshot();
+        }
+        let decrypted = decrypt(&self.keys, &packet, sender);
+        let packet_len = packet.len();
+        // Кадр уже разобран, буфер можно снова отдать приёму
+        self.pool.put(packet);
+        let decrypted = match decrypted {
+            Ok(decrypted) => decrypted,
+            Err(failure) => {
+                let counter = match failure {
+                    DecryptFailure::NoKey => &metrics.noise_packets_received,
+                    DecryptFailure::Open(OpenError::Authentication) => &metrics.auth_failures,
+                    DecryptFailure::Open(OpenError::Malformed) => &metrics.malformed_frames,
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+                // Если ни один ключ/паттерн не подошел, считаем пакет шумом
+                debug!("Received a noise packet of size {} from {}", packet_len, sender);
+                self.ws_tx.send(WsNotification::NoisePacket { sender, size: packet_len }).ok();
+                return None;
+            }
+        };
+        let counter = match decrypted.frame {
+            Frame::Chunk(_) => &metrics.decrypted_chunks,
+            Frame::Control(_) => &metrics.decrypted_control_frames,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        Some(decrypted)
+    }
+}
+
+/// Расшифровка одного пакета; этим же путём идёт разбор захвата (`replay`).
+pub fn decrypt(keys: &PreparedKeys, packet: &[u8], sender: SocketAddr) -> Result<DecryptedPacket, DecryptFailure> {
+    // Подсказка в пакете отсеивает чужие ключи без расшифровки; полную проверку
+    // проходят только совпавшие (обычно один, для шума — ни одного)
+    let candidates: Vec<_> = keys.iter().filter(|(_, packet_key)| packet_key.matches(packet)).collect();
+    let mut failure = DecryptFailure::NoKey;
+    for (key, packet_key) in candidates {
+        match packet_key.try_open(packet) {
+            Ok(frame) => {
+                debug!("Decrypted a packet from {} with key {}", sender, key_fingerprint(key));
+                return Ok(DecryptedPacket { frame, key: key.clone(), sender, packet_len: packet.len() });
+            }
+            Err(e) => failure = DecryptFailure::Open(e),
+        }
+    }
+    Err(failure)
+}
+
+/// Показания счётчиков приёма на момент опроса.
+#[derive(Clone, Copy, PartialEq, Default)]
+struct ReceiveSample {
+    packets_received: u64,
+    bytes_received: u64,
+    noise_packets_received: u64,
+    decrypted_chunks: u64,
+    decrypted_control_frames: u64,
+    decrypt_auth_failures: u64,
+    decrypt_malformed_frames: u64,
+    malformed_messages: u64,
+    reassembly_sessions: u64,
+    reassembly_buffer_bytes: u64,
+    receive_pool_bytes: u64,
+    backpressure_stalls: u64,
+    decrypt_queue_depth: u64,
+    reassembly_queue_depth: u64,
+    truncated_datagrams: u64,
+    oversized_datagrams: u64,
+    kernel_drops: Option<u64>,
+}
+
+impl ReceiveSample {
+    fn take(dispatcher: &Dispatcher, sockets: &UdpSockets) -> Self {
+        let metrics = &dispatcher.metrics;
+        Self {
+            packets_received: metrics.packets_received.load(Ordering::Relaxed),
+            bytes_received: metrics.bytes_received.load(Ordering::Relaxed),
+            noise_packets_received: metrics.noise_packets_received.load(Ordering::Relaxed),
+            decrypted_chunks: metrics.decrypted_chunks.load(Ordering::Relaxed),
+            decrypted_control_frames: metrics.decrypted_control_frames.load(Ordering::Relaxed),
+            decrypt_auth_failures: metrics.auth_failures.load(Ordering::Relaxed),
+            decrypt_malformed_frames: metrics.malformed_frames.load(Ordering::Relaxed),
+            malformed_messages: metrics.malformed_messages.load(Ordering::Relaxed),
+            reassembly_sessions: metrics.reassembly_sessions.load(Ordering::Relaxed),
+            reassembly_buffer_bytes: metrics.reassembly_buffer_bytes.load(Ordering::Relaxed),
+            receive_pool_bytes: dispatcher.pool.pooled_bytes() as u64,
+            backpressure_stalls: metrics.backpressure_stalls.load(Ordering::Relaxed),
+            decrypt_queue_depth: dispatcher.decrypt_queue_depth() as u64,
+            reassembly_queue_depth: dispatcher.reassembly_queue_depth() as u64,
+            truncated_datagrams: sockets.all().map(|s| s.rejected().truncated()).sum(),
+            oversized_datagrams: sockets.all().map(|s| s.rejected().oversized()).sum(),
+            kernel_drops: sockets.all().filter_map(|s| s.kernel_drops()).reduce(|a, b| a + b),
+        }
+    }
+
+    fn apply(&self, stats: &mut AppStats) {
+        stats.packets_received = self.packets_received;
+        stats.bytes_received = self.bytes_received;
+        stats.noise_packets_received = self.noise_packets_received;
+        stats.decrypted_chunks = self.decrypted_chunks;
+        stats.decrypted_control_frames = self.decrypted_control_frames;
+        stats.decrypt_auth_failures = self.decrypt_auth_failures;
+        stats.decrypt_malformed_frames = self.decrypt_malformed_frames;
+        stats.malformed_messages = self.malformed_messages;
+        stats.reassembly_sessions = self.reassembly_sessions;
+        stats.reassembly_buffer_bytes = self.reassembly_buffer_bytes;
+        stats.receive_pool_bytes = self.receive_pool_bytes;
+        stats.backpressure_stalls = self.backpressure_stalls;
+        stats.decrypt_queue_depth = self.decrypt_queue_depth;
+        stats.reassembly_queue_depth = self.reassembly_queue_depth;
+        stats.truncated_datagrams = self.truncated_datagrams;
+        stats.oversized_datagrams = self.oversized_datagrams;
+        stats.kernel_drops = self.kernel_drops;
+    }
+}
+
+/// Раз в секунду переносит счётчики приёма в статистику и рассылает её,
+/// если что-то изменилось. Так приём не берёт блокировку состояния на каждый пакет.
+pub async fn run_stats_reporter(
+    dispatcher: Dispatcher,
+    sockets: Arc<UdpSockets>,
+    state: SharedState,
+    ws_tx: broadcast::Sender<WsNotification>,
+) {
+    let mut interval = tokio::time::interval(STATS_INTERVAL);
+    let mut reported = None;
+    let mut audited = ReceiveSample::default();
+    let mut audited_at = Instant::now();
+    loop {
+        interval.tick().await;
+        let sample = ReceiveSample::take(&dispatcher, &sockets);
+        if reported == Some(sample) {
+            continue;
+        }
+        reported = Some(sample);
+        let stats = {
+            let mut stats = state.stats();
+            sample.apply(&mut stats);
+            *stats
+        };
+        ws_tx.send(WsNotification::StatsUpdate(stats)).ok();
+        if audited_at.elapsed() >= AUDIT_FAILURES_INTERVAL {
+            let authentication = sample.decrypt_auth_failures - audited.decrypt_auth_failures;
+            let malformed_frames = sample.decrypt_malformed_frames - audited.decrypt_malformed_frames;
+            let malformed_messages = sample.malformed_messages - audited.malformed_messages;
+            if authentication + malformed_frames + malformed_messages > 0 {
+                let window_secs = audited_at.elapsed().as_secs();
+                state.lock().await.audit.record(AuditEvent::DecryptFailures { authentication, malformed_frames, malformed_messages, window_secs });
+            }
+            audited = sample;
+            audited_at = Instant::now();
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::protocol::{DEFAULT_PACKET_SIZE, PACKET_BUCKETS};
+use rand::Rng;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Сколько ждём ответа на зонд.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Сколько раз пробуем один размер, прежде чем решить, что он не проходит.
+const PROBE_ATTEMPTS: u32 = 3;
+/// Через сколько повторяем поиск: маршрут мог измениться.
+const REPROBE_INTERVAL: Duration = Duration::from_secs(600);
+/// Столько потерь подряд без единого подтверждения считаем признаком того, что PMTU
+/// уменьшился и пакеты текущего размера пропадают.
+const BLACKHOLE_LOSSES: usize = 6;
+
+struct ActiveProbe {
+    probe_id: u32,
+    size: usize,
+    sent_at: Instant,
+    attempts: u32,
+}
+
+/// Поиск PMTU до одного узла. Начинаем с самой маленькой корзины и поднимаемся,
+/// пока зонды доходят. Размер пакетов к узлу всегда равен одной из `PACKET_BUCKETS`.
+pub struct PathMtu {
+    packet_size: usize,
+    probe: Option<ActiveProbe>,
+    /// Когда снова начинать поиск; `None` — поиск ещё идёт.
+    next_search_at: Option<Instant>,
+    /// Потери с последнего подтверждения.
+    consecutive_losses: usize,
+}
+
+impl Default for PathMtu {
+    fn default() -> Self {
+        Self { packet_size: DEFAULT_PACKET_SIZE, probe: None, next_search_at: None, consecutive_losses: 0 }
+    }
+}
+
+impl PathMtu {
+    /// Размер, до которого добиваются все пакеты к этому узлу.
+    pub fn packet_size(&self) -> usize {
+        self.packet_size
+    }
+
+    fn next_bucket(&self) -> Option<usize> {
+        PACKET_BUCKETS.iter().copied().find(|&size| size > self.packet_size)
+    }
+
+    /// Решает, нужно ли сейчас отправить зонд, и если да — возвращает `(probe_id, size)`.
+    pub fn poll_probe(&mut self, now: Instant) -> Option<(u32, usize)> {
+        if let Some(probe) = &mut self.probe {
+            if now - probe.sent_at < PROBE_TIMEOUT {
+                return None;
+            }
+            if probe.attempts >= PROBE_ATTEMPTS {
+                // Размер не проходит: останавливаемся на текущем
+                self.probe = None;
+                self.next_search_at = Some(now + REPROBE_INTERVAL);
+                return None;
+            }
+            probe.attempts += 1;
+            probe.sent_at = now;
+            return Some((probe.probe_id, probe.size));
+        }
+
+        if self.next_search_at.is_some_and(|at| now < at) {
+            return None;
+        }
+        let Some(size) = self.next_bucket() else {
+            self.next_search_at = Some(now + REPROBE_INTERVAL);
+            return None;
+        };
+        let probe_id = rand::thread_rng().gen();
+        self.probe = Some(ActiveProbe { probe_id, size, sent_at: now, attempts: 1 });
+        self.next_search_at = None;
+        Some((probe_id, size))
+    }
+
+    /// Зонд не удалось даже отправить (например, EMSGSIZE от ядра): размер точно не проходит.
+    pub fn on_probe_rejected(&mut self, probe_id: u32, now: Instant) {
+        if self.probe.as_ref().is_some_and(|p| p.probe_id == probe_id) {
+            self.probe = None;
+            self.next_search_at = Some(now + REPROBE_INTERVAL);
+        }
+    }
+
+    /// Узел подтвердил пакеты: текущий размер проходит.
+    pub fn on_ack(&mut self) {
+        self.consecutive_losses = 0;
+    }
+
+    /// Пакеты к узлу потерялись. Если подтверждений давно нет, спускаемся на корзину ниже
+    /// и возвращаем новый размер; повторный поиск — не раньше `REPROBE_INTERVAL`.
+    pub fn on_loss(&mut self, count: usize, now: Instant) -> Option<usize> {
+        self.consecutive_losses += count;
+        if self.consecutive_losses < BLACKHOLE_LOSSES {
+            return None;
+        }
+        self.consecutive_losses = 0;
+        let lower = PACKET_BUCKETS.iter().rev().copied().find(|&size| size < self.packet_size)?;
+        self.packet_size = lower;
+        self.probe = None;
+        self.next_search_at = Some(now + REPROBE_INTERVAL);
+        Some(lower)
+    }
+
+    /// Пришёл ответ на зонд. Возвращает `true`, если размер пакетов вырос.
+    pub fn on_probe_ack(&mut self, probe_id: u32, size: usize) -> bool {
+        match &self.probe {
+            Some(probe) if probe.probe_id == probe_id && probe.size == size => {
+                self.probe = None;
+                if size > self.packet_size {
+                    self.packet_size = size;
+                    return true;
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+}
@@ -1,139 +1,403 @@
 use crate::state::{
-    FileContent, MessageContent, SharedState, WsNotification, DecryptedMessage, ObfuscationPattern};
-use crate::protocol;
+    FileContent, MessageContent, SharedState, WsNotification, DecryptedMessage, ObfuscationPattern,
+    MessageStatus, TransmitCommand, MessageDirection, FileStatus};
+use crate::audit::{key_fingerprint, AuditEvent};
+use crate::config::Config;
+use crate::files;
+use crate::pipeline::{DecryptedPacket, PipelineMetrics};
+use crate::protocol::{AsemicPacket, ControlFrame, Frame, MessageEnvelope};
+use crate::fec;
+use crate::conversation::{self, Author};
+use crate::compression;
+use crate::expiry;
+use crate::quarantine::{self, PolicyDecision};
+use crate::resume::{self, ResumeStore};
+use crate::rotation;
 use base64::{engine::general_purpose, Engine};
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{mpsc, broadcast};
-use tracing::{info, warn, error, debug};
+use tokio::time::Instant;
+use tracing::{info, warn, debug};
 use uuid::Uuid;
 
+/// Сколько чанков накапливаем перед отправкой подтверждения.
+const ACK_BATCH: usize = 8;
+/// Сколько собранных сообщений помним для распознавания повторов.
+const COMPLETED_SESSIONS_LIMIT: usize = 1024;
+/// Как часто можно повторять квитанцию о доставке уже собранного сообщения.
+const RECEIPT_RESEND_INTERVAL: Duration = Duration::from_millis(500);
+/// Чанки собранного сообщения, пришедшие позже этого, уже не опоздавшие повторы
+/// отправителя, а, скорее всего, переигранные перехваченные пакеты.
+const REPLAY_AFTER: Duration = Duration::from_secs(120);
+/// Паттерн служебных ответов: на пакет он не влияет, а у кадров, в отличие от сообщений, его нет.
+const REPLY_PATTERN: ObfuscationPattern = ObfuscationPattern::Starfall;
+
+/// Недособранные сообщения. Ими владеет только стадия сборки, поэтому чанки
+/// складываются без блокировки общего состояния.
+struct Reassembly {
+    buffers: HashMap<(SocketAddr, u32), HashMap<u32, Vec<u8>>>,
+    /// Сколько байт чанков лежит в `buffers`.
+    buffered_bytes: usize,
+    /// Недособранные файловые передачи, сохранённые на диск.
+    resume: ResumeStore,
+    metrics: Arc<PipelineMetrics>,
+}
+
+impl Reassembly {
+    fn add_chunks(&mut self, session: (SocketAddr, u32), chunks: impl IntoIterator<Item = (u32, Vec<u8>)>) -> &HashMap<u32, Vec<u8>> {
+        let session_chunks = self.buffers.entry(session).or_default();
+        for (chunk_num, data) in chunks {
+            self.buffered_bytes += data.len();
+            if let Some(replaced) = session_chunks.insert(chunk_num, data) {
+                self.buffered_bytes -= replaced.len();
+            }
+        }
+        self.metrics.set_reassembly(self.buffers.len(), self.buffered_bytes);
+        &self.buffers[&session]
+    }
+
+    fn remove(&mut self, session: &(SocketAddr, u32)) {
+        if let Some(chunks) = self.buffers.remove(session) {
+            self.buffered_bytes -= chunks.values().map(Vec::len).sum::<usize>();
+        }
+        self.metrics.set_reassembly(self.buffers.len(), self.buffered_bytes);
+    }
+}
+
+/// Недавно собранное сообщение.
+struct CompletedSession {
+    completed_at: Instant,
+    last_receipt: Instant,
+    /// Повтор уже записан в журнал аудита.
+    replay_recorded: bool,
+}
+
+/// Стадия сборки: получает расшифрованные пакеты от потоков расшифровки
+/// (см. `pipeline`), собирает сообщения и отвечает на служебные кадры.
 pub async fn packet_processor_task(
-    mut packet_receiver: mpsc::Receiver<(Vec<u8>, SocketAddr)>,
+    mut decrypted_receiver: mpsc::Receiver<DecryptedPacket>,
+    resume: ResumeStore,
     state: SharedState,
     ws_tx: broadcast::Sender<WsNotification>,
+    transmit_tx: mpsc::Sender<TransmitCommand>,
+    config: Arc<Config>,
+    metrics: Arc<PipelineMetrics>,
 ) {
     info!("Packet processor task started.");
-    // Паттерны, которые мы будем пробовать при дешифровке
-    let patterns_to_try = [ObfuscationPattern::Starfall, ObfuscationPattern::Sunshine];
-
-    while let Some((packet, sender)) = packet_receiver.recv().await {
-        let mut decrypted_successfully = false;
-        
-        // Блокируем состояние один раз перед циклом для получения ключей
-        let keys = {
-            let mut state_guard = state.lock().await;
-            state_guard.stats.packets_received += 1;
-            // Отправляем обновление статистики всем клиентам
-            ws_tx.send(WsNotification::StatsUpdate(state_guard.stats)).ok();
-            state_guard.keys.clone()
+    let mut reassembly = Reassembly { buffers: HashMap::new(), buffered_bytes: 0, resume, metrics: Arc::clone(&metrics) };
+    // Чанки, которые мы получили, но ещё не подтвердили отправителю
+    let mut pending_acks: HashMap<(SocketAddr, u32), Vec<u32>> = HashMap::new();
+    // Недавно собранные сообщения, чтобы не собирать повторно пришедшие чанки заново
+    let mut completed_sessions: HashMap<(SocketAddr, u32), CompletedSession> = HashMap::new();
+    let mut completed_order: VecDeque<(SocketAddr, u32)> = VecDeque::new();
+
+    while let Some(DecryptedPacket { frame, key, sender, packet_len }) = decrypted_receiver.recv().await {
+        let asemic_packet = match frame {
+            Frame::Chunk(asemic_packet) => asemic_packet,
+            Frame::Control(control) => {
+                handle_control_frame(control, sender, &key, packet_len, &mut reassembly, &state, &ws_tx, &transmit_tx).await;
+                continue;
+            }
+        };
+
+        // Декодируем данные чанка из Base64
+        let chunk_data = match general_purpose::STANDARD.decode(&asemic_packet.data) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Failed to decode Base64 chunk from {}: {}", sender, e);
+                metrics.record_malformed_message();
+                continue; // Пропускаем этот чанк, он поврежден
+            }
+        };
+
+        let session_key = (sender, asemic_packet.msg_id);
+
+        // Повтор чанка уже собранного сообщения: видимо, потерялась квитанция, шлём её снова.
+        // Опоздавшие ремонтные чанки приходят пачкой, поэтому не чаще RECEIPT_RESEND_INTERVAL.
+        if let Some(completed) = completed_sessions.get_mut(&session_key) {
+            debug!("Duplicate chunk {} of completed message {} from {}", asemic_packet.chunk_num, asemic_packet.msg_id, sender);
+            if !completed.replay_recorded && completed.completed_at.elapsed() >= REPLAY_AFTER {
+                warn!("Chunks of message {} from {} arrived {:?} after it was assembled", asemic_packet.msg_id, sender, completed.completed_at.elapsed());
+                completed.replay_recorded = true;
+                let replay = AuditEvent::ReplayDetected { peer: sender, msg_id: asemic_packet.msg_id, key: key_fingerprint(&key) };
+                state.lock().await.audit.record(replay);
+            }
+            if completed.last_receipt.elapsed() < RECEIPT_RESEND_INTERVAL {
+                continue;
+            }
+            completed.last_receipt = Instant::now();
+            let receipt = TransmitCommand::SendControl {
+                target_addr: sender,
+                key: key.clone(),
+                pattern: REPLY_PATTERN,
+                frame: ControlFrame::Receipt { msg_id: asemic_packet.msg_id, status: MessageStatus::Delivered },
+            };
+            transmit_tx.try_send(receipt).ok();
+            continue;
+        }
+
+        // Возобновляемая передача после перезапуска: подхватываем сохранённые чанки
+        if !reassembly.buffers.contains_key(&session_key) {
+            let saved = reassembly.resume.preload(session_key, &key).await;
+            if !saved.is_empty() {
+                reassembly.add_chunks(session_key, saved);
+            }
+        }
+        reassembly.resume.record_chunk(session_key, asemic_packet.chunk_num, chunk_data.clone(), &key);
+
+        // Получаем или создаем буфер для сборки сообщения
+        let session_chunks = reassembly.add_chunks(session_key, [(asemic_packet.chunk_num, chunk_data)]);
+
+        let assembled = assemble(session_chunks, &asemic_packet);
+
+        // Подтверждаем чанки пачками; собранное сообщение подтверждает квитанция о доставке
+        let unacked = pending_acks.entry(session_key).or_default();
+        unacked.push(asemic_packet.chunk_num);
+        if assembled.is_some() {
+            pending_acks.remove(&session_key);
+            reassembly.resume.complete(session_key);
+            let now = Instant::now();
+            completed_sessions.insert(session_key, CompletedSession { completed_at: now, last_receipt: now, replay_recorded: false });
+            completed_order.push_back(session_key);
+            if completed_order.len() > COMPLETED_SESSIONS_LIMIT {
+                if let Some(oldest) = completed_order.pop_front() {
+                    completed_sessions.remove(&oldest);
+                }
+            }
+        } else if unacked.len() >= ACK_BATCH {
+            // Подтверждаем только то, что переживёт перезапуск: подтверждение уйдёт после сохранения
+            let ack = TransmitCommand::SendControl {
+                target_addr: sender,
+                key: key.clone(),
+                pattern: REPLY_PATTERN,
+                frame: ControlFrame::Ack { msg_id: asemic_packet.msg_id, chunks: std::mem::take(unacked) },
+            };
+            reassembly.resume.flush(session_key, &transmit_tx, ack);
+        }
+
+        // Проверяем, все ли части сообщения получены
+        let Some(full_message_bytes) = assembled else { continue };
+        info!("Full message {} from {} assembled ({} chunks).", asemic_packet.msg_id, sender, asemic_packet.total_chunks);
+        // Удаляем сообщение из буфера после успешной сборки
+        reassembly.remove(&session_key);
+
+        // Распаковка может быть долгой, состояние на это время не блокируем
+        let limit = state.lock().await.file_policy.max_message_bytes();
+        let full_message_bytes = match compression::decompress(&full_message_bytes, asemic_packet.compression, limit) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                warn!("Failed to decompress message {} from {}: {}", asemic_packet.msg_id, sender, e);
+                metrics.record_malformed_message();
+                continue;
+            }
+        };
+
+        // --- КЛЮЧЕВАЯ ЛОГИКА ---
+        // Теперь, когда у нас есть полный набор байт, мы десериализуем его обратно в MessageContent.
+        let MessageEnvelope { content, ttl_secs, pattern } = match serde_json::from_slice::<MessageEnvelope>(&full_message_bytes) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                warn!("Failed to deserialize assembled message content from {}: {}. Raw bytes len: {}", sender, e, full_message_bytes.len());
+                metrics.record_malformed_message();
+                continue;
+            }
         };
+        let pattern = pattern.unwrap_or_default();
 
-        // Перебираем все известные ключи и паттерны, чтобы попытаться расшифровать пакет
-        'decryption_loop: for &pattern in &patterns_to_try {
-            for key in &keys {
-                if let Some(asemic_packet) = protocol::try_decrypt_packet(&packet, key.as_bytes(), pattern) {
-                    decrypted_successfully = true;
-                    debug!("Decrypted a packet from {} with key '{}' and pattern {:?}", sender, key, pattern);
-                    
-                    // Декодируем данные чанка из Base64
-                    let chunk_data = match general_purpose::STANDARD.decode(&asemic_packet.data) {
-                        Ok(data) => data,
-                        Err(e) => {
-                            warn!("Failed to decode Base64 chunk from {}: {}", sender, e);
-                            continue; // Пропускаем этот чанк, он поврежден
+        // Общее состояние блокируем только там, где оно нужно: история и статистика под своими блокировками
+        if let MessageContent::KeyRotation { new_key, activate_at, grace_secs } = content {
+            // Предложение пришло под ключом `key`, его и меняем, но только с согласия пользователя
+            let proposal = rotation::Proposal { peer: sender, msg_id: asemic_packet.msg_id, pattern };
+            match rotation::plan(&key, new_key, proposal, activate_at, grace_secs, false) {
+                Ok(rotation) => {
+                    rotation::receive_proposal(&mut *state.lock().await, &ws_tx, rotation);
+                }
+                Err(reason) => warn!("Ignoring key rotation from {}: {}", sender, reason),
+            }
+        } else if content.is_action() {
+            // Правки, удаления, реакции и «печатает» меняют уже показанные сообщения
+            let update = conversation::apply_action(&mut state.history(), Author::Peer(sender), &content);
+            if let Some(update) = update {
+                ws_tx.send(update).ok();
+            }
+        } else {
+            let expires_at = expiry::expires_at(ttl_secs);
+            // Обрабатываем контент: если это файл, сохраняем его
+            let final_content = match content {
+                MessageContent::File(file_content) => {
+                    // Имя от узла нельзя использовать как путь: очищаем сразу
+                    let filename = files::sanitize_filename(&file_content.filename);
+                    let size = file_content.data.len() as u64;
+                    let decision = quarantine::admit(&mut *state.lock().await, &config, &filename, size, sender);
+                    let (file_id, status) = match decision {
+                        PolicyDecision::Reject(reason) => {
+                            warn!("Dropping file '{}' from {}: {}", filename, sender, reason);
+                            state.lock().await.audit.record(AuditEvent::FileDropped { filename: filename.clone(), size, peer: sender, reason });
+                            (None, FileStatus::Rejected)
+                        }
+                        decision => {
+                            // Запись в карантин и хэширование — в отдельной задаче
+                            let id = Uuid::new_v4();
+                            let auto_accept = decision == PolicyDecision::AutoAccept;
+                            let (state, ws_tx, config) = (Arc::clone(&state), ws_tx.clone(), Arc::clone(&config));
+                            let filename = filename.clone();
+                            tokio::spawn(async move {
+                                quarantine::quarantine_file(state, ws_tx, &config, id, filename, sender, file_content.data, auto_accept, expires_at).await;
+                            });
+                            (Some(id), FileStatus::Quarantined)
                         }
                     };
 
-                    let mut state_guard = state.lock().await;
-                    let session_key = (sender, asemic_packet.msg_id);
-                    
-                    // Получаем или создаем буфер для сборки сообщения
-                    let session_chunks = state_guard.reassembly_buffer.entry(session_key).or_default();
-                    session_chunks.insert(asemic_packet.chunk_num, chunk_data);
-                    
-                    // Проверяем, все ли части сообщения получены
-                    if session_chunks.len() as u32 == asemic_packet.total_chunks {
-                        info!("Full message {} from {} assembled ({} chunks).", asemic_packet.msg_id, sender, asemic_packet.total_chunks);
-                        let mut full_message_bytes = Vec::new();
-                        for i in 0..asemic_packet.total_chunks {
-                            if let Some(chunk) = session_chunks.get(&i) {
-                                full_message_bytes.extend_from_slice(chunk);
-                            } else {
-                                warn!("Missing chunk #{} for message {}. Aborting assembly.", i, asemic_packet.msg_id);
-                                // Удаляем неполное сообщение из буфера
-                                state_guard.reassembly_buffer.remove(&session_key);
-                                break;
-                            }
-                        }
-                        // Удаляем сообщение из буфера после успешной сборки
-                        state_guard.reassembly_buffer.remove(&session_key);
-                        
-                        // --- КЛЮЧЕВАЯ ЛОГИКА ---
-                        // Теперь, когда у нас есть полный набор байт, мы десериализуем его обратно в MessageContent.
-                        match serde_json::from_slice::<MessageContent>(&full_message_bytes) {
-                            Ok(content) => {
-                                // Обрабатываем контент: если это файл, сохраняем его
-                                let final_content = match content {
-                                    MessageContent::File(file_content) => {
-                                        let file_id = Uuid::new_v4();
-                                        // Сохраняем файл в фоновой задаче, чтобы не блокировать обработку пакетов
-                                        let downloads_path = state_guard.downloads_path.clone();
-                                        let filename_for_task = file_content.filename.clone();
-                                        let file_data_for_task = file_content.data.clone();
-
-                                        tokio::spawn(async move {
-                                            let file_path = downloads_path.join(&filename_for_task);
-                                            info!("Saving received file to {:?}", &file_path);
-                                            if let Err(e) = tokio::fs::write(&file_path, &file_data_for_task).await {
-                                                error!("Failed to save file {:?}: {}", file_path, e);
-                                            }
-                                        });
-
-                                        // Для отображения в UI, мы не хотим отправлять все данные файла.
-                                        // Отправляем только информацию о нем.
-                                        let content_for_ui = MessageContent::File(FileContent {
-                                            filename: file_content.filename.clone(),
-                                            data: Vec::new(), // Очищаем данные для отправки в UI
-                                            id: Some(file_id),
-                                        });
-                                        // Сохраняем файл в памяти для возможности скачивания
-                                        state_guard.received_files.insert(file_id, (file_content.filename, file_content.data));
-                                        
-                                        content_for_ui
-                                    },
-                                    text_content => text_content,
-                                };
-                                
-                                let message = DecryptedMessage {
-                                    id: Uuid::new_v4(),
-                                    timestamp: chrono::Utc::now(),
-                                    sender,
-                                    content: final_content,
-                                    decrypted_with_key: key.clone(),
-                                    decrypted_with_pattern: pattern,
-                                };
-                                
-                                state_guard.messages.push(message.clone());
-                                state_guard.stats.messages_decrypted += 1;
-                                // Уведомляем UI о новом сообщении и обновлении статистики
-                                ws_tx.send(WsNotification::NewMessage(message)).ok();
-                                ws_tx.send(WsNotification::StatsUpdate(state_guard.stats)).ok();
-
-                            },
-                            Err(e) => {
-                                warn!("Failed to deserialize assembled message content from {}: {}. Raw bytes len: {}", sender, e, full_message_bytes.len());
-                            }
+                    // Для отображения в UI, мы не хотим отправлять все данные файла.
+                    // Отправляем только информацию о нем.
+                    MessageContent::File(FileContent {
+                        filename,
+                        data: Vec::new(), // Очищаем данные для отправки в UI
+                        id: file_id,
+                        status: Some(status),
+                    })
+                },
+                text_content => text_content,
+            };
+
+            let message = DecryptedMessage {
+                id: Uuid::new_v4(),
+                direction: MessageDirection::Incoming,
+                msg_id: asemic_packet.msg_id,
+                timestamp: chrono::Utc::now(),
+                sender,
+                content: final_content,
+                decrypted_with_key: key.clone(),
+                decrypted_with_pattern: pattern,
+                read: false,
+                edited: false,
+                deleted: false,
+                reactions: Vec::new(),
+                expires_at,
+            };
+
+            state.history().messages.push(message.clone());
+            let stats = {
+                let mut stats = state.stats();
+                stats.messages_decrypted += 1;
+                *stats
+            };
+            // Уведомляем UI о новом сообщении и обновлении статистики
+            ws_tx.send(WsNotification::NewMessage(message)).ok();
+            ws_tx.send(WsNotification::StatsUpdate(stats)).ok();
+        }
+
+        // Сообщаем отправителю, что сообщение собрано целиком.
+        // try_send: сборка не должна ждать передатчик.
+        let receipt = TransmitCommand::SendControl {
+            target_addr: sender,
+            key,
+            pattern: REPLY_PATTERN,
+            frame: ControlFrame::Receipt { msg_id: asemic_packet.msg_id, status: MessageStatus::Delivered },
+        };
+        if let Err(e) = transmit_tx.try_send(receipt) {
+            warn!("Failed to queue delivery receipt for message {}: {}", asemic_packet.msg_id, e);
+        }
+    }
+}
+
+/// Собирает сообщение, как только чанков достаточно (с FEC — вместе с ремонтными).
+pub fn assemble(session_chunks: &HashMap<u32, Vec<u8>>, packet: &AsemicPacket) -> Option<Vec<u8>> {
+    match &packet.fec {
+        Some(params) => fec::try_reconstruct(session_chunks, packet.total_chunks, params),
+        None if session_chunks.len() as u32 == packet.total_chunks => (0..packet.total_chunks)
+            .map(|i| session_chunks.get(&i).map(Vec::as_slice))
+            .collect::<Option<Vec<_>>>()
+            .map(|chunks| chunks.concat()),
+        None => None,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_control_frame(
+    control: ControlFrame,
+    sender: SocketAddr,
+    key: &str,
+    packet_len: usize,
+    reassembly: &mut Reassembly,
+    state: &SharedState,
+    ws_tx: &broadcast::Sender<WsNotification>,
+    transmit_tx: &mpsc::Sender<TransmitCommand>,
+) {
+    match control {
+        ControlFrame::Probe { probe_id, size } => {
+            // Отвечаем, только если зонд дошёл целиком и без обрезки
+            if packet_len != size {
+                warn!("Probe from {} claims {} bytes but is {} bytes long", sender, size, packet_len);
+                return;
+            }
+            let reply = TransmitCommand::SendControl {
+                target_addr: sender,
+                key: key.to_string(),
+                pattern: REPLY_PATTERN,
+                frame: ControlFrame::ProbeAck { probe_id, size },
+            };
+            transmit_tx.try_send(reply).ok();
+        }
+        ControlFrame::ProbeAck { probe_id, size } => {
+            transmit_tx.try_send(TransmitCommand::ProbeAck { peer: sender, probe_id, size }).ok();
+        }
+        ControlFrame::ResumeOffer { msg_id, transfer_id, chunk_size, data_len } => {
+            let max_data_len = state.lock().await.file_policy.max_message_bytes();
+            let have = if !resume::is_valid_transfer_id(&transfer_id) || chunk_size == 0 {
+                warn!("Ignoring malformed resume offer for message {} from {}", msg_id, sender);
+                return;
+            } else if data_len > max_data_len {
+                // Файл всё равно будет отброшен правилами, не храним его части
+                Vec::new()
+            } else {
+                match reassembly.resume.offer(sender, msg_id, &transfer_id, chunk_size as usize, data_len, key).await {
+                    Ok(outcome) => {
+                        if let Some(previous) = outcome.previous_session {
+                            reassembly.remove(&previous);
                         }
+                        reassembly.add_chunks((sender, msg_id), outcome.preloaded);
+                        outcome.have
+                    }
+                    Err(e) => {
+                        warn!("Failed to prepare resumable transfer {}: {}", transfer_id, e);
+                        Vec::new()
                     }
-                    // Если пакет успешно расшифрован, прекращаем перебор ключей
-                    break 'decryption_loop;
                 }
-            }
+            };
+            let reply = TransmitCommand::SendControl {
+                target_addr: sender,
+                key: key.to_string(),
+                pattern: REPLY_PATTERN,
+                frame: ControlFrame::ResumeState { msg_id, have },
+            };
+            transmit_tx.try_send(reply).ok();
+        }
+        ControlFrame::ResumeState { msg_id, have } => {
+            transmit_tx.try_send(TransmitCommand::ResumeState { peer: sender, msg_id, have }).ok();
+        }
+        ControlFrame::Ack { msg_id, chunks } => {
+            let ack = TransmitCommand::PeerAck { peer: sender, msg_id, chunks: Some(chunks) };
+            transmit_tx.try_send(ack).ok();
         }
-        // Если ни один ключ/паттерн не подошел, считаем пакет шумом
-        if !decrypted_successfully {
-            debug!("Received a noise packet of size {} from {}", packet.len(), sender);
-            ws_tx.send(WsNotification::NoisePacket { sender, size: packet.len() }).ok();
+        ControlFrame::Receipt { msg_id, status } => {
+            // Квитанция может подтверждать только доставку или прочтение.
+            if !matches!(status, MessageStatus::Delivered | MessageStatus::Read) {
+                warn!("Ignoring receipt with unexpected status {:?} from {}", status, sender);
+                return;
+            }
+            // Квитанция означает, что сообщение собрано целиком: передатчику больше нечего повторять
+            let ack = TransmitCommand::PeerAck { peer: sender, msg_id, chunks: None };
+            transmit_tx.try_send(ack).ok();
+
+            let advanced = state.history().advance_outgoing_status(sender, msg_id, status);
+            if advanced {
+                debug!("Message {} is now {:?} (receipt from {})", msg_id, status, sender);
+                ws_tx.send(WsNotification::MessageStatus { target: sender, msg_id, status }).ok();
+            }
         }
     }
-}
\ No newline at end of file
+}
@@ -1,17 +1,37 @@
-use crate::state::ObfuscationPattern;
+use crate::state::{Compression, MessageContent, MessageStatus, ObfuscationPattern};
 use chacha20poly1305::{
-    aead::{Aead, KeyInit, Payload},
+    aead::{Aead, KeyInit},
     XChaCha20Poly1305, XNonce
 };
-use rand::{Rng, RngCore};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
-// Оптимальный размер пакета, чтобы не фрагментировался роутерами (MTU)
-// Оставляем запас под UDP заголовок и Nonce.
-pub const MAX_PACKET_SIZE: usize = 1350; 
-// Размер чанка данных для network.rs (оставляем как было или чуть уменьшаем)
-pub const CHUNK_SIZE: usize = 1200; 
+type HmacSha256 = Hmac<Sha256>;
+
+// Допустимые размеры UDP-датаграмм. Все пакеты к одному узлу добиваются до одного размера,
+// а какой из них выбрать, решает PMTU-зондирование (см. pmtu.rs).
+// Первый размер проходит почти везде: минимальный MTU IPv6 (1280) минус заголовки.
+pub const PACKET_BUCKETS: [usize; 5] = [1200, 1280, 1350, 1420, 1472];
+pub const DEFAULT_PACKET_SIZE: usize = PACKET_BUCKETS[0];
+/// Датаграммы длиннее не могут быть нашими пакетами.
+pub const MAX_PACKET_SIZE: usize = PACKET_BUCKETS[PACKET_BUCKETS.len() - 1];
+const NONCE_LEN: usize = 24;
+/// Подсказка ключа, см. `PacketKey`.
+const HINT_LEN: usize = 4;
+const HINT_KEY_CONTEXT: &[u8] = b"asemic key hint v1:";
+// Nonce + подсказка + тег Poly1305
+const PACKET_OVERHEAD: usize = NONCE_LEN + HINT_LEN + 16;
+// Префикс длины и JSON-обёртка AsemicPacket вокруг Base64-данных: худший случай,
+// когда все числовые поля максимальны и заданы и FEC, и сжатие
+const CHUNK_FRAME_OVERHEAD: usize = 4 + 212;
+
+/// Сколько байт данных помещается в один чанк, если пакет должен занимать `packet_size` байт.
+pub fn chunk_size_for(packet_size: usize) -> usize {
+    let base64_budget = packet_size.saturating_sub(PACKET_OVERHEAD + CHUNK_FRAME_OVERHEAD);
+    (base64_budget / 4) * 3
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AsemicPacket {
@@ -19,6 +39,73 @@ pub struct AsemicPacket {
     pub chunk_num: u32,
     pub total_chunks: u32,
     pub data: String, // Base64-кодированный чанк данных
+    /// Параметры помехоустойчивого кодирования, если отправитель добавил ремонтные чанки.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fec: Option<FecParams>,
+    /// Чем сжато собранное сообщение (флаг одинаков во всех чанках).
+    #[serde(default, skip_serializing_if = "Compression::is_off")]
+    pub compression: Compression,
+}
+
+/// Собранное из чанков сообщение. Поля содержимого лежат на верхнем уровне,
+/// поэтому сообщение без TTL совпадает со старым форматом.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MessageEnvelope {
+    #[serde(flatten)]
+    pub content: MessageContent,
+    /// Через сколько секунд после получения сообщение нужно стереть.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u32>,
+    /// Паттерн, выбранный отправителем; в самих пакетах его нет.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<ObfuscationPattern>,
+}
+
+/// Параметры Reed-Solomon для сообщения. Чанки с номерами `0..total_chunks` — исходные данные,
+/// дальше идут ремонтные: по `parity_for_block` на каждый блок из `block_size` чанков.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct FecParams {
+    pub block_size: u32,
+    pub parity: u32,
+    pub chunk_size: u32,
+    pub data_len: u64,
+}
+
+/// Служебные кадры, которые передаются в одном пакете без разбиения на чанки.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "ctrl")]
+pub enum ControlFrame {
+    /// Квитанция о доставке/прочтении сообщения `msg_id`.
+    Receipt { msg_id: u32, status: MessageStatus },
+    /// Подтверждение полученных чанков, по нему отправитель меряет RTT и потери.
+    Ack { msg_id: u32, chunks: Vec<u32> },
+    /// Зонд PMTU: сам пакет добит ровно до `size` байт.
+    Probe { probe_id: u32, size: usize },
+    /// Ответ на зонд: пакет размера `size` дошёл.
+    ProbeAck { probe_id: u32, size: usize },
+    /// Отправитель спрашивает, какие чанки передачи `transfer_id` (SHA-256 содержимого)
+    /// получатель уже хранит с прошлых попыток. Чанки сообщения ждут ответа.
+    ResumeOffer { msg_id: u32, transfer_id: String, chunk_size: u32, data_len: u64 },
+    /// Ответ: полуинтервалы `[start, end)` уже полученных чанков, их можно не слать.
+    ResumeState { msg_id: u32, have: Vec<(u32, u32)> },
+}
+
+/// Всё, что может лежать внутри расшифрованного пакета.
+/// `untagged`, чтобы чанки оставались совместимыми со старым форматом.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Frame {
+    Chunk(AsemicPacket),
+    Control(ControlFrame),
+}
+
+/// Сериализует кадр в открытый текст вида [4 bytes Length][JSON], готовый для `create_packet`.
+pub fn encode_frame(frame: &Frame) -> Vec<u8> {
+    let json_payload = serde_json::to_vec(frame).expect("frame serialization cannot fail");
+    let mut plaintext_payload = Vec::with_capacity(4 + json_payload.len());
+    plaintext_payload.extend_from_slice(&(json_payload.len() as u32).to_be_bytes());
+    plaintext_payload.extend_from_slice(&json_payload);
+    plaintext_payload
 }
 
 /// Создает 32-байтовый ключ из любой строки пользователя используя SHA-256
@@ -28,84 +115,130 @@ fn derive_key(input: &[u8]) -> [u8; 32] {
     hasher.finalize().into()
 }
 
-pub fn create_packet(payload: Vec<u8>, key: &[u8], _pattern: ObfuscationPattern) -> Vec<u8> {
-    let mut rng = rand::thread_rng();
-
-    // 1. Подготовка ключа
-    let key_bytes = derive_key(key);
-    let cipher = XChaCha20Poly1305::new(&key_bytes.into());
-
-    // 2. Генерация Nonce (24 байта случайности)
-    // Это делает каждый пакет уникальным, даже если данные те же.
-    let mut nonce = XNonce::default();
-    rng.fill_bytes(&mut nonce);
-
-    // 3. Добавление внутреннего паддинга (маскировка размера)
-    // Мы добавляем мусор К самим данным ПЕРЕД шифрованием.
-    // Так как у нас есть длина JSON в начале payload (от network.rs),
-    // при расшифровке мы просто отбросим этот хвост.
-    let mut payload_to_encrypt = payload;
-    let current_len = payload_to_encrypt.len() + 24 + 16; // payload + nonce + mac tag
-    
-    // Если пакет меньше максимума, добиваем мусором до случайной длины
-    if current_len < MAX_PACKET_SIZE {
-        let padding_needed = rng.gen_range(0..=(MAX_PACKET_SIZE - current_len));
-        let mut padding = vec![0u8; padding_needed];
+/// Ключ для отдельной роли: SHA-256 от контекста и ключа пользователя. Разные контексты
+/// дают независимые ключи, и ни один из них не совпадает с ключом AEAD.
+pub fn derive_subkey(context: &[u8], key: &[u8]) -> [u8; 32] {
+    derive_key(&[context, key].concat())
+}
+
+/// Почему пакет не открылся.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpenError {
+    /// Не тот ключ или повреждённый пакет: тег AEAD не сошёлся.
+    Authentication,
+    /// Расшифровано, но внутри не кадр нашего протокола.
+    Malformed,
+}
+
+/// Ключ, подготовленный для шифрования и разбора пакетов: производные ключи считаются один раз.
+///
+/// За nonce в пакете идёт подсказка — первые `HINT_LEN` байт HMAC от nonce на отдельном
+/// ключе. Получатель сверяет её с каждым своим ключом (пара сжатий SHA-256) и делает
+/// полную расшифровку только для совпавшего. Для наблюдателя без ключа подсказка —
+/// такие же случайные байты, как nonce, и от пакета к пакету не повторяется.
+pub struct PacketKey {
+    cipher: XChaCha20Poly1305,
+    hint: HmacSha256,
+}
+
+impl PacketKey {
+    pub fn new(key: &[u8]) -> Self {
+        let cipher = XChaCha20Poly1305::new(&derive_key(key).into());
+        // Ключ подсказки выводим отдельно, чтобы не использовать ключ AEAD в двух ролях
+        let hint_key = derive_subkey(HINT_KEY_CONTEXT, key);
+        let hint = <HmacSha256 as Mac>::new_from_slice(&hint_key).expect("HMAC accepts keys of any length");
+        Self { cipher, hint }
+    }
+
+    fn hint_for(&self, nonce: &[u8]) -> [u8; HINT_LEN] {
+        let mut mac = self.hint.clone();
+        mac.update(nonce);
+        let tag = mac.finalize().into_bytes();
+        let mut hint = [0u8; HINT_LEN];
+        hint.copy_from_slice(&tag[..HINT_LEN]);
+        hint
+    }
+
+    /// Подходит ли пакет к этому ключу. Проверяет только подсказку: совпадение
+    /// у чужого ключа возможно с вероятностью 2^-32, окончательно решает `open`.
+    pub fn matches(&self, packet: &[u8]) -> bool {
+        packet.len() > PACKET_OVERHEAD && self.hint_for(&packet[..NONCE_LEN]) == packet[NONCE_LEN..NONCE_LEN + HINT_LEN]
+    }
+
+    /// Шифрует `payload` в пакет длиной ровно `packet_size` байт.
+    /// Возвращает пустой вектор, если данные в этот размер не помещаются.
+    pub fn seal(&self, payload: Vec<u8>, packet_size: usize) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+
+        // 1. Генерация Nonce (24 байта случайности)
+        // Это делает каждый пакет уникальным, даже если данные те же.
+        let mut nonce = XNonce::default();
+        rng.fill_bytes(&mut nonce);
+
+        // 2. Добавление внутреннего паддинга (маскировка размера)
+        // Мы добавляем мусор К самим данным ПЕРЕД шифрованием.
+        // Так как у нас есть длина JSON в начале payload (от network.rs),
+        // при расшифровке мы просто отбросим этот хвост.
+        let mut payload_to_encrypt = payload;
+        let current_len = payload_to_encrypt.len() + PACKET_OVERHEAD; // payload + nonce + hint + mac tag
+        if current_len > packet_size {
+            return Vec::new();
+        }
+
+        // Добиваем мусором ровно до размера корзины: все пакеты к узлу одинаковой длины
+        let mut padding = vec![0u8; packet_size - current_len];
         rng.fill_bytes(&mut padding);
         payload_to_encrypt.extend_from_slice(&padding);
+
+        // 3. Шифрование
+        // Encrypt возвращает: [EncryptedData + AuthTag]
+        let ciphertext = match self.cipher.encrypt(&nonce, payload_to_encrypt.as_ref()) {
+            Ok(ct) => ct,
+            Err(_) => return Vec::new(), // Ошибка шифрования
+        };
+
+        // 4. Сборка финального пакета: [NONCE] + [HINT] + [CIPHERTEXT]
+        // Для внешнего наблюдателя это выглядит как сплошной рандом.
+        let mut final_packet = Vec::with_capacity(NONCE_LEN + HINT_LEN + ciphertext.len());
+        final_packet.extend_from_slice(&nonce);
+        final_packet.extend_from_slice(&self.hint_for(&nonce));
+        final_packet.extend_from_slice(&ciphertext);
+
+        final_packet
     }
 
-    // 4. Шифрование
-    // Encrypt возвращает: [EncryptedData + AuthTag]
-    let ciphertext = match cipher.encrypt(&nonce, payload_to_encrypt.as_ref()) {
-        Ok(ct) => ct,
-        Err(_) => return Vec::new(), // Ошибка шифрования
-    };
-
-    // 5. Сборка финального пакета: [NONCE] + [CIPHERTEXT]
-    // Для внешнего наблюдателя это выглядит как сплошной рандом.
-    let mut final_packet = Vec::with_capacity(24 + ciphertext.len());
-    final_packet.extend_from_slice(&nonce);
-    final_packet.extend_from_slice(&ciphertext);
-    
-    final_packet
-}
+    /// Расшифровывает пакет и разбирает кадр. Подсказку не проверяет.
+    pub fn open(&self, packet: &[u8]) -> Option<Frame> {
+        self.try_open(packet).ok()
+    }
 
-pub fn try_decrypt_packet(packet: &[u8], key: &[u8], _pattern: ObfuscationPattern) -> Option<AsemicPacket> {
-    // Пакет должен быть хотя бы длиннее Nonce (24 байта) + Tag (16 байт)
-    if packet.len() <= 40 { return None; }
-
-    // 1. Подготовка ключа
-    let key_bytes = derive_key(key);
-    let cipher = XChaCha20Poly1305::new(&key_bytes.into());
-
-    // 2. Разбор пакета
-    let nonce = XNonce::from_slice(&packet[0..24]);
-    let ciphertext = &packet[24..];
-
-    // 3. Попытка расшифровки
-    // Если ключ не тот, или пакет битый, или это просто шум интернета -> вернет Err
-    match cipher.decrypt(nonce, ciphertext) {
-        Ok(decrypted_data) => {
-            // Данные расшифрованы! Теперь нужно отделить полезную нагрузку от паддинга.
-            // Структура payload из network.rs: [4 bytes Length][JSON][Padding...]
-            
-            if decrypted_data.len() < 4 { return None; }
-            
-            // Читаем длину полезного JSON блока
-            if let Ok(len_bytes) = decrypted_data[0..4].try_into() {
-                let json_len = u32::from_be_bytes(len_bytes) as usize;
-                
-                // Проверяем, что длина адекватна
-                if decrypted_data.len() < 4 + json_len { return None; }
-                
-                // Вырезаем чистый JSON, игнорируя хвост с мусором
-                let json_slice = &decrypted_data[4..4+json_len];
-                
-                return serde_json::from_slice(json_slice).ok();
-            }
-            None
-        }
-        Err(_) => None, // Не удалось расшифровать (чужой пакет или шум)
+    /// Как `open`, но различает причину неудачи (для статистики).
+    pub fn try_open(&self, packet: &[u8]) -> Result<Frame, OpenError> {
+        // Пакет должен быть длиннее Nonce + подсказки + Tag
+        if packet.len() <= PACKET_OVERHEAD { return Err(OpenError::Authentication); }
+
+        // 1. Разбор пакета
+        let mut nonce = XNonce::default();
+        nonce.copy_from_slice(&packet[..NONCE_LEN]);
+        let ciphertext = &packet[NONCE_LEN + HINT_LEN..];
+
+        // 2. Попытка расшифровки
+        // Если ключ не тот, или пакет битый, или это просто шум интернета -> вернет Err
+        let decrypted_data = self.cipher.decrypt(&nonce, ciphertext).map_err(|_| OpenError::Authentication)?;
+
+        // Данные расшифрованы! Теперь нужно отделить полезную нагрузку от паддинга.
+        // Структура payload из network.rs: [4 bytes Length][JSON][Padding...]
+        let len_bytes: [u8; 4] = decrypted_data.get(0..4).and_then(|b| b.try_into().ok()).ok_or(OpenError::Malformed)?;
+        let json_len = u32::from_be_bytes(len_bytes) as usize;
+
+        // Вырезаем чистый JSON, игнорируя хвост с мусором
+        let json_slice = decrypted_data.get(4..4 + json_len).ok_or(OpenError::Malformed)?;
+        serde_json::from_slice(json_slice).map_err(|_| OpenError::Malformed)
     }
-}
\ No newline at end of file
+}
+
+/// Шифрует `payload` в пакет длиной ровно `packet_size` байт.
+/// Возвращает пустой вектор, если данные в этот размер не помещаются.
+pub fn create_packet(payload: Vec<u8>, key: &[u8], _pattern: ObfuscationPattern, packet_size: usize) -> Vec<u8> {
+    PacketKey::new(key).seal(payload, packet_size)
+}
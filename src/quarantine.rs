@@ -0,0 +1,259 @@
+use crate::audit::AuditEvent;
+use crate::config::Config;
+use crate::files;
+use crate::library;
+use crate::state::{
+    AppState, FilePolicy, FileStatus, History, MessageContent, PendingFile, PendingFileInfo, QuarantineUsage,
+    ReceivedFile, SharedState, WsNotification,
+};
+use chrono::{DateTime, Utc};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Что делать с только что собранным файлом.
+#[derive(Debug, PartialEq)]
+pub enum PolicyDecision {
+    /// Отбросить, не касаясь диска.
+    Reject(String),
+    /// Положить в карантин и спросить пользователя.
+    Quarantine,
+    /// Положить в карантин и сразу принять.
+    AutoAccept,
+}
+
+impl FilePolicy {
+    /// Верхняя граница собранного сообщения с файлом: Base64 и JSON-обёртка
+    /// раздувают файл примерно на треть, берём с запасом.
+    pub fn max_message_bytes(&self) -> u64 {
+        self.max_file_bytes.saturating_mul(2)
+    }
+
+    pub fn evaluate(&self, filename: &str, size: u64, sender: SocketAddr) -> PolicyDecision {
+        if size > self.max_file_bytes {
+            return PolicyDecision::Reject(format!("{} bytes exceeds the {} byte limit", size, self.max_file_bytes));
+        }
+        let extension = files::extension(filename).unwrap_or_default();
+        if self.denied_extensions.iter().any(|e| e.eq_ignore_ascii_case(&extension)) {
+            return PolicyDecision::Reject(format!("extension '{}' is denied", extension));
+        }
+        if !self.allowed_extensions.is_empty()
+            && !self.allowed_extensions.iter().any(|e| e.eq_ignore_ascii_case(&extension))
+        {
+            return PolicyDecision::Reject(format!("extension '{}' is not in the allow list", extension));
+        }
+        if self.auto_accept_from.contains(&sender.ip()) && size <= self.auto_accept_max_bytes {
+            return PolicyDecision::AutoAccept;
+        }
+        PolicyDecision::Quarantine
+    }
+}
+
+impl QuarantineUsage {
+    /// Резервирует место под файл `size` байт от `sender`, если не превышен ни общий объём карантина,
+    /// ни число файлов от этого узла. Иначе возвращает причину отказа.
+    fn reserve(&mut self, sender: IpAddr, size: u64, config: &Config) -> Result<(), String> {
+        if self.bytes + size > config.quarantine_quota_bytes {
+            return Err(format!("quarantine is full ({} of {} bytes used)", self.bytes, config.quarantine_quota_bytes));
+        }
+        let files = self.per_sender.entry(sender).or_default();
+        if *files >= config.max_pending_files_per_sender {
+            return Err(format!("{} files from this peer are already awaiting a decision", files));
+        }
+        *files += 1;
+        self.bytes += size;
+        Ok(())
+    }
+
+    /// Файл покинул карантин: принят, отклонён, истёк или не записался.
+    pub fn release(&mut self, sender: IpAddr, size: u64) {
+        self.bytes = self.bytes.saturating_sub(size);
+        if let Some(files) = self.per_sender.get_mut(&sender) {
+            *files = files.saturating_sub(1);
+            if *files == 0 {
+                self.per_sender.remove(&sender);
+            }
+        }
+    }
+}
+
+/// Решает, что делать с собранным файлом, и сразу резервирует под него место в карантине:
+/// проверка и резерв идут под одной блокировкой, иначе одновременные файлы прошли бы её вместе.
+pub fn admit(state: &mut AppState, config: &Config, filename: &str, size: u64, sender: SocketAddr) -> PolicyDecision {
+    match state.file_policy.evaluate(filename, size, sender) {
+        PolicyDecision::Reject(reason) => PolicyDecision::Reject(reason),
+        decision => match state.quarantine_usage.reserve(sender.ip(), size, config) {
+            Ok(()) => decision,
+            Err(reason) => PolicyDecision::Reject(reason),
+        },
+    }
+}
+
+#[derive(Debug)]
+pub enum AcceptError {
+    NotFound,
+    QuotaExceeded,
+    Io(io::Error),
+}
+
+impl From<io::Error> for AcceptError {
+    fn from(e: io::Error) -> Self {
+        AcceptError::Io(e)
+    }
+}
+
+/// Обновляет статус файла в истории сообщений, чтобы он пережил перезагрузку UI.
+pub fn set_file_status(history: &mut History, id: Uuid, status: FileStatus) {
+    for message in history.messages.iter_mut() {
+        if let MessageContent::File(file) = &mut message.content {
+            if file.id == Some(id) {
+                file.status = Some(status);
+            }
+        }
+    }
+}
+
+/// Кладёт файл в карантин под именем `id` и предлагает его пользователю.
+/// Вызывается из фоновой задачи, чтобы запись на диск не задерживала обработку пакетов.
+/// Место под файл уже зарезервировано в `admit`.
+#[allow(clippy::too_many_arguments)]
+pub async fn quarantine_file(
+    state: SharedState,
+    ws_tx: broadcast::Sender<WsNotification>,
+    config: &Config,
+    id: Uuid,
+    filename: String,
+    sender: SocketAddr,
+    data: Vec<u8>,
+    auto_accept: bool,
+    expires_at: Option<DateTime<Utc>>,
+) {
+    let info = PendingFileInfo {
+        id,
+        filename,
+        size: data.len() as u64,
+        sha256: files::sha256_hex(&data),
+        sender,
+        received_at: Utc::now(),
+        expires_at,
+    };
+    let path = state.lock().await.quarantine_path.join(id.to_string());
+    if let Err(e) = tokio::fs::write(&path, &data).await {
+        warn!("Failed to quarantine file '{}' from {}: {}", info.filename, sender, e);
+        state.lock().await.quarantine_usage.release(sender.ip(), info.size);
+        set_file_status(&mut state.history(), id, FileStatus::Rejected);
+        ws_tx.send(WsNotification::FileResolved { id, status: FileStatus::Rejected, filename: None }).ok();
+        return;
+    }
+    info!("File '{}' ({} bytes, sha256 {}) from {} quarantined", info.filename, info.size, info.sha256, sender);
+
+    let mut state_guard = state.lock().await;
+    state_guard.audit.record(AuditEvent::FileReceived {
+        file_id: id,
+        filename: info.filename.clone(),
+        size: info.size,
+        sha256: info.sha256.clone(),
+        peer: sender,
+    });
+    state_guard.pending_files.insert(id, PendingFile { info: info.clone(), path });
+    drop(state_guard);
+    ws_tx.send(WsNotification::FileOffer(info)).ok();
+
+    if auto_accept {
+        if let Err(e) = accept_file(&state, &ws_tx, config, id).await {
+            warn!("Auto-accept of file {} from {} failed: {:?}; leaving it in quarantine", id, sender, e);
+        }
+    }
+}
+
+/// Переносит файл из карантина в каталог загрузок. Возвращает итоговое имя.
+pub async fn accept_file(
+    state: &SharedState,
+    ws_tx: &broadcast::Sender<WsNotification>,
+    config: &Config,
+    id: Uuid,
+) -> Result<String, AcceptError> {
+    let (pending, downloads_path) = {
+        let mut state_guard = state.lock().await;
+        let pending = state_guard.pending_files.remove(&id).ok_or(AcceptError::NotFound)?;
+        if state_guard.downloads_used + pending.info.size > config.downloads_quota_bytes {
+            state_guard.pending_files.insert(id, pending);
+            return Err(AcceptError::QuotaExceeded);
+        }
+        state_guard.downloads_used += pending.info.size;
+        (pending, state_guard.downloads_path.clone())
+    };
+
+    match files::move_unique(&pending.path, &downloads_path, &pending.info.filename).await {
+        Ok(path) => {
+            let stored_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            info!("Accepted file '{}' from {}, saved as '{}'", pending.info.filename, pending.info.sender, stored_name);
+            let stored = ReceivedFile {
+                id,
+                mime: library::mime_type(&stored_name),
+                filename: stored_name.clone(),
+                size: pending.info.size,
+                sha256: pending.info.sha256,
+                sender: pending.info.sender,
+                received_at: pending.info.received_at,
+                expires_at: pending.info.expires_at,
+            };
+            let mut state_guard = state.lock().await;
+            state_guard.quarantine_usage.release(pending.info.sender.ip(), pending.info.size);
+            state_guard.audit.record(AuditEvent::FileSaved { file_id: id, stored_as: stored_name.clone(), sha256: stored.sha256.clone() });
+            state_guard.received_files.insert(id, stored.clone());
+            if let Err(e) = library::save(&state_guard).await {
+                warn!("Failed to update library index: {}", e);
+            }
+            set_file_status(&mut state.history(), id, FileStatus::Accepted);
+            ws_tx.send(WsNotification::FileResolved { id, status: FileStatus::Accepted, filename: Some(stored_name.clone()) }).ok();
+            ws_tx.send(WsNotification::FileStored(stored)).ok();
+            Ok(stored_name)
+        }
+        Err(e) => {
+            // Оставляем файл в карантине: пользователь может освободить место и повторить
+            let mut state_guard = state.lock().await;
+            state_guard.downloads_used = state_guard.downloads_used.saturating_sub(pending.info.size);
+            state_guard.pending_files.insert(id, pending);
+            Err(e.into())
+        }
+    }
+}
+
+/// Удаляет файл из карантина.
+pub async fn reject_file(
+    state: &SharedState,
+    ws_tx: &broadcast::Sender<WsNotification>,
+    id: Uuid,
+) -> Result<(), AcceptError> {
+    let pending = {
+        let mut state_guard = state.lock().await;
+        let pending = state_guard.pending_files.remove(&id).ok_or(AcceptError::NotFound)?;
+        state_guard.quarantine_usage.release(pending.info.sender.ip(), pending.info.size);
+        set_file_status(&mut state.history(), id, FileStatus::Rejected);
+        state_guard.audit.record(AuditEvent::FileRejected { file_id: id });
+        pending
+    };
+    info!("Rejected file '{}' from {}", pending.info.filename, pending.info.sender);
+    if let Err(e) = tokio::fs::remove_file(&pending.path).await {
+        warn!("Failed to remove quarantined file {:?}: {}", pending.path, e);
+    }
+    ws_tx.send(WsNotification::FileResolved { id, status: FileStatus::Rejected, filename: None }).ok();
+    Ok(())
+}
+
+/// Карантин не переживает перезапуск: метаданные хранятся только в памяти,
+/// поэтому оставшиеся файлы удаляем.
+pub async fn clear_stale(quarantine_path: &Path) -> io::Result<()> {
+    let mut entries = tokio::fs::read_dir(quarantine_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.metadata().await?.is_file() {
+            info!("Removing stale quarantined file {:?}", entry.path());
+            tokio::fs::remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
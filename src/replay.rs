@@ -0,0 +1,212 @@
+use crate::audit::key_fingerprint;
+use crate::capture::{self, CapturedDatagram};
+use crate::compression;
+use crate::config::Config;
+use crate::keys::KeyRing;
+use crate::pipeline::{self, DecryptFailure, DecryptedPacket};
+use crate::processor;
+use crate::protocol::{AsemicPacket, ControlFrame, Frame, FecParams, MessageEnvelope, OpenError, PACKET_BUCKETS};
+use crate::state::MessageContent;
+use base64::{engine::general_purpose, Engine};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::Path;
+
+/// Больше стольких диапазонов пропущенных чанков не перечисляем.
+const MAX_LISTED_RANGES: usize = 20;
+
+/// Сборка одного сообщения из захвата.
+struct Session {
+    /// Из первого пришедшего чанка.
+    total_chunks: u32,
+    fec: Option<FecParams>,
+    chunks: HashMap<u32, Vec<u8>>,
+    duplicates: usize,
+    /// Номер пакета в захвате, на котором сообщение собралось.
+    assembled_at: Option<usize>,
+    outcome: Option<Result<String, String>>,
+    /// Узел прислал `ResumeState`: часть чанков отправитель мог не слать вовсе.
+    resumed: bool,
+}
+
+/// `asemic_new replay <capture> [--keys-file <path>]`: прогоняет захват через расшифровку и сборку
+/// с данными ключами и печатает, что расшифровалось, какие чанки не дошли и почему.
+pub fn run(path: &Path, keys: &[String]) -> std::io::Result<()> {
+    let datagrams = capture::read_pcap(path)?;
+    let key_ring = KeyRing::default();
+    key_ring.publish(keys);
+    let (_, prepared) = key_ring.snapshot();
+    let limit = Config::default().file_policy().max_message_bytes();
+
+    println!("Replaying {} datagrams from {:?} with {} key(s)", datagrams.len(), path, keys.len());
+    println!("{:>6}  {:>10}  {:<40}  {:>5}  result", "#", "time, s", "sender", "bytes");
+    let started_us = datagrams.first().map_or(0, |d| d.timestamp_us);
+    let mut counts: BTreeMap<&'static str, usize> = BTreeMap::new();
+    let mut sessions: HashMap<(SocketAddr, u32), Session> = HashMap::new();
+    let mut session_order = Vec::new();
+
+    for (index, CapturedDatagram { timestamp_us, sender, payload, .. }) in datagrams.iter().enumerate() {
+        let number = index + 1;
+        let (class, mut result) = match pipeline::decrypt(&prepared, payload, *sender) {
+            Err(DecryptFailure::NoKey) => ("noise", "noise: the key hint matches none of the keys".to_string()),
+            Err(DecryptFailure::Open(OpenError::Authentication)) => {
+                ("authentication failure", "FAILED: key hint matched, but the packet did not authenticate".to_string())
+            }
+            Err(DecryptFailure::Open(OpenError::Malformed)) => {
+                ("malformed frame", "FAILED: decrypted, but the plaintext is not a protocol frame".to_string())
+            }
+            Ok(DecryptedPacket { frame: Frame::Control(control), key, .. }) => {
+                if let ControlFrame::ResumeState { msg_id, .. } = &control {
+                    if let Some(session) = sessions.get_mut(&(*sender, *msg_id)) {
+                        session.resumed = true;
+                    }
+                }
+                ("control frame", format!("control {} (key {})", describe_control(&control), key_fingerprint(&key)))
+            }
+            Ok(DecryptedPacket { frame: Frame::Chunk(chunk), key, .. }) => {
+                let description =
+                    format!("chunk {}/{} of message {} (key {})", chunk.chunk_num, chunk.total_chunks, chunk.msg_id, key_fingerprint(&key));
+                match general_purpose::STANDARD.decode(&chunk.data) {
+                    Ok(data) => ("chunk", add_chunk(&mut sessions, &mut session_order, *sender, chunk, data, number, limit, description)),
+                    Err(e) => ("malformed chunk", format!("FAILED: {}: chunk data is not Base64: {}", description, e)),
+                }
+            }
+        };
+        *counts.entry(class).or_default() += 1;
+        if !PACKET_BUCKETS.contains(&payload.len()) {
+            result.push_str("  [size is not one of the packet buckets]");
+        }
+        let elapsed = timestamp_us.saturating_sub(started_us) as f64 / 1_000_000.0;
+        println!("{:>6}  {:>10.6}  {:<40}  {:>5}  {}", number, elapsed, sender.to_string(), payload.len(), result);
+    }
+
+    println!();
+    println!("Packets:");
+    for (class, count) in &counts {
+        println!("  {:<24} {}", class, count);
+    }
+    println!();
+    println!("Messages:");
+    if session_order.is_empty() {
+        println!("  none");
+    }
+    for session_key in &session_order {
+        let session = &sessions[session_key];
+        println!("  {} message {}: {}", session_key.0, session_key.1, describe_session(session));
+    }
+    Ok(())
+}
+
+/// Кладёт чанк в сессию и пытается собрать сообщение. Возвращает описание для строки пакета.
+#[allow(clippy::too_many_arguments)]
+fn add_chunk(
+    sessions: &mut HashMap<(SocketAddr, u32), Session>,
+    session_order: &mut Vec<(SocketAddr, u32)>,
+    sender: SocketAddr,
+    chunk: AsemicPacket,
+    data: Vec<u8>,
+    number: usize,
+    limit: u64,
+    description: String,
+) -> String {
+    let session_key = (sender, chunk.msg_id);
+    let chunk_num = chunk.chunk_num;
+    let session = sessions.entry(session_key).or_insert_with(|| {
+        session_order.push(session_key);
+        Session { total_chunks: chunk.total_chunks, fec: chunk.fec, chunks: HashMap::new(), duplicates: 0, assembled_at: None, outcome: None, resumed: false }
+    });
+    if session.assembled_at.is_some() || session.chunks.contains_key(&chunk_num) {
+        session.duplicates += 1;
+        return format!("{}, duplicate", description);
+    }
+    session.chunks.insert(chunk_num, data);
+    let Some(assembled) = processor::assemble(&session.chunks, &chunk) else {
+        return description;
+    };
+    session.assembled_at = Some(number);
+    session.outcome = Some(decode_message(&assembled, &chunk, limit));
+    format!("{}, message assembled", description)
+}
+
+/// Распаковывает и разбирает собранное сообщение так же, как стадия сборки.
+fn decode_message(assembled: &[u8], chunk: &AsemicPacket, limit: u64) -> Result<String, String> {
+    let bytes = compression::decompress(assembled, chunk.compression, limit).map_err(|e| format!("decompression failed: {}", e))?;
+    let envelope: MessageEnvelope = serde_json::from_slice(&bytes).map_err(|e| format!("not a valid message: {}", e))?;
+    let ttl = envelope.ttl_secs.map_or(String::new(), |ttl| format!(", ttl {} s", ttl));
+    // Содержимое не печатаем: разбор нужен для отладки, а не для чтения переписки
+    let content = match envelope.content {
+        MessageContent::Text(text) => format!("text, {} bytes", text.len()),
+        MessageContent::File(file) => format!("file '{}', {} bytes", file.filename, file.data.len()),
+        MessageContent::Reply { reply_to, text } => format!("reply to {}, {} bytes", reply_to, text.len()),
+        MessageContent::Edit { target, .. } => format!("edit of {}", target),
+        MessageContent::Delete { target } => format!("deletion of {}", target),
+        MessageContent::Reaction { target, remove, .. } => format!("reaction to {}{}", target, if remove { " removed" } else { "" }),
+        MessageContent::Typing { active } => format!("typing {}", if active { "started" } else { "stopped" }),
+        MessageContent::KeyRotation { new_key, activate_at, .. } => {
+            format!("key rotation to {} at {}", key_fingerprint(&new_key), activate_at)
+        }
+    };
+    Ok(format!("{}{}", content, ttl))
+}
+
+fn describe_session(session: &Session) -> String {
+    let total = session.total_chunks;
+    let data_chunks = session.chunks.keys().filter(|&&c| c < total).count();
+    let repair_chunks = session.chunks.len() - data_chunks;
+    let received = format!(
+        "{}/{} data chunks, {} repair, {} duplicates",
+        data_chunks, total, repair_chunks, session.duplicates
+    );
+    match (&session.outcome, session.assembled_at) {
+        (Some(Ok(content)), Some(at)) => format!("assembled at packet {} ({}): {}", at, received, content),
+        (Some(Err(reason)), Some(at)) => format!("FAILED after assembly at packet {} ({}): {}", at, received, reason),
+        _ => {
+            let missing: Vec<u32> = (0..total).filter(|c| !session.chunks.contains_key(c)).collect();
+            let mut why = format!("INCOMPLETE ({}); missing {}", received, format_ranges(&missing));
+            match session.fec {
+                Some(_) if repair_chunks > 0 => why.push_str("; the repair chunks are not enough to recover them"),
+                Some(_) => why.push_str("; no repair chunks arrived"),
+                None => {}
+            }
+            if session.resumed {
+                why.push_str("; the receiver reported chunks from an earlier attempt, the sender may have skipped them");
+            }
+            why
+        }
+    }
+}
+
+fn describe_control(control: &ControlFrame) -> String {
+    match control {
+        ControlFrame::Receipt { msg_id, status } => format!("Receipt {:?} for message {}", status, msg_id),
+        ControlFrame::Ack { msg_id, chunks } => format!("Ack for {} chunks of message {}", chunks.len(), msg_id),
+        ControlFrame::Probe { probe_id, size } => format!("Probe {} of {} bytes", probe_id, size),
+        ControlFrame::ProbeAck { probe_id, size } => format!("ProbeAck {} of {} bytes", probe_id, size),
+        ControlFrame::ResumeOffer { msg_id, data_len, .. } => format!("ResumeOffer for message {} ({} bytes)", msg_id, data_len),
+        ControlFrame::ResumeState { msg_id, have } => format!("ResumeState for message {} ({} ranges held)", msg_id, have.len()),
+    }
+}
+
+/// `1-4, 9, 12-13` для отсортированного списка номеров.
+fn format_ranges(numbers: &[u32]) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for &n in numbers {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == n => *end = n,
+            _ => ranges.push((n, n)),
+        }
+    }
+    let mut text: Vec<String> = ranges
+        .iter()
+        .take(MAX_LISTED_RANGES)
+        .map(|&(start, end)| if start == end { start.to_string() } else { format!("{}-{}", start, end) })
+        .collect();
+    if ranges.len() > MAX_LISTED_RANGES {
+        text.push(format!("and {} more ranges", ranges.len() - MAX_LISTED_RANGES));
+    }
+    if text.is_empty() {
+        "none".to_string()
+    } else {
+        text.join(", ")
+    }
+}
@@ -0,0 +1,477 @@
+use crate::protocol;
+use crate::state::TransmitCommand;
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use chacha20::XChaCha20;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::mpsc as std_mpsc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info, warn};
+
+/// Недособранные передачи старше этого срока удаляются при запуске.
+const PARTIAL_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+/// Сколько диапазонов помещается в `ResumeState`, не выходя за минимальный пакет.
+/// Остальные чанки отправитель просто пришлёт заново.
+pub const MAX_RESUME_RANGES: usize = 48;
+/// Контексты производных ключей: шифрование сохранённых чанков и проверка, тем ли ключом они сохранены.
+const SPOOL_KEY_CONTEXT: &[u8] = b"asemic resume spool v1:";
+const SPOOL_CHECK_CONTEXT: &[u8] = b"asemic resume check v1:";
+
+/// Сохранённое состояние одной недособранной передачи. Полученные данные лежат
+/// в `<transfer_id>.part` по своим смещениям, а карта полученных байт — в `<transfer_id>.json`.
+/// Карта хранится в байтах, а не в номерах чанков: после перезапуска отправитель
+/// может выбрать другой размер чанка (PMTU ищется заново).
+///
+/// Данные зашифрованы потоком XChaCha20 на ключе, производном от ключа переписки:
+/// правила приёма проверяют файл только после сборки, и до того его части не должны
+/// лежать на диске в открытом виде. Поток позволяет писать и читать по любому смещению.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct PartialTransfer {
+    transfer_id: String,
+    /// Сессия, в которой сейчас идёт передача.
+    sender: SocketAddr,
+    msg_id: u32,
+    chunk_size: usize,
+    data_len: u64,
+    /// Полученные байты: отсортированные непересекающиеся полуинтервалы.
+    received: Vec<(u64, u64)>,
+    updated_at: DateTime<Utc>,
+    /// Отпечаток ключа шифрования данных; части, сохранённые под другим ключом, не читаем.
+    #[serde(default)]
+    key_check: String,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl PartialTransfer {
+    fn total_chunks(&self) -> u32 {
+        (self.data_len as usize).div_ceil(self.chunk_size).max(1) as u32
+    }
+
+    fn chunk_range(&self, chunk_num: u32) -> (u64, u64) {
+        let start = chunk_num as u64 * self.chunk_size as u64;
+        (start, (start + self.chunk_size as u64).min(self.data_len))
+    }
+
+    fn covers(&self, (start, end): (u64, u64)) -> bool {
+        self.received.iter().any(|&(s, e)| s <= start && end <= e)
+    }
+
+    fn insert_range(&mut self, (start, end): (u64, u64)) {
+        if start >= end || self.covers((start, end)) {
+            return;
+        }
+        self.received.push((start, end));
+        self.received.sort_unstable();
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(self.received.len());
+        for &(s, e) in &self.received {
+            match merged.last_mut() {
+                Some(last) if s <= last.1 => last.1 = last.1.max(e),
+                _ => merged.push((s, e)),
+            }
+        }
+        self.received = merged;
+        self.dirty = true;
+    }
+
+    /// Номера чанков (при текущем размере чанка), которые уже целиком получены.
+    fn held_chunks(&self) -> Vec<u32> {
+        (0..self.total_chunks()).filter(|&c| self.covers(self.chunk_range(c))).collect()
+    }
+}
+
+/// Ключ шифрования сохранённых чанков передачи, выведенный из ключа переписки.
+struct SpoolKey {
+    key: [u8; 32],
+    check: String,
+}
+
+impl SpoolKey {
+    fn new(conversation_key: &str) -> Self {
+        let check = protocol::derive_subkey(SPOOL_CHECK_CONTEXT, conversation_key.as_bytes());
+        Self {
+            key: protocol::derive_subkey(SPOOL_KEY_CONTEXT, conversation_key.as_bytes()),
+            check: check[..8].iter().map(|b| format!("{:02x}", b)).collect(),
+        }
+    }
+
+    /// Шифрует или расшифровывает `data`, лежащие в файле передачи по смещению `offset`.
+    /// Nonce берётся из `transfer_id`: у каждой передачи свой поток ключей.
+    fn apply(&self, transfer_id: &str, offset: u64, data: &mut [u8]) {
+        let nonce = Sha256::digest(transfer_id.as_bytes());
+        let mut cipher = XChaCha20::new(&self.key.into(), nonce[..24].into());
+        cipher.seek(offset);
+        cipher.apply_keystream(data);
+    }
+}
+
+/// Сворачивает отсортированные номера чанков в полуинтервалы `[start, end)`.
+fn to_ranges(chunks: &[u32]) -> Vec<(u32, u32)> {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for &chunk in chunks {
+        match ranges.last_mut() {
+            Some(last) if last.1 == chunk => last.1 += 1,
+            _ => ranges.push((chunk, chunk + 1)),
+        }
+    }
+    ranges
+}
+
+/// Что получатель знает о передаче, которую отправитель предлагает продолжить.
+pub struct ResumeOutcome {
+    /// Уже полученные чанки новой сессии, готовые для буфера сборки.
+    pub preloaded: HashMap<u32, Vec<u8>>,
+    /// Они же диапазонами для ответа отправителю.
+    pub have: Vec<(u32, u32)>,
+    /// Прежняя сессия этой передачи: её буфер сборки больше не нужен.
+    pub previous_session: Option<(SocketAddr, u32)>,
+}
+
+/// Запрос к потоку `resume`. Ответы приходят через `oneshot`.
+enum Request {
+    Offer {
+        sender: SocketAddr,
+        msg_id: u32,
+        transfer_id: String,
+        chunk_size: usize,
+        data_len: u64,
+        key: String,
+        reply: oneshot::Sender<io::Result<ResumeOutcome>>,
+    },
+    Record { session: (SocketAddr, u32), chunk_num: u32, data: Vec<u8>, key: String },
+    Flush { session: (SocketAddr, u32), then: mpsc::Sender<TransmitCommand>, ack: TransmitCommand },
+    Preload { session: (SocketAddr, u32), key: String, reply: oneshot::Sender<HashMap<u32, Vec<u8>>> },
+    Complete { session: (SocketAddr, u32) },
+}
+
+/// Хранилище недособранных файловых передач на стороне получателя.
+/// Дисковые операции выполняет отдельный поток по порядку поступления запросов,
+/// поэтому `flush` видит все записанные до него чанки.
+pub struct ResumeStore {
+    queue: std_mpsc::Sender<Request>,
+}
+
+impl ResumeStore {
+    /// Загружает сохранённые передачи и запускает поток записи.
+    pub fn load(dir: PathBuf) -> io::Result<Self> {
+        let mut spool = Spool::load(dir)?;
+        let (queue, requests) = std_mpsc::channel::<Request>();
+        std::thread::Builder::new().name("resume".to_string()).spawn(move || {
+            while let Ok(request) = requests.recv() {
+                spool.handle(request);
+            }
+        })?;
+        Ok(Self { queue })
+    }
+
+    fn send(&self, request: Request) {
+        if self.queue.send(request).is_err() {
+            warn!("Resume writer has stopped");
+        }
+    }
+
+    /// Отправитель предлагает продолжить передачу `transfer_id` в сессии `(sender, msg_id)`.
+    /// Привязывает сессию к сохранённому состоянию (или заводит новое) и сообщает, что уже есть.
+    /// `key` — ключ, которым зашифровано предложение; им же шифруются сохранённые чанки.
+    pub async fn offer(
+        &self,
+        sender: SocketAddr,
+        msg_id: u32,
+        transfer_id: &str,
+        chunk_size: usize,
+        data_len: u64,
+        key: &str,
+    ) -> io::Result<ResumeOutcome> {
+        let (reply, outcome) = oneshot::channel();
+        let (transfer_id, key) = (transfer_id.to_string(), key.to_string());
+        self.send(Request::Offer { sender, msg_id, transfer_id, chunk_size, data_len, key, reply });
+        outcome.await.map_err(|_| io::Error::other("resume writer has stopped"))?
+    }
+
+    /// Записывает исходный чанк сессии, если она возобновляемая. Карта сохраняется позже, в `flush`.
+    pub fn record_chunk(&self, session: (SocketAddr, u32), chunk_num: u32, data: Vec<u8>, key: &str) {
+        self.send(Request::Record { session, chunk_num, data, key: key.to_string() });
+    }
+
+    /// Сохраняет карту полученных байт и только потом ставит `ack` в очередь передатчика,
+    /// чтобы отправитель не считал доставленным то, что потеряется при перезапуске.
+    pub fn flush(&self, session: (SocketAddr, u32), then: &mpsc::Sender<TransmitCommand>, ack: TransmitCommand) {
+        self.send(Request::Flush { session, then: then.clone(), ack });
+    }
+
+    /// Уже сохранённые чанки сессии. Нужны, когда отправитель продолжает слать
+    /// чанки после перезапуска получателя: буфер сборки в памяти пуст.
+    pub async fn preload(&self, session: (SocketAddr, u32), key: &str) -> HashMap<u32, Vec<u8>> {
+        let (reply, chunks) = oneshot::channel();
+        self.send(Request::Preload { session, key: key.to_string(), reply });
+        chunks.await.unwrap_or_default()
+    }
+
+    /// Передача собрана (и дальше либо принята, либо отброшена правилами):
+    /// сохранённое состояние больше не нужно.
+    pub fn complete(&self, session: (SocketAddr, u32)) {
+        self.send(Request::Complete { session });
+    }
+}
+
+/// Недособранные файловые передачи на диске. Живёт в потоке `resume`,
+/// чтобы запись чанков не занимала потоки tokio.
+struct Spool {
+    dir: PathBuf,
+    partials: HashMap<String, PartialTransfer>,
+    sessions: HashMap<(SocketAddr, u32), String>,
+}
+
+/// `transfer_id` попадает в имя файла, поэтому принимаем только hex SHA-256.
+pub fn is_valid_transfer_id(transfer_id: &str) -> bool {
+    transfer_id.len() == 64 && transfer_id.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase())
+}
+
+impl Spool {
+    /// Загружает сохранённые передачи, удаляя устаревшие и повреждённые.
+    fn load(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let mut store = Self { dir, partials: HashMap::new(), sessions: HashMap::new() };
+        let now = Utc::now();
+        for entry in fs::read_dir(&store.dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            let partial = fs::read(&path)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<PartialTransfer>(&bytes).ok())
+                // Без отпечатка ключа — части из версии, хранившей их в открытом виде
+                .filter(|p| is_valid_transfer_id(&p.transfer_id) && p.chunk_size > 0 && !p.key_check.is_empty());
+            match partial {
+                Some(partial) if (now - partial.updated_at).to_std().unwrap_or_default() < PARTIAL_TTL => {
+                    store.sessions.insert((partial.sender, partial.msg_id), partial.transfer_id.clone());
+                    store.partials.insert(partial.transfer_id.clone(), partial);
+                }
+                _ => {
+                    info!("Removing stale partial transfer {:?}", path);
+                    fs::remove_file(&path).ok();
+                    fs::remove_file(path.with_extension("part")).ok();
+                }
+            }
+        }
+        // Данные без карты бесполезны
+        for entry in fs::read_dir(&store.dir)? {
+            let path = entry?.path();
+            let orphan = path.extension().is_some_and(|e| e == "part")
+                && path.file_stem().is_none_or(|stem| !store.partials.contains_key(&*stem.to_string_lossy()));
+            if orphan {
+                fs::remove_file(&path).ok();
+            }
+        }
+        info!("Loaded {} partial transfers", store.partials.len());
+        Ok(store)
+    }
+
+    fn handle(&mut self, request: Request) {
+        match request {
+            Request::Offer { sender, msg_id, transfer_id, chunk_size, data_len, key, reply } => {
+                reply.send(self.offer(sender, msg_id, &transfer_id, chunk_size, data_len, &key)).ok();
+            }
+            Request::Record { session, chunk_num, data, key } => self.record_chunk(session, chunk_num, data, &key),
+            Request::Flush { session, then, ack } => {
+                self.flush(session);
+                if let Err(e) = then.try_send(ack) {
+                    warn!("Failed to queue ack for session {:?}: {}", session, e);
+                }
+            }
+            Request::Preload { session, key, reply } => {
+                reply.send(self.preload(session, &key)).ok();
+            }
+            Request::Complete { session } => self.complete(session),
+        }
+    }
+
+    fn data_path(&self, transfer_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.part", transfer_id))
+    }
+
+    fn meta_path(&self, transfer_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", transfer_id))
+    }
+
+    fn save(&self, partial: &PartialTransfer) -> io::Result<()> {
+        let json = serde_json::to_vec(partial).map_err(io::Error::other)?;
+        let path = self.meta_path(&partial.transfer_id);
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)?;
+        fs::rename(&tmp_path, &path)
+    }
+
+    fn offer(
+        &mut self,
+        sender: SocketAddr,
+        msg_id: u32,
+        transfer_id: &str,
+        chunk_size: usize,
+        data_len: u64,
+        key: &str,
+    ) -> io::Result<ResumeOutcome> {
+        let spool_key = SpoolKey::new(key);
+        let mut outcome = ResumeOutcome { preloaded: HashMap::new(), have: Vec::new(), previous_session: None };
+        let session = (sender, msg_id);
+
+        if let Some(previous) = self.partials.get(transfer_id).map(|p| (p.sender, p.msg_id)) {
+            if previous != session {
+                self.sessions.remove(&previous);
+                outcome.previous_session = Some(previous);
+            }
+        }
+        let existing = self.partials.get(transfer_id).filter(|p| {
+            // Продолжаем только передачу от того же узла (порт мог смениться) с тем же содержимым и ключом
+            p.sender.ip() == sender.ip() && p.data_len == data_len && p.key_check == spool_key.check
+        });
+        let mut partial = match existing {
+            Some(existing) => {
+                let mut partial = existing.clone();
+                partial.sender = sender;
+                partial.msg_id = msg_id;
+                partial.chunk_size = chunk_size;
+                partial
+            }
+            None => PartialTransfer {
+                transfer_id: transfer_id.to_string(),
+                sender,
+                msg_id,
+                chunk_size,
+                data_len,
+                received: Vec::new(),
+                updated_at: Utc::now(),
+                key_check: spool_key.check.clone(),
+                dirty: false,
+            },
+        };
+        partial.updated_at = Utc::now();
+
+        let mut held = partial.held_chunks();
+        if held.len() as u32 == partial.total_chunks() {
+            // Всё уже есть, но сообщение не собрано (сбой между записью и сборкой):
+            // пусть отправитель пришлёт последний чанк, его приход и запустит сборку
+            held.pop();
+        }
+        match self.read_chunks(&partial, &held, &spool_key) {
+            Ok(preloaded) => outcome.preloaded = preloaded,
+            Err(e) => {
+                warn!("Saved data of transfer {} is unreadable, starting over: {}", transfer_id, e);
+                partial.received.clear();
+                held.clear();
+            }
+        }
+        if !held.is_empty() {
+            info!("Resuming transfer {} from {}: {} of {} chunks already received", transfer_id, sender, held.len(), partial.total_chunks());
+        }
+        outcome.have = to_ranges(&held);
+        outcome.have.truncate(MAX_RESUME_RANGES);
+
+        self.save(&partial)?;
+        self.sessions.insert(session, transfer_id.to_string());
+        self.partials.insert(transfer_id.to_string(), partial);
+        Ok(outcome)
+    }
+
+    /// Ремонтные, повторные и чужие по ключу чанки пропускаются.
+    fn record_chunk(&mut self, session: (SocketAddr, u32), chunk_num: u32, mut data: Vec<u8>, key: &str) {
+        let Some(transfer_id) = self.sessions.get(&session) else { return };
+        let data_path = self.data_path(transfer_id);
+        let Some(partial) = self.partials.get_mut(transfer_id) else { return };
+        let spool_key = SpoolKey::new(key);
+        if partial.key_check != spool_key.check {
+            return;
+        }
+        // Ремонтные чанки и чанки неправильной длины не сохраняем
+        if chunk_num >= partial.total_chunks() {
+            return;
+        }
+        let (start, end) = partial.chunk_range(chunk_num);
+        if data.len() as u64 != end - start || partial.covers((start, end)) {
+            return;
+        }
+        spool_key.apply(&partial.transfer_id, start, &mut data);
+        let written = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&data_path)
+            .and_then(|mut file| {
+                file.seek(SeekFrom::Start(start))?;
+                file.write_all(&data)
+            });
+        match written {
+            Ok(()) => partial.insert_range((start, end)),
+            Err(e) => warn!("Failed to persist chunk {} of transfer {}: {}", chunk_num, partial.transfer_id, e),
+        }
+    }
+
+    fn flush(&mut self, session: (SocketAddr, u32)) {
+        let Some(transfer_id) = self.sessions.get(&session) else { return };
+        let Some(partial) = self.partials.get(transfer_id) else { return };
+        if !partial.dirty {
+            return;
+        }
+        let mut partial = partial.clone();
+        partial.dirty = false;
+        partial.updated_at = Utc::now();
+        match self.save(&partial) {
+            Ok(()) => {
+                self.partials.insert(partial.transfer_id.clone(), partial);
+            }
+            Err(e) => warn!("Failed to save state of transfer {}: {}", partial.transfer_id, e),
+        }
+    }
+
+    fn preload(&self, session: (SocketAddr, u32), key: &str) -> HashMap<u32, Vec<u8>> {
+        let Some(partial) = self.sessions.get(&session).and_then(|id| self.partials.get(id)) else {
+            return HashMap::new();
+        };
+        let spool_key = SpoolKey::new(key);
+        if partial.key_check != spool_key.check {
+            return HashMap::new();
+        }
+        match self.read_chunks(partial, &partial.held_chunks(), &spool_key) {
+            Ok(chunks) => {
+                debug!("Restored {} saved chunks of transfer {}", chunks.len(), partial.transfer_id);
+                chunks
+            }
+            Err(e) => {
+                warn!("Failed to load saved chunks of transfer {}: {}", partial.transfer_id, e);
+                HashMap::new()
+            }
+        }
+    }
+
+    fn read_chunks(&self, partial: &PartialTransfer, chunks: &[u32], spool_key: &SpoolKey) -> io::Result<HashMap<u32, Vec<u8>>> {
+        let mut loaded = HashMap::new();
+        if chunks.is_empty() {
+            return Ok(loaded);
+        }
+        let mut file = fs::File::open(self.data_path(&partial.transfer_id))?;
+        for &chunk_num in chunks {
+            let (start, end) = partial.chunk_range(chunk_num);
+            let mut data = vec![0u8; (end - start) as usize];
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(&mut data)?;
+            spool_key.apply(&partial.transfer_id, start, &mut data);
+            loaded.insert(chunk_num, data);
+        }
+        Ok(loaded)
+    }
+
+    fn complete(&mut self, session: (SocketAddr, u32)) {
+        let Some(transfer_id) = self.sessions.remove(&session) else { return };
+        self.partials.remove(&transfer_id);
+        fs::remove_file(self.meta_path(&transfer_id)).ok();
+        fs::remove_file(self.data_path(&transfer_id)).ok();
+    }
+}
@@ -0,0 +1,302 @@
+use crate::audit::{key_fingerprint, AuditEvent};
+use crate::protocol::ControlFrame;
+use crate::state::{AppState, History, KeyRotation, MessageStatus, ObfuscationPattern, RotationPhase, SharedState, TransmitCommand, WsNotification};
+use chrono::{DateTime, Utc};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::info;
+use uuid::Uuid;
+
+/// Собеседнику нужно время, чтобы увидеть предложение и согласиться на него.
+pub const DEFAULT_ACTIVATION_DELAY_SECS: u32 = 15 * 60;
+pub const DEFAULT_GRACE_SECS: u32 = 24 * 60 * 60;
+/// Предложения с активацией позже этого срока отклоняем: так узел не может
+/// незаметно подложить ключ «на потом».
+const MAX_ACTIVATION_DELAY_SECS: i64 = 7 * 24 * 60 * 60;
+/// Короче этого старый ключ исчез бы раньше, чем дойдут отправленные им сообщения.
+const MIN_GRACE_SECS: u32 = 10 * 60;
+const MAX_GRACE_SECS: u32 = 30 * 24 * 60 * 60;
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Сообщение, которым ротация предложена или пришла.
+pub struct Proposal {
+    pub peer: SocketAddr,
+    pub msg_id: u32,
+    pub pattern: ObfuscationPattern,
+}
+
+/// Проверяет параметры ротации (своей или присланной узлом) и собирает её.
+/// Ротация узла начинается в фазе `Pending` и ждёт согласия пользователя.
+pub fn plan(
+    old_key: &str,
+    new_key: String,
+    proposal: Proposal,
+    activate_at: DateTime<Utc>,
+    grace_secs: u32,
+    initiated_locally: bool,
+) -> Result<KeyRotation, String> {
+    if new_key.is_empty() || new_key == old_key {
+        return Err("new key must be non-empty and differ from the current one".to_string());
+    }
+    let now = Utc::now();
+    if activate_at <= now {
+        return Err(format!("activation time {} is in the past", activate_at));
+    }
+    if activate_at > now + chrono::Duration::seconds(MAX_ACTIVATION_DELAY_SECS) {
+        return Err(format!("activation time {} is too far in the future", activate_at));
+    }
+    if grace_secs < MIN_GRACE_SECS {
+        return Err(format!("grace period of {} s is shorter than the minimum of {} s", grace_secs, MIN_GRACE_SECS));
+    }
+    let grace_secs = grace_secs.min(MAX_GRACE_SECS);
+    let Proposal { peer, msg_id, pattern } = proposal;
+    Ok(KeyRotation {
+        id: Uuid::new_v4(),
+        msg_id,
+        pattern,
+        old_key: old_key.to_string(),
+        new_key,
+        peer,
+        activate_at,
+        retire_at: activate_at + chrono::Duration::seconds(grace_secs as i64),
+        initiated_locally,
+        phase: if initiated_locally { RotationPhase::Proposed } else { RotationPhase::Pending },
+    })
+}
+
+/// Начинает свою ротацию: новый ключ принимается сразу, чтобы ответы им не потерялись.
+/// Исходящие перейдут на него, только если узел успеет подтвердить согласие.
+/// Возвращает `false`, если такая ротация уже идёт.
+pub fn register(state: &mut AppState, ws_tx: &broadcast::Sender<WsNotification>, rotation: KeyRotation) -> bool {
+    let accepted = rotation.clone();
+    if !record(state, ws_tx, rotation) {
+        return false;
+    }
+    accept_new_key(state, ws_tx, &accepted);
+    true
+}
+
+/// Предложение узла: показываем пользователю и ждём решения (`accept` или `decline`).
+/// Возвращает `false`, если такое предложение уже есть (например, пришло повторно).
+pub fn receive_proposal(state: &mut AppState, ws_tx: &broadcast::Sender<WsNotification>, rotation: KeyRotation) -> bool {
+    record(state, ws_tx, rotation)
+}
+
+fn record(state: &mut AppState, ws_tx: &broadcast::Sender<WsNotification>, rotation: KeyRotation) -> bool {
+    // Ротация относится к паре (узел, ключ); с другими узлами этот ключ живёт своей жизнью
+    let in_progress = |r: &KeyRotation| !matches!(r.phase, RotationPhase::Retired | RotationPhase::Aborted);
+    if state.rotations.iter().any(|r| in_progress(r) && r.peer == rotation.peer && r.old_key == rotation.old_key) {
+        return false;
+    }
+    info!(
+        "Key rotation {} -> {} with {} proposed ({}), activates at {}",
+        key_fingerprint(&rotation.old_key),
+        key_fingerprint(&rotation.new_key),
+        rotation.peer,
+        if rotation.initiated_locally { "by us" } else { "by peer" },
+        rotation.activate_at
+    );
+    state.audit.record(AuditEvent::KeyRotationProposed {
+        old_key: key_fingerprint(&rotation.old_key),
+        new_key: key_fingerprint(&rotation.new_key),
+        peer: rotation.peer,
+        activate_at: rotation.activate_at,
+        retire_at: rotation.retire_at,
+        initiated_locally: rotation.initiated_locally,
+    });
+    ws_tx.send(WsNotification::KeyRotationUpdate(rotation.clone())).ok();
+    state.rotations.push(rotation);
+    true
+}
+
+/// Новый ключ принимается с момента согласия, чтобы ответы им не потерялись.
+fn accept_new_key(state: &mut AppState, ws_tx: &broadcast::Sender<WsNotification>, rotation: &KeyRotation) {
+    if !state.keys.contains(&rotation.new_key) {
+        state.keys.push(rotation.new_key.clone());
+        state.keys_changed(ws_tx);
+    }
+}
+
+/// Почему решение по ротации не принято.
+#[derive(Debug)]
+pub enum DecisionError {
+    NotFound,
+    /// Время активации уже прошло: узел ротацию без нас не начнёт.
+    Expired,
+}
+
+/// Пользователь согласился на ротацию узла. Возвращает её, чтобы подтвердить узлу согласие.
+pub fn accept(state: &mut AppState, history: &History, ws_tx: &broadcast::Sender<WsNotification>, id: Uuid, now: DateTime<Utc>) -> Result<KeyRotation, DecisionError> {
+    let index = pending_index(state, id)?;
+    if now >= state.rotations[index].activate_at {
+        abort(state, history, ws_tx, index, "the activation time passed before it was accepted");
+        return Err(DecisionError::Expired);
+    }
+    let rotation = state.rotations[index].clone();
+    info!("Accepted key rotation {} -> {} from {}", key_fingerprint(&rotation.old_key), key_fingerprint(&rotation.new_key), rotation.peer);
+    accept_new_key(state, ws_tx, &rotation);
+    state.audit.record(AuditEvent::KeyRotationAccepted {
+        old_key: key_fingerprint(&rotation.old_key),
+        new_key: key_fingerprint(&rotation.new_key),
+        peer: rotation.peer,
+    });
+    state.rotations[index].phase = RotationPhase::Proposed;
+    ws_tx.send(WsNotification::KeyRotationUpdate(state.rotations[index].clone())).ok();
+    Ok(state.rotations[index].clone())
+}
+
+/// Пользователь отказался от ротации узла: новый ключ так и не принимается.
+pub fn decline(state: &mut AppState, history: &History, ws_tx: &broadcast::Sender<WsNotification>, id: Uuid) -> Result<(), DecisionError> {
+    let index = pending_index(state, id)?;
+    abort(state, history, ws_tx, index, "declined");
+    Ok(())
+}
+
+/// Квитанция о прочтении предложения: так узел узнаёт, что мы согласились.
+pub fn consent_receipt(rotation: &KeyRotation) -> TransmitCommand {
+    TransmitCommand::SendControl {
+        target_addr: rotation.peer,
+        key: rotation.old_key.clone(),
+        pattern: rotation.pattern,
+        frame: ControlFrame::Receipt { msg_id: rotation.msg_id, status: MessageStatus::Read },
+    }
+}
+
+fn pending_index(state: &AppState, id: Uuid) -> Result<usize, DecisionError> {
+    state.rotations.iter().position(|r| r.id == id && r.phase == RotationPhase::Pending).ok_or(DecisionError::NotFound)
+}
+
+/// Узел согласился на нашу ротацию: на сообщение с предложением пришла квитанция о прочтении.
+fn peer_accepted(history: &History, rotation: &KeyRotation) -> bool {
+    history.outgoing.iter().any(|m| m.target == rotation.peer && m.msg_id == rotation.msg_id && m.status == MessageStatus::Read)
+}
+
+/// Нужен ли ключ ещё кому-то, кроме ротации `except`: другой незавершённой ротации
+/// или переписке с узлом, с которым этот ключ ещё не сменён.
+fn key_in_use(state: &AppState, history: &History, key: &str, except: Uuid) -> bool {
+    let in_rotation = state.rotations.iter().any(|r| {
+        r.id != except && !matches!(r.phase, RotationPhase::Retired | RotationPhase::Aborted) && (r.old_key == key || r.new_key == key)
+    });
+    let rotated_away = |peer: SocketAddr| {
+        state.rotations.iter().any(|r| r.peer == peer && r.old_key == key && matches!(r.phase, RotationPhase::Active | RotationPhase::Retired))
+    };
+    let mut peers = history
+        .messages
+        .iter()
+        .filter(|m| m.decrypted_with_key == key)
+        .map(|m| m.sender)
+        .chain(history.outgoing.iter().filter(|m| m.sent_with_key == key).map(|m| m.target));
+    in_rotation || peers.any(|peer| !rotated_away(peer))
+}
+
+/// Снимает ротацию, оставляя старый ключ. Уже принимаемый новый ключ удаляется,
+/// если больше никому не нужен.
+fn abort(state: &mut AppState, history: &History, ws_tx: &broadcast::Sender<WsNotification>, index: usize, reason: &str) {
+    let mut rotation = state.rotations.remove(index);
+    if rotation.phase == RotationPhase::Proposed && !key_in_use(state, history, &rotation.new_key, rotation.id) {
+        state.keys.retain(|k| k != &rotation.new_key);
+        state.keys_changed(ws_tx);
+    }
+    info!("Key rotation {} -> {} with {} aborted: {}", key_fingerprint(&rotation.old_key), key_fingerprint(&rotation.new_key), rotation.peer, reason);
+    state.audit.record(AuditEvent::KeyRotationAborted {
+        old_key: key_fingerprint(&rotation.old_key),
+        new_key: key_fingerprint(&rotation.new_key),
+        peer: rotation.peer,
+        reason: reason.to_string(),
+    });
+    rotation.phase = RotationPhase::Aborted;
+    ws_tx.send(WsNotification::KeyRotationUpdate(rotation)).ok();
+}
+
+/// Ключ, которым на самом деле шифровать исходящие узлу `peer`, выбранные в UI ключом `key`.
+/// Ротация с одним узлом не меняет ключ для остальных.
+pub fn current_key(state: &AppState, peer: SocketAddr, key: &str) -> String {
+    let mut key = key.to_string();
+    // Ротации могут идти цепочкой; число шагов ограничено числом ротаций на случай цикла.
+    // Завершённая ротация, чей старый ключ остался ради других узлов, по-прежнему действует.
+    for _ in 0..state.rotations.len() {
+        let replaces = |r: &&KeyRotation| matches!(r.phase, RotationPhase::Active | RotationPhase::Retired) && r.peer == peer && r.old_key == key;
+        match state.rotations.iter().find(replaces) {
+            Some(rotation) => key = rotation.new_key.clone(),
+            None => break,
+        }
+    }
+    key
+}
+
+/// Раз в секунду переводит ротации в следующую фазу.
+pub async fn run(state: SharedState, ws_tx: broadcast::Sender<WsNotification>) {
+    let mut interval = tokio::time::interval(TICK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut state_guard = state.lock().await;
+        if !state_guard.rotations.is_empty() {
+            advance(&mut state_guard, &state.history(), &ws_tx, Utc::now());
+        }
+    }
+}
+
+/// Переводит ротации в фазы, наступившие к моменту `now`.
+pub fn advance(state: &mut AppState, history: &History, ws_tx: &broadcast::Sender<WsNotification>, now: DateTime<Utc>) {
+    // Предложения, на которые пользователь (или узел — на наше) не ответил до активации, не состоялись
+    let unanswered = |r: &KeyRotation| {
+        now >= r.activate_at
+            && (r.phase == RotationPhase::Pending || (r.phase == RotationPhase::Proposed && r.initiated_locally && !peer_accepted(history, r)))
+    };
+    while let Some(index) = state.rotations.iter().position(unanswered) {
+        let reason = if state.rotations[index].initiated_locally { "the peer did not accept before the activation time" } else { "not accepted before the activation time" };
+        abort(state, history, ws_tx, index, reason);
+    }
+    let mut keys_changed = false;
+    for index in 0..state.rotations.len() {
+        let rotation = state.rotations[index].clone();
+        if rotation.phase == RotationPhase::Proposed && now >= rotation.activate_at {
+            info!("Key rotation {} -> {} is now active", key_fingerprint(&rotation.old_key), key_fingerprint(&rotation.new_key));
+            // Настройки ключа переходят к новому, если для него своих нет
+            if let Some(compression) = state.key_compression.get(&rotation.old_key).copied() {
+                state.key_compression.entry(rotation.new_key.clone()).or_insert(compression);
+                ws_tx.send(WsNotification::KeyCompressionUpdate(state.key_compression.clone())).ok();
+            }
+            if let Some(ttl) = state.key_ttl.get(&rotation.old_key).copied() {
+                state.key_ttl.entry(rotation.new_key.clone()).or_insert(ttl);
+                ws_tx.send(WsNotification::KeyTtlUpdate(state.key_ttl.clone())).ok();
+            }
+            if let Some(label) = state.key_labels.get(&rotation.old_key).cloned() {
+                state.key_labels.entry(rotation.new_key.clone()).or_insert(label);
+                ws_tx.send(WsNotification::KeyLabelsUpdate(state.key_labels.clone())).ok();
+            }
+            state.audit.record(AuditEvent::KeyRotationActivated {
+                old_key: key_fingerprint(&rotation.old_key),
+                new_key: key_fingerprint(&rotation.new_key),
+            });
+            state.rotations[index].phase = RotationPhase::Active;
+            ws_tx.send(WsNotification::KeyRotationUpdate(state.rotations[index].clone())).ok();
+        }
+        if state.rotations[index].phase == RotationPhase::Active && now >= rotation.retire_at {
+            state.rotations[index].phase = RotationPhase::Retired;
+            // Ключ, общий и с другими узлами, остаётся для них
+            if key_in_use(state, history, &rotation.old_key, rotation.id) {
+                info!("Grace period with {} over, key {} stays for other peers", rotation.peer, key_fingerprint(&rotation.old_key));
+            } else {
+                info!("Grace period over, retiring key {}", key_fingerprint(&rotation.old_key));
+                state.keys.retain(|k| k != &rotation.old_key);
+                state.key_compression.remove(&rotation.old_key);
+                state.key_ttl.remove(&rotation.old_key);
+                state.key_labels.remove(&rotation.old_key);
+                state.audit.record(AuditEvent::KeyRetired { key: key_fingerprint(&rotation.old_key) });
+                keys_changed = true;
+            }
+            ws_tx.send(WsNotification::KeyRotationUpdate(state.rotations[index].clone())).ok();
+        }
+    }
+    // Завершённая ротация нужна, пока её старый ключ остаётся: она перенаправляет исходящие узлу
+    let keys = &state.keys;
+    state.rotations.retain(|r| r.phase != RotationPhase::Retired || keys.contains(&r.old_key));
+    if keys_changed {
+        state.keys_changed(ws_tx);
+        ws_tx.send(WsNotification::KeyCompressionUpdate(state.key_compression.clone())).ok();
+        ws_tx.send(WsNotification::KeyTtlUpdate(state.key_ttl.clone())).ok();
+        ws_tx.send(WsNotification::KeyLabelsUpdate(state.key_labels.clone())).ok();
+    }
+}
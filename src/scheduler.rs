@@ -0,0 +1,449 @@
+use crate::fec;
+use crate::files;
+use crate::protocol::{self, ControlFrame, FecParams};
+use crate::state::{Compression, MessageContent, ObfuscationPattern};
+use base64::{engine::general_purpose, Engine};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::debug;
+
+/// Сколько раз можно повторить один чанк, прежде чем сдаться.
+const MAX_CHUNK_RETRIES: u32 = 8;
+/// Сколько раз предлагаем получателю продолжить передачу, прежде чем слать всё с начала.
+const RESUME_OFFER_ATTEMPTS: u32 = 3;
+/// Пауза между повторами `ResumeOffer`.
+const RESUME_OFFER_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Приоритет исходящего сообщения. Чем меньше значение, тем раньше уходят чанки.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TransmitPriority {
+    Text,
+    File,
+}
+
+impl TransmitPriority {
+    pub fn for_content(content: &MessageContent) -> Self {
+        match content {
+            MessageContent::File(_) => TransmitPriority::File,
+            _ => TransmitPriority::Text,
+        }
+    }
+}
+
+/// Одно сообщение в очереди передатчика: сериализованные данные, позиция отправки
+/// и чанки, ожидающие подтверждения.
+pub struct OutgoingTransfer {
+    pub msg_id: u32,
+    pub target_addr: SocketAddr,
+    pub key: String,
+    pub pattern: ObfuscationPattern,
+    pub priority: TransmitPriority,
+    data: Vec<u8>,
+    /// Размер чанка фиксируется при постановке в очередь и не меняется вместе с PMTU.
+    chunk_size: usize,
+    next_chunk: u32,
+    /// Число исходных чанков (без ремонтных).
+    pub total_chunks: u32,
+    fec: Option<FecParams>,
+    /// Чем сжаты `data`; передаётся получателю в каждом чанке.
+    compression: Compression,
+    /// Ремонтные чанки Reed-Solomon, идут после исходных.
+    repair: Vec<Vec<u8>>,
+    /// Отправленные, но ещё не подтверждённые чанки и время их последней отправки.
+    in_flight: HashMap<u32, Instant>,
+    /// Потерянные чанки, которые нужно отправить заново (раньше новых).
+    retransmit: VecDeque<u32>,
+    retries: HashMap<u32, u32>,
+    /// Пока идёт обмен `ResumeOffer`/`ResumeState`, чанки не отправляются.
+    resume: Option<ResumeHandshake>,
+    /// Чанки, которые у получателя уже есть с прошлых попыток.
+    held_by_peer: HashSet<u32>,
+}
+
+struct ResumeHandshake {
+    transfer_id: String,
+    attempts: u32,
+    last_offer_at: Option<Instant>,
+}
+
+impl OutgoingTransfer {
+    pub fn new(
+        msg_id: u32,
+        target_addr: SocketAddr,
+        key: String,
+        pattern: ObfuscationPattern,
+        priority: TransmitPriority,
+        data: Vec<u8>,
+        chunk_size: usize,
+    ) -> Self {
+        // Пустое сообщение всё равно занимает один чанк
+        let total_chunks = data.len().div_ceil(chunk_size).max(1) as u32;
+        Self {
+            msg_id,
+            target_addr,
+            key,
+            pattern,
+            priority,
+            data,
+            chunk_size,
+            next_chunk: 0,
+            total_chunks,
+            fec: None,
+            compression: Compression::Off,
+            repair: Vec::new(),
+            in_flight: HashMap::new(),
+            retransmit: VecDeque::new(),
+            retries: HashMap::new(),
+            resume: None,
+            held_by_peer: HashSet::new(),
+        }
+    }
+
+    /// Делает передачу возобновляемой: перед отправкой чанков спрашиваем получателя,
+    /// что у него уже есть. Идентификатор передачи — SHA-256 содержимого, поэтому
+    /// повторная отправка того же файла после перезапуска найдёт сохранённые части.
+    pub fn resumable(mut self) -> Self {
+        self.resume = Some(ResumeHandshake { transfer_id: files::sha256_hex(&self.data), attempts: 0, last_offer_at: None });
+        self
+    }
+
+    /// Получатель сообщил, какие чанки у него уже есть: пропускаем их.
+    fn apply_resume_state(&mut self, have: &[(u32, u32)]) -> u32 {
+        if self.resume.take().is_none() {
+            return 0;
+        }
+        for &(start, end) in have {
+            self.held_by_peer.extend(start..end.min(self.total_chunks));
+        }
+        self.skip_held_chunks();
+        self.held_by_peer.len() as u32
+    }
+
+    fn skip_held_chunks(&mut self) {
+        while self.held_by_peer.contains(&self.next_chunk) {
+            self.next_chunk += 1;
+        }
+    }
+
+    /// Отмечает, что `data` уже сжаты (см. `compression::compress`).
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Добавляет ремонтные чанки: `redundancy` — их доля от исходных.
+    pub fn with_fec(mut self, redundancy: f32) -> Self {
+        self.fec = FecParams::for_message(self.data.len(), self.chunk_size, redundancy);
+        if let Some(params) = &self.fec {
+            self.repair = fec::encode_repair(&self.data, self.total_chunks, params);
+        }
+        self
+    }
+
+    pub fn chunks_sent(&self) -> u32 {
+        self.next_chunk
+    }
+
+    /// Все пакеты сообщения, включая ремонтные.
+    pub fn total_packets(&self) -> u32 {
+        self.total_chunks + self.repair.len() as u32
+    }
+
+    /// Каждый чанк отправлен хотя бы один раз.
+    pub fn first_pass_done(&self) -> bool {
+        self.next_chunk >= self.total_packets()
+    }
+
+    fn has_pending_chunks(&self) -> bool {
+        self.resume.is_none() && (!self.first_pass_done() || !self.retransmit.is_empty())
+    }
+
+    fn is_acknowledged(&self) -> bool {
+        !self.has_pending_chunks() && self.in_flight.is_empty()
+    }
+
+    /// Формирует открытый текст следующего чанка (повторы идут первыми).
+    fn next_frame(&mut self, now: Instant) -> (u32, Vec<u8>) {
+        let chunk_num = match self.retransmit.pop_front() {
+            Some(chunk_num) => chunk_num,
+            None => {
+                self.next_chunk += 1;
+                let chunk_num = self.next_chunk - 1;
+                self.skip_held_chunks();
+                chunk_num
+            }
+        };
+        let chunk_data = match fec::repair_chunk(&self.repair, chunk_num, self.total_chunks) {
+            Some(repair) => repair,
+            None => {
+                let start = chunk_num as usize * self.chunk_size;
+                let end = (start + self.chunk_size).min(self.data.len());
+                &self.data[start..end]
+            }
+        };
+        let asemic_packet = protocol::AsemicPacket {
+            msg_id: self.msg_id,
+            chunk_num,
+            total_chunks: self.total_chunks,
+            data: general_purpose::STANDARD.encode(chunk_data),
+            fec: self.fec,
+            compression: self.compression,
+        };
+        self.in_flight.insert(chunk_num, now);
+        (chunk_num, protocol::encode_frame(&protocol::Frame::Chunk(asemic_packet)))
+    }
+
+    /// Помечает чанки потерянными. Возвращает `false`, если какой-то чанк исчерпал попытки.
+    /// Ремонтные чанки не повторяем: их задача — обойтись без повторов.
+    fn mark_lost(&mut self, lost: &[u32]) -> bool {
+        for &chunk_num in lost {
+            self.in_flight.remove(&chunk_num);
+            if chunk_num >= self.total_chunks {
+                continue;
+            }
+            let retries = self.retries.entry(chunk_num).or_default();
+            *retries += 1;
+            if *retries > MAX_CHUNK_RETRIES {
+                return false;
+            }
+            self.retransmit.push_back(chunk_num);
+        }
+        true
+    }
+}
+
+/// Чанк, готовый к шифрованию и отправке.
+pub struct ScheduledChunk {
+    pub msg_id: u32,
+    pub chunk_num: u32,
+    pub target_addr: SocketAddr,
+    pub key: String,
+    pub pattern: ObfuscationPattern,
+    pub plaintext: Vec<u8>,
+    pub is_retransmission: bool,
+    /// Этим чанком завершился первый проход по сообщению.
+    pub first_pass_done: bool,
+}
+
+/// Служебный кадр, который передатчик должен отправить от имени сообщения.
+pub struct ControlToSend {
+    pub target_addr: SocketAddr,
+    pub key: String,
+    pub pattern: ObfuscationPattern,
+    pub frame: ControlFrame,
+}
+
+/// Результат обработки подтверждения.
+#[derive(Default)]
+pub struct AckOutcome {
+    pub acked: usize,
+    pub rtt_sample: Option<Duration>,
+    /// Потери, обнаруженные по более поздним подтверждённым чанкам.
+    pub lost: usize,
+    /// Сообщение подтверждено целиком и убрано из очереди.
+    pub completed: Option<OutgoingTransfer>,
+    /// Сообщение исчерпало попытки повторной отправки.
+    pub failed: Option<OutgoingTransfer>,
+}
+
+/// Потери, обнаруженные по таймауту.
+pub struct LossReport {
+    pub target_addr: SocketAddr,
+    pub lost: usize,
+    pub failed: Option<OutgoingTransfer>,
+}
+
+/// Очередь передатчика. Чанки разных сообщений чередуются:
+/// сначала по приоритету, внутри одного приоритета — по кругу.
+/// Сообщение остаётся в очереди, пока все его чанки не подтверждены.
+#[derive(Default)]
+pub struct TransmitQueue {
+    transfers: VecDeque<OutgoingTransfer>,
+}
+
+impl TransmitQueue {
+    pub fn is_empty(&self) -> bool {
+        self.transfers.is_empty()
+    }
+
+    /// Есть ли чанки, которые можно отправить прямо сейчас.
+    pub fn has_pending_chunks(&self) -> bool {
+        self.transfers.iter().any(|t| t.has_pending_chunks())
+    }
+
+    pub fn push(&mut self, transfer: OutgoingTransfer) {
+        self.transfers.push_back(transfer);
+    }
+
+    /// Возвращает `ResumeOffer`, которые пора (пере)отправить. Если получатель
+    /// так и не ответил (например, старая версия), передача идёт с начала.
+    pub fn poll_resume_offers(&mut self, now: Instant) -> Vec<ControlToSend> {
+        let mut offers = Vec::new();
+        for transfer in self.transfers.iter_mut() {
+            let Some(resume) = &mut transfer.resume else { continue };
+            if resume.last_offer_at.is_some_and(|at| now - at < RESUME_OFFER_INTERVAL) {
+                continue;
+            }
+            if resume.attempts >= RESUME_OFFER_ATTEMPTS {
+                debug!("No resume reply for message {} from {}, sending from scratch", transfer.msg_id, transfer.target_addr);
+                transfer.resume = None;
+                continue;
+            }
+            resume.attempts += 1;
+            resume.last_offer_at = Some(now);
+            offers.push(ControlToSend {
+                target_addr: transfer.target_addr,
+                key: transfer.key.clone(),
+                pattern: transfer.pattern,
+                frame: ControlFrame::ResumeOffer {
+                    msg_id: transfer.msg_id,
+                    transfer_id: resume.transfer_id.clone(),
+                    chunk_size: transfer.chunk_size as u32,
+                    data_len: transfer.data.len() as u64,
+                },
+            });
+        }
+        offers
+    }
+
+    /// Ответ на `ResumeOffer`. Возвращает число чанков, которые можно не отправлять.
+    pub fn on_resume_state(&mut self, peer: SocketAddr, msg_id: u32, have: &[(u32, u32)]) -> Option<u32> {
+        let transfer = self.transfers.iter_mut().find(|t| t.msg_id == msg_id && t.target_addr == peer)?;
+        Some(transfer.apply_resume_state(have))
+    }
+
+    /// Убирает сообщение узлу `peer` из очереди. Возвращает его, если оно ещё не было подтверждено.
+    pub fn cancel(&mut self, peer: SocketAddr, msg_id: u32) -> Option<OutgoingTransfer> {
+        let index = self.transfers.iter().position(|t| t.msg_id == msg_id && t.target_addr == peer)?;
+        self.transfers.remove(index)
+    }
+
+    /// Убирает передачи узлу `peer`, чьи чанки больше `max_chunk_size`: размер чанка
+    /// фиксирован, и после уменьшения PMTU такие чанки в пакет уже не помещаются.
+    pub fn remove_oversized(&mut self, peer: SocketAddr, max_chunk_size: usize) -> Vec<OutgoingTransfer> {
+        let (oversized, kept): (VecDeque<_>, VecDeque<_>) = std::mem::take(&mut self.transfers)
+            .into_iter()
+            .partition(|t| t.target_addr == peer && t.chunk_size > max_chunk_size);
+        self.transfers = kept;
+        oversized.into()
+    }
+
+    pub fn get(&self, peer: SocketAddr, msg_id: u32) -> Option<&OutgoingTransfer> {
+        self.transfers.iter().find(|t| t.msg_id == msg_id && t.target_addr == peer)
+    }
+
+    /// Выдаёт следующий чанк. Сообщение, отдавшее чанк, уходит в конец очереди,
+    /// поэтому сообщения одного приоритета получают полосу поровну.
+    pub fn next_chunk(&mut self, now: Instant) -> Option<ScheduledChunk> {
+        let best_priority = self
+            .transfers
+            .iter()
+            .filter(|t| t.has_pending_chunks())
+            .map(|t| t.priority)
+            .min()?;
+        let index = self
+            .transfers
+            .iter()
+            .position(|t| t.has_pending_chunks() && t.priority == best_priority)?;
+        let mut transfer = self.transfers.remove(index)?;
+
+        let is_retransmission = !transfer.retransmit.is_empty();
+        let was_first_pass_done = transfer.first_pass_done();
+        let (chunk_num, plaintext) = transfer.next_frame(now);
+        let chunk = ScheduledChunk {
+            msg_id: transfer.msg_id,
+            chunk_num,
+            target_addr: transfer.target_addr,
+            key: transfer.key.clone(),
+            pattern: transfer.pattern,
+            plaintext,
+            is_retransmission,
+            first_pass_done: !was_first_pass_done && transfer.first_pass_done(),
+        };
+        self.transfers.push_back(transfer);
+        Some(chunk)
+    }
+
+    /// Обрабатывает подтверждение от `peer`. `chunks == None` означает, что
+    /// получатель собрал сообщение целиком.
+    pub fn on_ack(&mut self, peer: SocketAddr, msg_id: u32, chunks: Option<&[u32]>, now: Instant) -> AckOutcome {
+        let mut outcome = AckOutcome::default();
+        let Some(index) = self
+            .transfers
+            .iter()
+            .position(|t| t.msg_id == msg_id && t.target_addr == peer)
+        else {
+            return outcome;
+        };
+        let transfer = &mut self.transfers[index];
+
+        let acked_chunks: Vec<u32> = match chunks {
+            Some(chunks) => chunks.to_vec(),
+            None => {
+                transfer.retransmit.clear();
+                transfer.next_chunk = transfer.total_chunks;
+                transfer.in_flight.keys().copied().collect()
+            }
+        };
+
+        let mut newest_acked: Option<Instant> = None;
+        for chunk_num in acked_chunks {
+            if let Some(sent_at) = transfer.in_flight.remove(&chunk_num) {
+                outcome.acked += 1;
+                newest_acked = Some(newest_acked.map_or(sent_at, |t| t.max(sent_at)));
+            }
+            transfer.retransmit.retain(|&c| c != chunk_num);
+        }
+        if let Some(sent_at) = newest_acked {
+            outcome.rtt_sample = Some(now - sent_at);
+            // Чанки, отправленные раньше подтверждённого, но не подтверждённые, считаем потерянными
+            let lost: Vec<u32> = transfer
+                .in_flight
+                .iter()
+                .filter(|(_, &t)| t < sent_at)
+                .map(|(&c, _)| c)
+                .collect();
+            outcome.lost = lost.len();
+            if !transfer.mark_lost(&lost) {
+                outcome.failed = self.transfers.remove(index);
+                return outcome;
+            }
+        }
+
+        if self.transfers[index].is_acknowledged() {
+            outcome.completed = self.transfers.remove(index);
+        }
+        outcome
+    }
+
+    /// Ищет чанки, не подтверждённые за `rto(peer)`, и ставит их на повтор.
+    pub fn detect_timeouts(&mut self, now: Instant, rto: impl Fn(SocketAddr) -> Duration) -> Vec<LossReport> {
+        let mut reports = Vec::new();
+        let mut index = 0;
+        while index < self.transfers.len() {
+            let transfer = &mut self.transfers[index];
+            let timeout = rto(transfer.target_addr);
+            let lost: Vec<u32> = transfer
+                .in_flight
+                .iter()
+                .filter(|(_, &sent_at)| now - sent_at >= timeout)
+                .map(|(&c, _)| c)
+                .collect();
+            if lost.is_empty() {
+                index += 1;
+                continue;
+            }
+            let target_addr = transfer.target_addr;
+            if transfer.mark_lost(&lost) {
+                reports.push(LossReport { target_addr, lost: lost.len(), failed: None });
+                index += 1;
+            } else {
+                let failed = self.transfers.remove(index);
+                reports.push(LossReport { target_addr, lost: lost.len(), failed });
+            }
+        }
+        reports
+    }
+}
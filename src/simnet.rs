@@ -0,0 +1,653 @@
+use crate::config::Config;
+use crate::network::UdpSockets;
+use crate::node::Node;
+use crate::state::{AppState, AppStats, History, MessageContent, ObfuscationPattern, SendMessagePayload, TransmitCommand};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, Notify};
+use tokio::time::Instant;
+
+/// Сколько датаграмм помещается во входящую очередь узла, как в буфере сокета.
+const INBOX_CAPACITY: usize = 4096;
+/// Заголовки IPv4 и UDP: MTU считается для IP-пакета, а узел видит только полезную нагрузку.
+const IPV4_UDP_HEADERS: usize = 28;
+const IPV6_UDP_HEADERS: usize = 48;
+/// Порт, на котором «слушают» все симулированные узлы.
+const NODE_PORT: u16 = 7070;
+/// Как часто `wait_for` проверяет состояние узла.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Условия на канале от одного узла к другому.
+#[derive(Clone, Debug)]
+pub struct LinkConditions {
+    /// Вероятность потерять датаграмму.
+    pub loss: f64,
+    /// Вероятность доставить датаграмму дважды.
+    pub duplicate: f64,
+    /// Вероятность задержать датаграмму на `reorder_delay`, чтобы её обогнали следующие.
+    pub reorder: f64,
+    pub reorder_delay: Duration,
+    pub latency: Duration,
+    /// Случайная добавка к задержке, от нуля до `jitter`.
+    pub jitter: Duration,
+    /// MTU пути для IP-пакета. Всё, что больше, теряется молча, как датаграмма с DF на маршрутизаторе.
+    pub mtu: usize,
+}
+
+impl Default for LinkConditions {
+    fn default() -> Self {
+        Self {
+            loss: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+            reorder_delay: Duration::from_millis(30),
+            latency: Duration::from_millis(10),
+            jitter: Duration::ZERO,
+            mtu: 1500,
+        }
+    }
+}
+
+/// Что сеть сделала с отправленными датаграммами.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct SimStats {
+    pub sent: u64,
+    pub delivered: u64,
+    pub lost: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    /// Больше MTU канала.
+    pub oversized: u64,
+    /// Входящая очередь получателя была полна или получателя нет.
+    pub dropped_at_receiver: u64,
+}
+
+struct Datagram {
+    payload: Vec<u8>,
+    from: SocketAddr,
+    to: SocketAddr,
+}
+
+struct Network {
+    rng: StdRng,
+    default_link: LinkConditions,
+    links: HashMap<(SocketAddr, SocketAddr), LinkConditions>,
+    inboxes: HashMap<SocketAddr, mpsc::Sender<(Vec<u8>, SocketAddr)>>,
+    /// Датаграммы в пути по времени доставки; номер отправки разводит одновременные.
+    in_flight: BTreeMap<(Instant, u64), Datagram>,
+    next_seq: u64,
+    hosts: u32,
+    stats: SimStats,
+}
+
+/// Сеть внутри процесса. Работает на часах tokio, поэтому на остановленных часах
+/// (`start_paused`) задержки не тратят реального времени. Решения о потерях, задержках
+/// и дублях зависят только от `seed` и порядка отправок.
+#[derive(Clone)]
+pub struct SimNetwork {
+    inner: Arc<Mutex<Network>>,
+    wake: Arc<Notify>,
+}
+
+impl SimNetwork {
+    /// Создаёт сеть и запускает задачу доставки; должна вызываться внутри рантайма tokio.
+    pub fn new(seed: u64, default_link: LinkConditions) -> Self {
+        let network = Network {
+            rng: StdRng::seed_from_u64(seed),
+            default_link,
+            links: HashMap::new(),
+            inboxes: HashMap::new(),
+            in_flight: BTreeMap::new(),
+            next_seq: 0,
+            hosts: 0,
+            stats: SimStats::default(),
+        };
+        let sim = Self { inner: Arc::new(Mutex::new(network)), wake: Arc::new(Notify::new()) };
+        tokio::spawn(sim.clone().deliver());
+        sim
+    }
+
+    /// Подключает новый узел с адресом `10.0.x.y:7070`.
+    pub fn endpoint(&self) -> SimEndpoint {
+        let (inbox_tx, inbox_rx) = mpsc::channel(INBOX_CAPACITY);
+        let mut network = self.lock();
+        network.hosts += 1;
+        let addr = SocketAddr::from((Ipv4Addr::from(0x0a00_0000 | network.hosts), NODE_PORT));
+        network.inboxes.insert(addr, inbox_tx);
+        SimEndpoint { addr, network: self.clone(), inbox: tokio::sync::Mutex::new(inbox_rx) }
+    }
+
+    /// Задаёт условия на канале `from` → `to`; обратное направление не меняется.
+    pub fn set_link(&self, from: SocketAddr, to: SocketAddr, conditions: LinkConditions) {
+        self.lock().links.insert((from, to), conditions);
+    }
+
+    pub fn stats(&self) -> SimStats {
+        self.lock().stats
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Network> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn send(&self, payload: &[u8], from: SocketAddr, to: SocketAddr) -> usize {
+        let mut guard = self.lock();
+        let network = &mut *guard;
+        let link = network.links.get(&(from, to)).unwrap_or(&network.default_link).clone();
+        network.stats.sent += 1;
+        let headers = if to.is_ipv4() { IPV4_UDP_HEADERS } else { IPV6_UDP_HEADERS };
+        if payload.len() + headers > link.mtu {
+            network.stats.oversized += 1;
+            return payload.len();
+        }
+        if network.rng.gen_bool(link.loss) {
+            network.stats.lost += 1;
+            return payload.len();
+        }
+        let copies = if network.rng.gen_bool(link.duplicate) {
+            network.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        let now = Instant::now();
+        for _ in 0..copies {
+            let mut delay = link.latency + link.jitter.mul_f64(network.rng.gen());
+            if network.rng.gen_bool(link.reorder) {
+                network.stats.reordered += 1;
+                delay += link.reorder_delay;
+            }
+            network.next_seq += 1;
+            let datagram = Datagram { payload: payload.to_vec(), from, to };
+            network.in_flight.insert((now + delay, network.next_seq), datagram);
+        }
+        drop(guard);
+        self.wake.notify_one();
+        payload.len()
+    }
+
+    /// Доставляет датаграммы, чьё время пришло, и спит до следующей.
+    async fn deliver(self) {
+        loop {
+            let next_at = {
+                let mut network = self.lock();
+                let now = Instant::now();
+                while let Some(entry) = network.in_flight.first_entry() {
+                    if entry.key().0 > now {
+                        break;
+                    }
+                    let Datagram { payload, from, to } = entry.remove();
+                    let delivered = network.inboxes.get(&to).is_some_and(|inbox| inbox.try_send((payload, from)).is_ok());
+                    if delivered {
+                        network.stats.delivered += 1;
+                    } else {
+                        network.stats.dropped_at_receiver += 1;
+                    }
+                }
+                network.in_flight.keys().next().map(|&(at, _)| at)
+            };
+            match next_at {
+                Some(at) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(at) => {}
+                        _ = self.wake.notified() => {}
+                    }
+                }
+                None => self.wake.notified().await,
+            }
+        }
+    }
+}
+
+/// Подключение узла к `SimNetwork`: то, чем для настоящего узла служит UDP-сокет.
+pub struct SimEndpoint {
+    addr: SocketAddr,
+    network: SimNetwork,
+    inbox: tokio::sync::Mutex<mpsc::Receiver<(Vec<u8>, SocketAddr)>>,
+}
+
+impl SimEndpoint {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Как UDP: отправка всегда успешна, а дойдёт ли датаграмма — решает сеть.
+    pub fn send_to(&self, packet: &[u8], target: SocketAddr) -> std::io::Result<usize> {
+        Ok(self.network.send(packet, self.addr, target))
+    }
+
+    /// Следующая датаграмма и её отправитель; `None`, если сеть остановлена.
+    pub async fn recv(&self) -> Option<(Vec<u8>, SocketAddr)> {
+        self.inbox.lock().await.recv().await
+    }
+}
+
+/// Несколько узлов в одном процессе на общей `SimNetwork`. Каталоги узлов создаются
+/// во временном каталоге и удаляются вместе с симуляцией.
+pub struct Simulation {
+    pub network: SimNetwork,
+    base_dir: PathBuf,
+    nodes: usize,
+}
+
+impl Simulation {
+    pub fn new(seed: u64, default_link: LinkConditions) -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let base_dir = std::env::temp_dir().join(format!("asemic-sim-{}-{}", std::process::id(), id));
+        Self { network: SimNetwork::new(seed, default_link), base_dir, nodes: 0 }
+    }
+
+    /// Запускает узел с настройками по умолчанию.
+    pub async fn spawn_node(&mut self) -> Node {
+        self.spawn_node_with(Config::default()).await
+    }
+
+    /// Запускает узел. Расшифровка всегда идёт в задачах рантайма, иначе часы не остановить.
+    pub async fn spawn_node_with(&mut self, config: Config) -> Node {
+        self.nodes += 1;
+        let config = Arc::new(Config { decrypt_in_tasks: true, decrypt_workers: config.decrypt_workers.min(2), ..config });
+        let sockets = UdpSockets::Simulated(self.network.endpoint());
+        Node::start(&self.base_dir.join(format!("node-{}", self.nodes)), sockets, config).await.expect("Failed to start simulated node")
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.base_dir).ok();
+    }
+}
+
+/// Адрес узла в симулированной сети.
+pub fn addr_of(node: &Node) -> SocketAddr {
+    node.sockets.local_addr().expect("Simulated node has an address")
+}
+
+/// Отправляет сообщение с `from`, как это делает `POST /send`, и возвращает его `msg_id`.
+pub async fn send(from: &Node, to: &Node, key: &str, content: MessageContent, redundancy: f32) -> u32 {
+    let payload = SendMessagePayload {
+        target_addr: addr_of(to).to_string(),
+        key: key.to_string(),
+        pattern: ObfuscationPattern::Starfall,
+        content,
+        redundancy,
+        ttl_secs: None,
+    };
+    from.send_message(payload, addr_of(to)).await.expect("Transmitter is running")
+}
+
+/// Ждёт, пока состояние узла не удовлетворит условию, не дольше `timeout` по часам tokio.
+pub async fn wait_for(node: &Node, timeout: Duration, condition: impl Fn(&AppState) -> bool) -> bool {
+    poll(timeout, || async { condition(&*node.state.lock().await) }).await
+}
+
+/// То же для истории сообщений узла.
+pub async fn wait_for_history(node: &Node, timeout: Duration, condition: impl Fn(&History) -> bool) -> bool {
+    poll(timeout, || async { condition(&node.state.history()) }).await
+}
+
+/// То же для статистики узла.
+pub async fn wait_for_stats(node: &Node, timeout: Duration, condition: impl Fn(&AppStats) -> bool) -> bool {
+    poll(timeout, || async { condition(&node.state.stats()) }).await
+}
+
+async fn poll<F: Future<Output = bool>>(timeout: Duration, mut check: impl FnMut() -> F) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if check().await {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files;
+    use crate::keys;
+    use crate::quarantine;
+    use crate::rotation;
+    use crate::state::{FileContent, FileStatus, KeyRotation, MessageDirection, MessageStatus, NoiseLevel, RotationPhase};
+    use crate::web;
+    use chrono::Utc;
+
+    fn received_texts(history: &History, sender: SocketAddr) -> Vec<String> {
+        history
+            .messages
+            .iter()
+            .filter(|m| m.direction == MessageDirection::Incoming && m.sender == sender)
+            .filter_map(|m| match &m.content {
+                MessageContent::Text(text) => Some(text.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn file(name: &str, len: usize, seed: u64) -> FileContent {
+        let mut data = vec![0u8; len];
+        StdRng::seed_from_u64(seed).fill(&mut data[..]);
+        FileContent { filename: name.to_string(), data, id: None, status: None }
+    }
+
+    async fn shared_key(nodes: &[&Node]) -> String {
+        let key = keys::generate_key();
+        for node in nodes {
+            node.add_key(&key, None).await;
+        }
+        key
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn network_decisions_depend_only_on_seed() {
+        async fn run(seed: u64) -> (Vec<u8>, SimStats) {
+            let link = LinkConditions { loss: 0.2, duplicate: 0.1, reorder: 0.2, jitter: Duration::from_millis(5), ..LinkConditions::default() };
+            let network = SimNetwork::new(seed, link);
+            let (a, b) = (network.endpoint(), network.endpoint());
+            for i in 0..=255u8 {
+                a.send_to(&[i], b.local_addr()).unwrap();
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let mut inbox = b.inbox.lock().await;
+            let mut received = Vec::new();
+            while let Ok((payload, _)) = inbox.try_recv() {
+                received.push(payload[0]);
+            }
+            (received, network.stats())
+        }
+        let (first, stats) = run(7).await;
+        assert_eq!(run(7).await, (first.clone(), stats));
+        assert_ne!(run(8).await.0, first);
+        assert_eq!(stats.delivered, first.len() as u64);
+        assert_eq!(stats.sent - stats.lost + stats.duplicated, stats.delivered);
+        assert!(first.windows(2).any(|w| w[0] > w[1]), "some datagrams should be reordered");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn text_is_delivered_and_acknowledged() {
+        let mut sim = Simulation::new(1, LinkConditions::default());
+        let (alice, bob) = (sim.spawn_node().await, sim.spawn_node().await);
+        let key = shared_key(&[&alice, &bob]).await;
+
+        let msg_id = send(&alice, &bob, &key, MessageContent::Text("hello".to_string()), 0.0).await;
+        assert!(wait_for_history(&bob, Duration::from_secs(5), |h| received_texts(h, addr_of(&alice)) == ["hello"]).await);
+        let message = bob.state.history().messages[0].clone();
+        assert_eq!((message.msg_id, message.decrypted_with_key), (msg_id, key));
+        // Квитанция Боба доходит обратно и продвигает исходящее сообщение Алисы
+        let bob_addr = addr_of(&bob);
+        let acknowledged = |h: &History| {
+            h.outgoing
+                .iter()
+                .any(|m| m.target == bob_addr && m.msg_id == msg_id && matches!(m.status, MessageStatus::Delivered | MessageStatus::Read))
+        };
+        assert!(wait_for_history(&alice, Duration::from_secs(5), acknowledged).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn file_survives_loss_reordering_and_duplication() {
+        let link = LinkConditions {
+            loss: 0.1,
+            duplicate: 0.05,
+            reorder: 0.1,
+            latency: Duration::from_millis(40),
+            jitter: Duration::from_millis(20),
+            ..LinkConditions::default()
+        };
+        let mut sim = Simulation::new(2, link);
+        let (alice, bob) = (sim.spawn_node().await, sim.spawn_node().await);
+        let key = shared_key(&[&alice, &bob]).await;
+        let sent = file("report.bin", 200_000, 2);
+        let sha256 = files::sha256_hex(&sent.data);
+
+        send(&alice, &bob, &key, MessageContent::File(sent), 0.0).await;
+        assert!(wait_for(&bob, Duration::from_secs(120), |s| s.pending_files.values().any(|f| f.info.sha256 == sha256)).await);
+        let stats = sim.network.stats();
+        assert!(stats.lost > 0 && stats.duplicated > 0 && stats.reordered > 0, "{:?}", stats);
+        assert!(wait_for_stats(&alice, Duration::from_secs(10), |s| s.retransmissions > 0).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn repair_chunks_rebuild_a_file_without_retransmissions() {
+        let mut sim = Simulation::new(3, LinkConditions::default());
+        let (alice, bob) = (sim.spawn_node().await, sim.spawn_node().await);
+        // Теряется каждая двадцатая датаграмма в среднем, но только от Алисы к Бобу
+        sim.network.set_link(addr_of(&alice), addr_of(&bob), LinkConditions { loss: 0.05, ..LinkConditions::default() });
+        let key = shared_key(&[&alice, &bob]).await;
+        let sent = file("photo.jpg", 100_000, 3);
+        let sha256 = files::sha256_hex(&sent.data);
+
+        send(&alice, &bob, &key, MessageContent::File(sent), 0.3).await;
+        assert!(wait_for(&bob, Duration::from_secs(60), |s| s.pending_files.values().any(|f| f.info.sha256 == sha256)).await);
+        assert!(sim.network.stats().lost > 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn packets_shrink_to_fit_a_small_path_mtu() {
+        let mut sim = Simulation::new(4, LinkConditions { mtu: 1400, ..LinkConditions::default() });
+        let (alice, bob) = (sim.spawn_node().await, sim.spawn_node().await);
+        let key = shared_key(&[&alice, &bob]).await;
+        let sent = file("archive.zip", 50_000, 4);
+        let sha256 = files::sha256_hex(&sent.data);
+
+        send(&alice, &bob, &key, MessageContent::File(sent), 0.0).await;
+        assert!(wait_for(&bob, Duration::from_secs(30), |s| s.pending_files.values().any(|f| f.info.sha256 == sha256)).await);
+        // Поиск PMTU поднимается до 1350 и упирается в 1420: зонды такого размера теряются
+        let bob_addr = addr_of(&bob);
+        assert!(wait_for(&alice, Duration::from_secs(30), |s| s.peer_links.get(&bob_addr).is_some_and(|l| l.packet_size == 1350)).await);
+        assert!(sim.network.stats().oversized > 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn packets_step_down_when_the_path_mtu_drops() {
+        let mut sim = Simulation::new(7, LinkConditions::default());
+        let (alice, bob) = (sim.spawn_node().await, sim.spawn_node().await);
+        let (alice_addr, bob_addr) = (addr_of(&alice), addr_of(&bob));
+        let key = shared_key(&[&alice, &bob]).await;
+        send(&alice, &bob, &key, MessageContent::File(file("first.bin", 50_000, 7)), 0.0).await;
+        let packet_size = |size: usize| move |s: &AppState| s.peer_links.get(&bob_addr).is_some_and(|l| l.packet_size == size);
+        assert!(wait_for(&alice, Duration::from_secs(30), packet_size(1472)).await);
+
+        // Маршрут сменился: полноразмерные пакеты теперь пропадают без следа
+        sim.network.set_link(alice_addr, bob_addr, LinkConditions { mtu: 1400, ..LinkConditions::default() });
+        let mut attempt = 0;
+        let delivered = |h: &History| received_texts(h, alice_addr).iter().any(|t| t.starts_with("after the drop"));
+        while !wait_for_history(&bob, Duration::from_secs(5), delivered).await {
+            attempt += 1;
+            assert!(attempt < 20, "no text got through after the path MTU dropped");
+            send(&alice, &bob, &key, MessageContent::Text(format!("after the drop {}", attempt)), 0.0).await;
+        }
+        assert!(wait_for(&alice, Duration::from_secs(5), packet_size(1350)).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cover_traffic_is_counted_as_noise_and_not_shown() {
+        let mut sim = Simulation::new(5, LinkConditions::default());
+        let (alice, bob) = (sim.spawn_node().await, sim.spawn_node().await);
+        let key = shared_key(&[&alice, &bob]).await;
+
+        alice.transmit_tx.send(TransmitCommand::SetNoiseLevel(NoiseLevel::Fast)).await.unwrap();
+        send(&alice, &bob, &key, MessageContent::Text("cover me".to_string()), 0.0).await;
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        // Шум шифруется тем же ключом, но внутри нет кадра протокола
+        assert!(wait_for_stats(&bob, Duration::from_secs(2), |s| s.decrypt_malformed_frames >= 40).await);
+        let history = bob.state.history();
+        assert_eq!(received_texts(&history, addr_of(&alice)), ["cover me"]);
+        assert_eq!(history.messages.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn several_peers_send_to_one_node_at_once() {
+        let link = LinkConditions { loss: 0.05, reorder: 0.1, jitter: Duration::from_millis(10), ..LinkConditions::default() };
+        let mut sim = Simulation::new(6, link);
+        let hub = sim.spawn_node().await;
+        let mut peers = Vec::new();
+        for _ in 0..4 {
+            peers.push(sim.spawn_node().await);
+        }
+        let mut keys = Vec::new();
+        for peer in &peers {
+            keys.push(shared_key(&[peer, &hub]).await);
+        }
+
+        for (i, (peer, key)) in peers.iter().zip(&keys).enumerate() {
+            send(peer, &hub, key, MessageContent::Text(format!("from peer {}", i)), 0.0).await;
+            send(peer, &hub, key, MessageContent::File(file(&format!("peer-{}.bin", i), 30_000, i as u64)), 0.0).await;
+        }
+        let all_arrived = |s: &AppState| peers.iter().all(|p| s.pending_files.values().any(|f| f.info.sender == addr_of(p)));
+        assert!(wait_for(&hub, Duration::from_secs(60), all_arrived).await);
+        let state = hub.state.lock().await;
+        let history = hub.state.history();
+        for (i, (peer, key)) in peers.iter().zip(&keys).enumerate() {
+            assert_eq!(received_texts(&history, addr_of(peer)), [format!("from peer {}", i)]);
+            let pending = state.pending_files.values().find(|f| f.info.sender == addr_of(peer)).unwrap();
+            assert_eq!(pending.info.sha256, files::sha256_hex(&file("", 30_000, i as u64).data));
+            assert!(history.messages.iter().filter(|m| m.sender == addr_of(peer)).all(|m| &m.decrypted_with_key == key));
+        }
+    }
+
+    fn file_statuses(history: &History, sender: SocketAddr) -> Vec<Option<FileStatus>> {
+        history
+            .messages
+            .iter()
+            .filter(|m| m.sender == sender)
+            .filter_map(|m| match &m.content {
+                MessageContent::File(file) => Some(file.status),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn quarantine_limits_total_bytes_and_files_per_sender() {
+        let mut sim = Simulation::new(9, LinkConditions::default());
+        let (alice, carol) = (sim.spawn_node().await, sim.spawn_node().await);
+        let config = Config { quarantine_quota_bytes: 50_000, max_pending_files_per_sender: 2, ..Config::default() };
+        let bob = sim.spawn_node_with(config).await;
+        let key = shared_key(&[&alice, &bob, &carol]).await;
+        let (alice_addr, carol_addr) = (addr_of(&alice), addr_of(&carol));
+        let pending_from = |s: &AppState, sender: SocketAddr| s.pending_files.values().filter(|f| f.info.sender == sender).count();
+
+        // Третий файл от одного узла не попадает в карантин, пока два ждут решения
+        for i in 0..3 {
+            send(&alice, &bob, &key, MessageContent::File(file(&format!("{}.bin", i), 10_000, i)), 0.0).await;
+        }
+        assert!(wait_for_history(&bob, Duration::from_secs(10), |h| file_statuses(h, alice_addr).len() == 3).await);
+        assert!(wait_for(&bob, Duration::from_secs(5), |s| pending_from(s, alice_addr) == 2).await);
+        let statuses = file_statuses(&bob.state.history(), alice_addr);
+        assert_eq!(statuses.iter().filter(|s| **s == Some(FileStatus::Rejected)).count(), 1);
+
+        // 20 000 байт уже заняты: 40 000 не помещаются в 50 000, а 20 000 — да
+        send(&carol, &bob, &key, MessageContent::File(file("big.bin", 40_000, 3)), 0.0).await;
+        assert!(wait_for_history(&bob, Duration::from_secs(10), |h| file_statuses(h, carol_addr) == [Some(FileStatus::Rejected)]).await);
+        send(&carol, &bob, &key, MessageContent::File(file("small.bin", 20_000, 4)), 0.0).await;
+        assert!(wait_for(&bob, Duration::from_secs(10), |s| pending_from(s, carol_addr) == 1).await);
+        assert_eq!(bob.state.lock().await.quarantine_usage.bytes, 40_000);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_accepts_do_not_exceed_the_downloads_quota() {
+        let mut sim = Simulation::new(10, LinkConditions::default());
+        let alice = sim.spawn_node().await;
+        let config = Config { downloads_quota_bytes: 15_000, ..Config::default() };
+        let bob = sim.spawn_node_with(config.clone()).await;
+        let key = shared_key(&[&alice, &bob]).await;
+        for i in 0..2 {
+            send(&alice, &bob, &key, MessageContent::File(file(&format!("{}.bin", i), 10_000, i)), 0.0).await;
+        }
+        assert!(wait_for(&bob, Duration::from_secs(10), |s| s.pending_files.len() == 2).await);
+
+        let ids: Vec<_> = bob.state.lock().await.pending_files.keys().copied().collect();
+        let (first, second) = tokio::join!(
+            quarantine::accept_file(&bob.state, &bob.ws_tx, &config, ids[0]),
+            quarantine::accept_file(&bob.state, &bob.ws_tx, &config, ids[1]),
+        );
+        assert_eq!(first.is_ok() as u8 + second.is_ok() as u8, 1);
+        assert!(matches!(first.err().or(second.err()), Some(quarantine::AcceptError::QuotaExceeded)));
+        let state = bob.state.lock().await;
+        assert_eq!((state.downloads_used, state.pending_files.len()), (10_000, 1));
+    }
+
+    /// Предлагает ротацию так же, как `POST /keys/rotate`, и ждёт, пока предложение дойдёт до узла.
+    async fn propose_rotation(from: &Node, to: &Node, key: &str) -> KeyRotation {
+        let new_key = keys::generate_key();
+        let activate_at = Utc::now() + chrono::Duration::seconds(rotation::DEFAULT_ACTIVATION_DELAY_SECS as i64);
+        let msg_id = rand::thread_rng().gen();
+        let sent_as = rotation::Proposal { peer: addr_of(to), msg_id, pattern: ObfuscationPattern::Starfall };
+        let proposal = rotation::plan(key, new_key.clone(), sent_as, activate_at, rotation::DEFAULT_GRACE_SECS, true).unwrap();
+        assert!(rotation::register(&mut *from.state.lock().await, &from.ws_tx, proposal.clone()));
+        let payload = SendMessagePayload {
+            target_addr: addr_of(to).to_string(),
+            key: key.to_string(),
+            pattern: ObfuscationPattern::Starfall,
+            content: MessageContent::KeyRotation { new_key, activate_at, grace_secs: rotation::DEFAULT_GRACE_SECS },
+            redundancy: 0.0,
+            ttl_secs: Some(0),
+        };
+        assert!(web::queue_message(&from.state, &from.transmit_tx, &from.ws_tx, payload, addr_of(to), msg_id).await);
+        assert!(wait_for_history(from, Duration::from_secs(5), |h| outgoing_status(h, &proposal) == Some(MessageStatus::Delivered)).await);
+        proposal
+    }
+
+    fn outgoing_status(history: &History, proposal: &KeyRotation) -> Option<MessageStatus> {
+        history.outgoing.iter().find(|m| m.target == proposal.peer && m.msg_id == proposal.msg_id).map(|m| m.status)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn key_rotation_waits_for_the_peer_to_accept() {
+        let mut sim = Simulation::new(7, LinkConditions::default());
+        let (alice, bob) = (sim.spawn_node().await, sim.spawn_node().await);
+        let key = shared_key(&[&alice, &bob]).await;
+        let proposal = propose_rotation(&alice, &bob, &key).await;
+
+        // Доставленное предложение у Боба ждёт решения, и новый ключ ещё не принимается
+        let state = bob.state.lock().await;
+        assert_eq!(state.rotations.iter().map(|r| r.phase).collect::<Vec<_>>(), [RotationPhase::Pending]);
+        assert!(!state.keys.contains(&proposal.new_key));
+        drop(state);
+
+        // Без согласия к моменту активации ротация снимается у обоих, старый ключ остаётся
+        for node in [&alice, &bob] {
+            let mut state = node.state.lock().await;
+            rotation::advance(&mut state, &node.state.history(), &node.ws_tx, proposal.activate_at);
+            assert!(state.rotations.is_empty());
+            assert!(state.keys.contains(&key) && !state.keys.contains(&proposal.new_key));
+            assert_eq!(rotation::current_key(&state, addr_of(&bob), &key), key);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn accepted_key_rotation_replaces_the_key_only_for_that_peer() {
+        let mut sim = Simulation::new(8, LinkConditions::default());
+        let (alice, bob, carol) = (sim.spawn_node().await, sim.spawn_node().await, sim.spawn_node().await);
+        let key = shared_key(&[&alice, &bob, &carol]).await;
+        let (bob_addr, carol_addr) = (addr_of(&bob), addr_of(&carol));
+        send(&alice, &carol, &key, MessageContent::Text("still on the old key".to_string()), 0.0).await;
+        let proposal = propose_rotation(&alice, &bob, &key).await;
+
+        let pending_id = bob.state.lock().await.rotations[0].id;
+        let accepted = rotation::accept(&mut *bob.state.lock().await, &bob.state.history(), &bob.ws_tx, pending_id, Utc::now()).unwrap();
+        assert!(bob.state.lock().await.keys.contains(&proposal.new_key));
+        bob.transmit_tx.send(rotation::consent_receipt(&accepted)).await.unwrap();
+        assert!(wait_for_history(&alice, Duration::from_secs(5), |h| outgoing_status(h, &proposal) == Some(MessageStatus::Read)).await);
+
+        // Боб получает новый ключ, Кэрол — по-прежнему старый, и после льготного периода он остаётся
+        let mut state = alice.state.lock().await;
+        rotation::advance(&mut state, &alice.state.history(), &alice.ws_tx, proposal.activate_at);
+        assert_eq!(state.rotations[0].phase, RotationPhase::Active);
+        assert_eq!(rotation::current_key(&state, bob_addr, &key), proposal.new_key);
+        assert_eq!(rotation::current_key(&state, carol_addr, &key), key);
+        rotation::advance(&mut state, &alice.state.history(), &alice.ws_tx, proposal.retire_at);
+        assert!(state.keys.contains(&key));
+        assert_eq!(rotation::current_key(&state, bob_addr, &key), proposal.new_key);
+    }
+}
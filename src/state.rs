@@ -1,16 +1,24 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::net::SocketAddr;
-use tokio::sync::Mutex;
+use std::net::{IpAddr, SocketAddr};
+use tokio::sync::{Mutex, MutexGuard};
 use uuid::Uuid;
-use std::sync::Arc;
+use std::sync::{Arc, PoisonError};
 use std::path::PathBuf;
+use crate::audit::{key_fingerprint, AuditEvent, AuditLog, KeySource};
+use crate::keys::KeyRing;
+use crate::protocol::ControlFrame;
+use tokio::sync::broadcast;
+use tracing::info;
 
 // ИСПРАВЛЕНИЕ: Добавлены необходимые директивы.
-#[derive(Serialize, Deserialize, Clone, Debug, Copy, PartialEq)]
+/// На шифрование паттерн не влияет, поэтому из пакета его не узнать:
+/// отправитель кладёт свой в `MessageEnvelope`.
+#[derive(Serialize, Deserialize, Clone, Debug, Copy, PartialEq, Default)]
 pub enum ObfuscationPattern {
     Sunshine,
+    #[default]
     Starfall,
 }
 
@@ -22,6 +30,50 @@ pub enum NoiseLevel {
     Fast,
 }
 
+/// Статус исходящего сообщения. Порядок вариантов важен: статус может только расти.
+/// `Cancelled` и `Failed` — конечные статусы, их выставляет только передатчик.
+#[derive(Serialize, Deserialize, Clone, Debug, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MessageStatus {
+    Queued,
+    Sent,
+    Delivered,
+    Read,
+    Cancelled,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Copy, PartialEq)]
+pub enum MessageDirection {
+    Incoming,
+    Outgoing,
+}
+
+/// Судьба входящего файла: сначала карантин, затем решение пользователя или правил.
+#[derive(Serialize, Deserialize, Clone, Debug, Copy, PartialEq)]
+pub enum FileStatus {
+    Quarantined,
+    Accepted,
+    Rejected,
+    /// Принят, но позже удалён из библиотеки.
+    Deleted,
+}
+
+/// Сжатие содержимого сообщения перед шифрованием. Выбирается для каждого ключа
+/// и по умолчанию выключено: размер сжатых данных зависит от содержимого.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    Off,
+    Deflate,
+    Zstd,
+}
+
+impl Compression {
+    pub fn is_off(&self) -> bool {
+        *self == Compression::Off
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct FileContent {
     pub filename: String,
@@ -29,6 +81,9 @@ pub struct FileContent {
     pub data: Vec<u8>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub id: Option<Uuid>,
+    /// Только для UI: по сети не передаётся.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<FileStatus>,
 }
 
 // ИСПРАВЛЕНИЕ: Добавлены необходимые директивы.
@@ -37,12 +92,80 @@ pub struct FileContent {
 pub enum MessageContent {
     Text(String),
     File(FileContent),
+    /// Ответ на сообщение с идентификатором `reply_to`.
+    Reply { reply_to: u32, text: String },
+    /// Новый текст своего сообщения `target`.
+    Edit { target: u32, text: String },
+    /// Удаление своего сообщения `target` у всех участников.
+    Delete { target: u32 },
+    /// Реакция на сообщение `target`; `remove` снимает её.
+    Reaction {
+        target: u32,
+        emoji: String,
+        #[serde(default)]
+        remove: bool,
+    },
+    /// Собеседник начал или перестал печатать.
+    Typing { active: bool },
+    /// Предложение сменить ключ, которым зашифровано это сообщение, на `new_key`
+    /// с момента `activate_at`; старый ключ принимается ещё `grace_secs` секунд.
+    KeyRotation {
+        new_key: String,
+        activate_at: DateTime<Utc>,
+        grace_secs: u32,
+    },
+}
+
+impl MessageContent {
+    /// Действия над другими сообщениями: в историю как отдельные сообщения не попадают.
+    pub fn is_action(&self) -> bool {
+        matches!(
+            self,
+            MessageContent::Edit { .. } | MessageContent::Delete { .. } | MessageContent::Reaction { .. } | MessageContent::Typing { .. }
+        )
+    }
+}
+
+/// Реакция на сообщение.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Reaction {
+    pub emoji: String,
+    /// Кто поставил; `None` — мы сами.
+    pub from: Option<SocketAddr>,
 }
 
 // --- Структуры для API-запросов (перенесены из web.rs) ---
 
 #[derive(Deserialize)]
-pub struct AddKeyPayload { pub key: String }
+pub struct AddKeyPayload {
+    pub key: String,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct GenerateKeyPayload {
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ExportKeyPayload {
+    pub key: String,
+}
+
+#[derive(Deserialize)]
+pub struct ImportKeyPayload {
+    /// Строка экспорта `ASEMIC-KEY-V1:...`.
+    pub text: String,
+}
+
+#[derive(Serialize)]
+pub struct KeyExportResponse {
+    pub key: String,
+    pub label: String,
+    pub text: String,
+}
 
 #[derive(Deserialize)]
 pub struct SendMessagePayload {
@@ -50,6 +173,41 @@ pub struct SendMessagePayload {
     pub key: String,
     pub pattern: ObfuscationPattern,
     pub content: MessageContent,
+    /// Доля ремонтных FEC-чанков (0.25 = +25% пакетов). По умолчанию FEC выключен.
+    #[serde(default)]
+    pub redundancy: f32,
+    /// Через сколько секунд сообщение исчезнет у обеих сторон.
+    /// `None` — по настройке ключа, `Some(0)` — не исчезает.
+    #[serde(default)]
+    pub ttl_secs: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct SetKeyCompressionPayload {
+    pub key: String,
+    pub compression: Compression,
+}
+
+#[derive(Deserialize)]
+pub struct SetKeyTtlPayload {
+    pub key: String,
+    /// `None` или 0 — сообщения с этим ключом не исчезают.
+    pub ttl_secs: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct RotateKeyPayload {
+    pub key: String,
+    /// Собеседник, с которым делим ключ: ему уходит предложение.
+    pub target_addr: String,
+    pub pattern: ObfuscationPattern,
+    /// Если не задан, генерируется случайный.
+    #[serde(default)]
+    pub new_key: Option<String>,
+    #[serde(default)]
+    pub activate_in_secs: Option<u32>,
+    #[serde(default)]
+    pub grace_secs: Option<u32>,
 }
 
 #[derive(Deserialize)]
@@ -57,6 +215,24 @@ pub struct SetNoisePayload {
     pub level: NoiseLevel,
 }
 
+#[derive(Serialize)]
+pub struct SendMessageResponse {
+    pub msg_id: u32,
+}
+
+/// Параметры `GET /audit`.
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    /// Только записи с номером не меньше этого.
+    #[serde(default)]
+    pub since: Option<u64>,
+    /// Тип события, например `key_added`.
+    #[serde(default)]
+    pub event: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
 
 // --- Остальной код файла без изменений ---
 
@@ -75,20 +251,167 @@ mod base64_serde {
 #[derive(Serialize, Clone, Debug)]
 pub struct DecryptedMessage {
     pub id: Uuid,
+    pub direction: MessageDirection,
+    /// Идентификатор сообщения на проводе, на него ссылаются квитанции.
+    pub msg_id: u32,
     pub timestamp: DateTime<Utc>,
     pub sender: SocketAddr,
     pub content: MessageContent,
     pub decrypted_with_key: String,
     pub decrypted_with_pattern: ObfuscationPattern,
+    pub read: bool,
+    pub edited: bool,
+    /// Удалено отправителем: содержимое стёрто.
+    pub deleted: bool,
+    pub reactions: Vec<Reaction>,
+    /// Когда сообщение и его файл будут стёрты (TTL задаёт отправитель).
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Входящий файл в карантине, ожидающий решения пользователя.
+pub struct PendingFile {
+    pub info: PendingFileInfo,
+    /// Путь внутри каталога карантина; имя файла — его id, а не имя от отправителя.
+    pub path: PathBuf,
+}
+
+/// То, что UI показывает в запросе на приём файла.
+#[derive(Serialize, Clone, Debug)]
+pub struct PendingFileInfo {
+    pub id: Uuid,
+    /// Уже очищенное имя, под которым файл будет сохранён.
+    pub filename: String,
+    pub size: u64,
+    pub sha256: String,
+    pub sender: SocketAddr,
+    pub received_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Принятый файл в каталоге загрузок. Список таких файлов хранится на диске
+/// рядом с ними и переживает перезапуск.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReceivedFile {
+    pub id: Uuid,
+    /// Имя внутри каталога загрузок (может отличаться от присланного суффиксом " (n)").
+    pub filename: String,
+    pub size: u64,
+    pub sha256: String,
+    pub mime: String,
+    pub sender: SocketAddr,
+    pub received_at: DateTime<Utc>,
+    /// Файл из исчезающего сообщения удаляется вместе с ним.
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Правила приёма входящих файлов. Начальные значения берутся из `Config`,
+/// менять их можно через `/config/files`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FilePolicy {
+    /// Файлы от этих адресов принимаются без вопроса (если не больше `auto_accept_max_bytes`).
+    #[serde(default)]
+    pub auto_accept_from: Vec<IpAddr>,
+    pub auto_accept_max_bytes: u64,
+    /// Файлы больше этого размера отбрасываются сразу после сборки.
+    pub max_file_bytes: u64,
+    /// Если список не пуст, принимаются только эти расширения.
+    #[serde(default)]
+    pub allowed_extensions: Vec<String>,
+    #[serde(default)]
+    pub denied_extensions: Vec<String>,
+}
+
+/// Сообщение, которое мы отправили сами. Хранится в истории рядом с входящими.
+#[derive(Serialize, Clone, Debug)]
+pub struct OutgoingMessage {
+    pub id: Uuid,
+    pub direction: MessageDirection,
+    pub msg_id: u32,
+    pub timestamp: DateTime<Utc>,
+    pub target: SocketAddr,
+    /// Содержимое без тела файла, как и у входящих сообщений.
+    pub content: MessageContent,
+    pub sent_with_key: String,
+    pub sent_with_pattern: ObfuscationPattern,
+    pub status: MessageStatus,
+    pub edited: bool,
+    pub deleted: bool,
+    pub reactions: Vec<Reaction>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum RotationPhase {
+    /// Узел предложил новый ключ; ждём, примет ли его пользователь. Ключ ещё не принимается.
+    Pending,
+    /// Новый ключ уже принимается, но исходящие ещё идут старым.
+    Proposed,
+    /// Исходящие идут новым ключом, старый пока принимается.
+    Active,
+    /// Старый ключ удалён.
+    Retired,
+    /// Ротация отклонена или не состоялась; остаётся старый ключ.
+    Aborted,
+}
+
+/// Смена общего ключа, согласованная внутри канала.
+#[derive(Serialize, Clone, Debug)]
+pub struct KeyRotation {
+    pub id: Uuid,
+    /// Сообщение с предложением и узор, которым оно пришло: на него отвечаем квитанцией о согласии.
+    pub msg_id: u32,
+    pub pattern: ObfuscationPattern,
+    pub old_key: String,
+    pub new_key: String,
+    pub peer: SocketAddr,
+    pub activate_at: DateTime<Utc>,
+    /// Конец льготного периода: после него старый ключ удаляется.
+    pub retire_at: DateTime<Utc>,
+    pub initiated_locally: bool,
+    pub phase: RotationPhase,
 }
 
 #[derive(Debug)]
 pub enum TransmitCommand {
     SendMessage {
+        msg_id: u32,
         target_addr: SocketAddr,
         key: String,
         pattern: ObfuscationPattern,
         content: MessageContent,
+        redundancy: f32,
+        compression: Compression,
+        ttl_secs: Option<u32>,
+    },
+    SendControl {
+        target_addr: SocketAddr,
+        key: String,
+        pattern: ObfuscationPattern,
+        frame: ControlFrame,
+    },
+    /// Убрать из очереди наше сообщение `msg_id` узлу `target`.
+    CancelMessage {
+        target: SocketAddr,
+        msg_id: u32,
+    },
+    /// Получатель подтвердил чанки сообщения; `None` — сообщение собрано целиком.
+    PeerAck {
+        peer: SocketAddr,
+        msg_id: u32,
+        chunks: Option<Vec<u32>>,
+    },
+    /// Узел ответил на PMTU-зонд.
+    ProbeAck {
+        peer: SocketAddr,
+        probe_id: u32,
+        size: usize,
+    },
+    /// Узел сообщил, какие чанки возобновляемой передачи у него уже есть.
+    ResumeState {
+        peer: SocketAddr,
+        msg_id: u32,
+        have: Vec<(u32, u32)>,
     },
     SetNoiseLevel(NoiseLevel),
 }
@@ -99,45 +422,262 @@ pub enum WsNotification {
     FullState {
         keys: Vec<String>,
         messages: Vec<DecryptedMessage>,
-        stats: AppStats,
+        outgoing: Vec<OutgoingMessage>,
+        pending_files: Vec<PendingFileInfo>,
+        files: Vec<ReceivedFile>,
+        key_compression: HashMap<String, Compression>,
+        key_ttl: HashMap<String, u32>,
+        key_labels: HashMap<String, String>,
+        rotations: Vec<KeyRotation>,
+        stats: Box<AppStats>,
     },
     NewMessage(DecryptedMessage),
+    NewOutgoingMessage(OutgoingMessage),
     NoisePacket {
         sender: SocketAddr,
         size: usize,
     },
     KeyUpdate(Vec<String>),
+    /// Настройки сжатия по ключам (ключи без записи не сжимаются).
+    KeyCompressionUpdate(HashMap<String, Compression>),
+    /// TTL сообщений по ключам, в секундах.
+    KeyTtlUpdate(HashMap<String, u32>),
+    /// Метки ключей для отображения.
+    KeyLabelsUpdate(HashMap<String, String>),
+    /// Ротация ключа предложена или перешла в следующую фазу.
+    KeyRotationUpdate(KeyRotation),
     StatsUpdate(AppStats),
+    /// Статус нашего сообщения `msg_id` узлу `target`: `msg_id` сам по себе не уникален.
+    MessageStatus {
+        target: SocketAddr,
+        msg_id: u32,
+        status: MessageStatus,
+    },
+    /// Текст сообщения (входящего или нашего) изменён; `id` — локальный идентификатор записи.
+    MessageEdited {
+        id: Uuid,
+        content: MessageContent,
+    },
+    MessageDeleted {
+        id: Uuid,
+    },
+    /// Истёк TTL: запись стёрта из истории.
+    MessageExpired {
+        id: Uuid,
+    },
+    ReactionsUpdated {
+        id: Uuid,
+        reactions: Vec<Reaction>,
+    },
+    Typing {
+        peer: SocketAddr,
+        active: bool,
+    },
+    /// Пришёл файл, ждём решения пользователя.
+    FileOffer(PendingFileInfo),
+    /// Файл принят (и сохранён под `filename`) или отклонён.
+    FileResolved {
+        id: Uuid,
+        status: FileStatus,
+        filename: Option<String>,
+    },
+    /// Файл появился в библиотеке.
+    FileStored(ReceivedFile),
+    FileDeleted {
+        id: Uuid,
+    },
+    TransmitProgress {
+        target: SocketAddr,
+        msg_id: u32,
+        chunks_sent: u32,
+        total_chunks: u32,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default, Copy)]
 pub struct AppStats {
     pub packets_sent: u64,
     pub packets_received: u64,
+    /// Байт отправлено во всех пакетах, включая служебные и шум.
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
     pub noise_packets_sent: u64,
     pub messages_decrypted: u64,
+    pub retransmissions: u64,
+    /// Исходящая скорость за последний интервал, байт в секунду.
+    pub throughput_bps: f64,
+    /// Средний сглаженный RTT по узлам, которым мы что-то отправляли.
+    pub rtt_ms: f64,
+    /// Входящие пакеты, не подошедшие ни к одному ключу.
+    pub noise_packets_received: u64,
+    /// Расшифрованные чанки сообщений и служебные кадры.
+    pub decrypted_chunks: u64,
+    pub decrypted_control_frames: u64,
+    /// Подсказка ключа совпала, но тег AEAD — нет.
+    pub decrypt_auth_failures: u64,
+    /// Пакет расшифрован, но внутри не кадр протокола.
+    pub decrypt_malformed_frames: u64,
+    /// Собранные сообщения, которые не удалось декодировать или разобрать.
+    pub malformed_messages: u64,
+    /// Недособранные сообщения и память под их чанки.
+    pub reassembly_sessions: u64,
+    pub reassembly_buffer_bytes: u64,
+    /// Память свободных буферов приёма.
+    pub receive_pool_bytes: u64,
+    /// Пакеты, ждущие расшифровки, по всем потокам.
+    pub decrypt_queue_depth: u64,
+    /// Расшифрованные пакеты, ждущие сборки.
+    pub reassembly_queue_depth: u64,
+    /// Сколько раз стадия конвейера ждала, пока освободится место в очереди следующей.
+    pub backpressure_stalls: u64,
+    /// Входящие датаграммы, не поместившиеся в буфер приёма.
+    pub truncated_datagrams: u64,
+    /// Входящие датаграммы длиннее любого нашего пакета, включая обрезанные.
+    pub oversized_datagrams: u64,
+    /// Датаграммы, отброшенные ядром из-за переполненного буфера сокета;
+    /// `None`, если платформа этого не сообщает.
+    pub kernel_drops: Option<u64>,
+}
+
+/// Состояние канала до конкретного узла по данным контроля скорости.
+#[derive(Serialize, Clone, Debug)]
+pub struct PeerLinkStats {
+    pub rtt_ms: Option<f64>,
+    pub rate_pps: f64,
+    /// Размер пакетов к узлу, найденный PMTU-зондированием.
+    pub packet_size: usize,
+    pub packets_acked: u64,
+    pub packets_lost: u64,
+}
+
+/// Место, занятое карантином: файлы, ожидающие решения, и те, что ещё записываются.
+/// Резервируется до записи на диск, см. `quarantine::admit`.
+#[derive(Default, Debug)]
+pub struct QuarantineUsage {
+    pub bytes: u64,
+    /// Сколько файлов в карантине от каждого узла; порт мог смениться, поэтому по IP.
+    pub per_sender: HashMap<IpAddr, usize>,
 }
 
 pub struct AppState {
     pub keys: Vec<String>,
-    pub messages: Vec<DecryptedMessage>,
-    pub received_files: HashMap<Uuid, (String, Vec<u8>)>,
-    pub reassembly_buffer: HashMap<(SocketAddr, u32), HashMap<u32, Vec<u8>>>,
+    /// Сжатие для исходящих сообщений по ключу; ключей без записи это не касается.
+    pub key_compression: HashMap<String, Compression>,
+    /// TTL исходящих сообщений по ключу, в секундах, если отправитель не задал свой.
+    pub key_ttl: HashMap<String, u32>,
+    /// Метки ключей, которые видит пользователь; переносятся при экспорте.
+    pub key_labels: HashMap<String, String>,
+    /// Незавершённые ротации ключей.
+    pub rotations: Vec<KeyRotation>,
+    /// Метаданные принятых файлов; содержимое читается с диска.
+    pub received_files: HashMap<Uuid, ReceivedFile>,
+    pub pending_files: HashMap<Uuid, PendingFile>,
+    pub quarantine_usage: QuarantineUsage,
     pub downloads_path: PathBuf,
-    pub stats: AppStats,
+    /// Сколько байт занимает каталог загрузок, вместе с файлами, которые сейчас туда переносятся.
+    /// Резервируется до переноса, чтобы одновременные приёмы не превысили квоту.
+    pub downloads_used: u64,
+    pub quarantine_path: PathBuf,
+    pub file_policy: FilePolicy,
+    pub peer_links: HashMap<SocketAddr, PeerLinkStats>,
+    pub audit: AuditLog,
+    /// Копия `keys` для потоков расшифровки; обновляется через `keys_changed`.
+    pub key_ring: Arc<KeyRing>,
 }
 
 impl AppState {
-    pub fn new(downloads_path: PathBuf) -> Self {
+    pub fn new(downloads_path: PathBuf, quarantine_path: PathBuf, file_policy: FilePolicy, audit: AuditLog, key_ring: Arc<KeyRing>) -> Self {
         Self {
             keys: Vec::new(),
-            messages: Vec::new(),
+            key_compression: HashMap::new(),
+            key_ttl: HashMap::new(),
+            key_labels: HashMap::new(),
+            rotations: Vec::new(),
             received_files: HashMap::new(),
-            reassembly_buffer: HashMap::new(),
+            pending_files: HashMap::new(),
+            quarantine_usage: QuarantineUsage::default(),
             downloads_path,
-            stats: AppStats::default(),
+            downloads_used: 0,
+            quarantine_path,
+            file_policy,
+            peer_links: HashMap::new(),
+            audit,
+            key_ring,
+        }
+    }
+
+    /// Добавляет ключ с необязательной меткой и записывает это в журнал аудита.
+    /// Общий путь для `POST /keys`, генерации, импорта и `Node::add_key`.
+    pub fn add_key(&mut self, ws_tx: &broadcast::Sender<WsNotification>, key: String, label: Option<&str>, source: KeySource) {
+        if let Some(label) = label.map(crate::keys::normalize_label).filter(|l| !l.is_empty()) {
+            self.key_labels.insert(key.clone(), label);
+            ws_tx.send(WsNotification::KeyLabelsUpdate(self.key_labels.clone())).ok();
+        }
+        if !self.keys.contains(&key) {
+            info!("Added new key {}", key_fingerprint(&key));
+            self.audit.record(AuditEvent::KeyAdded { key: key_fingerprint(&key), source });
+            self.keys.push(key);
+        }
+        self.keys_changed(ws_tx);
+    }
+
+    /// Вызывается после любого изменения `keys`: обновляет ключи расшифровки и UI.
+    pub fn keys_changed(&self, ws_tx: &broadcast::Sender<WsNotification>) {
+        self.key_ring.publish(&self.keys);
+        ws_tx.send(WsNotification::KeyUpdate(self.keys.clone())).ok();
+    }
+}
+
+/// История переписки. Её меняют сборка сообщений, передатчик и квитанции,
+/// поэтому она не под общей блокировкой `AppState`.
+#[derive(Default)]
+pub struct History {
+    pub messages: Vec<DecryptedMessage>,
+    /// История отправленных нами сообщений.
+    pub outgoing: Vec<OutgoingMessage>,
+}
+
+impl History {
+    /// Повышает статус исходящего сообщения узлу `target`. Возвращает `true`, если статус изменился
+    /// (квитанции могут приходить повторно или не по порядку). `msg_id` выбирает отправитель,
+    /// поэтому без адреса чужая квитанция могла бы сменить статус сообщения другому узлу.
+    pub fn advance_outgoing_status(&mut self, target: SocketAddr, msg_id: u32, status: MessageStatus) -> bool {
+        match self.outgoing.iter_mut().rev().find(|m| m.target == target && m.msg_id == msg_id) {
+            Some(message) if message.status < status => {
+                message.status = status;
+                true
+            }
+            _ => false,
         }
     }
 }
 
-pub type SharedState = Arc<Mutex<AppState>>;
\ No newline at end of file
+/// Общее состояние узла. История и статистика меняются на каждом сообщении и пакете,
+/// поэтому у них свои короткие блокировки: их никогда не держат через `.await`.
+/// Остальное — ключи, ротации, файлы, правила — под асинхронной блокировкой `lock()`.
+/// Если нужны обе, `AppState` берётся первым.
+pub struct Shared {
+    app: Mutex<AppState>,
+    history: std::sync::Mutex<History>,
+    stats: std::sync::Mutex<AppStats>,
+}
+
+impl Shared {
+    pub fn new(app: AppState) -> Self {
+        Self { app: Mutex::new(app), history: Default::default(), stats: Default::default() }
+    }
+
+    pub async fn lock(&self) -> MutexGuard<'_, AppState> {
+        self.app.lock().await
+    }
+
+    pub fn history(&self) -> std::sync::MutexGuard<'_, History> {
+        self.history.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn stats(&self) -> std::sync::MutexGuard<'_, AppStats> {
+        self.stats.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub type SharedState = Arc<Shared>;
\ No newline at end of file
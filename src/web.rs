@@ -1,12 +1,28 @@
 // ИСПРАВЛЕНИЕ: Теперь импортируем всё необходимое из state.rs, где оно централизованно определено.
 use crate::state::{
     SharedState, TransmitCommand, WsNotification, AddKeyPayload,
-    SendMessagePayload, SetNoisePayload
+    SendMessagePayload, SetNoisePayload, SendMessageResponse, MessageStatus,
+    OutgoingMessage, MessageDirection, MessageContent, FileContent, FilePolicy,
+    ReceivedFile, SetKeyCompressionPayload, SetKeyTtlPayload, RotateKeyPayload,
+    GenerateKeyPayload, ExportKeyPayload, ImportKeyPayload, KeyExportResponse, AuditQuery, RotationPhase
 };
+use crate::protocol::ControlFrame;
+use crate::config::Config;
+use crate::conversation::{self, Author};
+use crate::expiry;
+use crate::files;
+use crate::keys;
+use crate::audit::{self, key_fingerprint, AuditEvent, AuditFilter, KeySource};
+use crate::library;
+use crate::metrics;
+use crate::network;
+use crate::quarantine::{self, AcceptError};
+use crate::rotation::{self, DecisionError};
 use axum::{
+    body::Body,
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        Path, State,
+        Path, Query, State,
     },
     http::{header, StatusCode},
     response::{IntoResponse, Response},
@@ -14,11 +30,13 @@ use axum::{
     Json, Router,
 };
 // Убрали serde::Deserialize, так как структуры теперь в state.rs
-use tokio::net::lookup_host;
 use tokio::sync::{broadcast, mpsc};
+use tokio_util::io::ReaderStream;
 use tower_http::services::ServeDir;
+use rand::Rng;
 use tracing::{info, warn};
 use uuid::Uuid;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 // ИСПРАВЛЕНИЕ: Структуры `AddKeyPayload`, `SendMessagePayload`, `SetNoisePayload` удалены отсюда,
@@ -28,6 +46,7 @@ type WebState = (
     SharedState,
     mpsc::Sender<TransmitCommand>,
     broadcast::Sender<WsNotification>,
+    Arc<Config>,
 );
 
 pub async fn run_web_server(
@@ -35,8 +54,9 @@ pub async fn run_web_server(
     transmit_sender: mpsc::Sender<TransmitCommand>,
     ws_tx: broadcast::Sender<WsNotification>,
     serve_dir: ServeDir,
+    config: Arc<Config>,
 ) {
-    let app_state: WebState = (state, transmit_sender, ws_tx);
+    let app_state: WebState = (state, transmit_sender, ws_tx, config);
 
     let app = Router::new()
         .nest_service("/static", serve_dir)
@@ -44,9 +64,27 @@ pub async fn run_web_server(
         .route("/ws", get(websocket_handler))
         .route("/keys", post(add_key_handler))
         .route("/keys", delete(remove_key_handler))
+        .route("/keys/compression", post(set_key_compression_handler))
+        .route("/keys/ttl", post(set_key_ttl_handler))
+        .route("/keys/rotate", post(rotate_key_handler))
+        .route("/keys/rotations/:rotation_id/accept", post(accept_rotation_handler))
+        .route("/keys/rotations/:rotation_id/decline", post(decline_rotation_handler))
+        .route("/keys/generate", post(generate_key_handler))
+        .route("/keys/export", post(export_key_handler))
+        .route("/keys/export/qr", post(export_key_qr_handler))
+        .route("/keys/import", post(import_key_handler))
         .route("/send", post(send_message_handler))
+        .route("/send/:id/cancel", post(cancel_message_handler))
+        .route("/messages/:message_id/read", post(mark_read_handler))
         .route("/download/:file_id", get(download_file_handler))
+        .route("/files", get(list_files_handler))
+        .route("/files/:file_id", delete(delete_file_handler))
+        .route("/files/:file_id/accept", post(accept_file_handler))
+        .route("/files/:file_id/reject", post(reject_file_handler))
         .route("/config/noise", post(set_noise_handler))
+        .route("/config/files", get(get_file_policy_handler).post(set_file_policy_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/audit", get(audit_handler))
         .with_state(Arc::new(app_state));
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
@@ -72,16 +110,24 @@ async fn websocket_handler(
 }
 
 async fn handle_socket(mut socket: WebSocket, state: Arc<WebState>) {
-    let (shared_state, _, ws_tx) = &*state;
+    let (shared_state, _, ws_tx, _) = &*state;
     let mut ws_rx = ws_tx.subscribe();
 
     let initial_state;
     {
         let state_guard = shared_state.lock().await;
+        let history = shared_state.history();
         initial_state = WsNotification::FullState {
             keys: state_guard.keys.clone(),
-            messages: state_guard.messages.clone(),
-            stats: state_guard.stats,
+            messages: history.messages.clone(),
+            outgoing: history.outgoing.clone(),
+            pending_files: state_guard.pending_files.values().map(|f| f.info.clone()).collect(),
+            files: state_guard.received_files.values().cloned().collect(),
+            key_compression: state_guard.key_compression.clone(),
+            key_ttl: state_guard.key_ttl.clone(),
+            key_labels: state_guard.key_labels.clone(),
+            rotations: state_guard.rotations.iter().filter(|r| r.phase != RotationPhase::Retired).cloned().collect(),
+            stats: Box::new(*shared_state.stats()),
         };
     }
 
@@ -112,49 +158,254 @@ async fn add_key_handler(
     State(state): State<Arc<WebState>>,
     Json(payload): Json<AddKeyPayload>,
 ) -> impl IntoResponse {
-    let (shared_state, _, ws_tx) = &*state;
+    let (shared_state, _, ws_tx, _) = &*state;
     let mut state_guard = shared_state.lock().await;
-    if !state_guard.keys.contains(&payload.key) && !payload.key.is_empty() {
-        state_guard.keys.push(payload.key.clone());
-        info!("Added new key: {}", payload.key);
+    if !payload.key.is_empty() {
+        state_guard.add_key(ws_tx, payload.key, payload.label.as_deref(), KeySource::Manual);
     }
-    let keys = state_guard.keys.clone();
-    ws_tx.send(WsNotification::KeyUpdate(keys)).ok();
     StatusCode::OK
 }
 
+/// Создаёт случайный 256-битный ключ, чтобы не придумывать пароль самому.
+async fn generate_key_handler(
+    State(state): State<Arc<WebState>>,
+    Json(payload): Json<GenerateKeyPayload>,
+) -> impl IntoResponse {
+    let (shared_state, _, ws_tx, _) = &*state;
+    let key = keys::generate_key();
+    let label = keys::normalize_label(payload.label.as_deref().unwrap_or_default());
+    shared_state.lock().await.add_key(ws_tx, key.clone(), Some(&label), KeySource::Generated);
+    let text = keys::export(&key, &label);
+    Json(KeyExportResponse { key, label, text })
+}
+
+async fn export_key_handler(
+    State(state): State<Arc<WebState>>,
+    Json(payload): Json<ExportKeyPayload>,
+) -> impl IntoResponse {
+    let (shared_state, _, _, _) = &*state;
+    let state_guard = shared_state.lock().await;
+    if !state_guard.keys.contains(&payload.key) {
+        return (StatusCode::NOT_FOUND, "Unknown key").into_response();
+    }
+    let label = state_guard.key_labels.get(&payload.key).cloned().unwrap_or_default();
+    let text = keys::export(&payload.key, &label);
+    Json(KeyExportResponse { key: payload.key, label, text }).into_response()
+}
+
+/// QR-код строки экспорта. POST, а не GET: ключ не должен попадать в URL и журналы запросов.
+async fn export_key_qr_handler(
+    State(state): State<Arc<WebState>>,
+    Json(payload): Json<ExportKeyPayload>,
+) -> impl IntoResponse {
+    let (shared_state, _, _, _) = &*state;
+    let text = {
+        let state_guard = shared_state.lock().await;
+        if !state_guard.keys.contains(&payload.key) {
+            return (StatusCode::NOT_FOUND, "Unknown key").into_response();
+        }
+        let label = state_guard.key_labels.get(&payload.key).cloned().unwrap_or_default();
+        keys::export(&payload.key, &label)
+    };
+    match keys::qr_svg(&text) {
+        Ok(svg) => ([(header::CONTENT_TYPE, "image/svg+xml"), (header::CACHE_CONTROL, "no-store")], svg).into_response(),
+        Err(e) => {
+            warn!("Failed to render key QR code: {:?}", e);
+            (StatusCode::UNPROCESSABLE_ENTITY, "Key is too long for a QR code").into_response()
+        }
+    }
+}
+
+async fn import_key_handler(
+    State(state): State<Arc<WebState>>,
+    Json(payload): Json<ImportKeyPayload>,
+) -> impl IntoResponse {
+    let (shared_state, _, ws_tx, _) = &*state;
+    match keys::import(&payload.text) {
+        Ok(imported) => {
+            let text = keys::export(&imported.key, &imported.label);
+            shared_state.lock().await.add_key(ws_tx, imported.key.clone(), Some(&imported.label), KeySource::Imported);
+            Json(KeyExportResponse { key: imported.key, label: imported.label, text }).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Cannot import key: {}", e)).into_response(),
+    }
+}
+
 async fn remove_key_handler(
     State(state): State<Arc<WebState>>,
     Json(payload): Json<AddKeyPayload>,
 ) -> impl IntoResponse {
-    let (shared_state, _, ws_tx) = &*state;
+    let (shared_state, _, ws_tx, _) = &*state;
     let mut state_guard = shared_state.lock().await;
+    if state_guard.keys.contains(&payload.key) {
+        info!("Removed key {}", key_fingerprint(&payload.key));
+        state_guard.audit.record(AuditEvent::KeyRemoved { key: key_fingerprint(&payload.key) });
+    }
     state_guard.keys.retain(|k| k != &payload.key);
-    info!("Removed key: {}", payload.key);
-    let keys = state_guard.keys.clone();
-    ws_tx.send(WsNotification::KeyUpdate(keys)).ok();
+    state_guard.keys_changed(ws_tx);
+    if state_guard.key_compression.remove(&payload.key).is_some() {
+        ws_tx.send(WsNotification::KeyCompressionUpdate(state_guard.key_compression.clone())).ok();
+    }
+    if state_guard.key_ttl.remove(&payload.key).is_some() {
+        ws_tx.send(WsNotification::KeyTtlUpdate(state_guard.key_ttl.clone())).ok();
+    }
+    if state_guard.key_labels.remove(&payload.key).is_some() {
+        ws_tx.send(WsNotification::KeyLabelsUpdate(state_guard.key_labels.clone())).ok();
+    }
     StatusCode::OK
 }
 
+async fn set_key_compression_handler(
+    State(state): State<Arc<WebState>>,
+    Json(payload): Json<SetKeyCompressionPayload>,
+) -> impl IntoResponse {
+    let (shared_state, _, ws_tx, _) = &*state;
+    let mut state_guard = shared_state.lock().await;
+    if !state_guard.keys.contains(&payload.key) {
+        return StatusCode::NOT_FOUND;
+    }
+    info!("Compression for key {} set to {:?}", key_fingerprint(&payload.key), payload.compression);
+    if payload.compression.is_off() {
+        state_guard.key_compression.remove(&payload.key);
+    } else {
+        state_guard.key_compression.insert(payload.key, payload.compression);
+    }
+    ws_tx.send(WsNotification::KeyCompressionUpdate(state_guard.key_compression.clone())).ok();
+    StatusCode::OK
+}
+
+async fn set_key_ttl_handler(
+    State(state): State<Arc<WebState>>,
+    Json(payload): Json<SetKeyTtlPayload>,
+) -> impl IntoResponse {
+    let (shared_state, _, ws_tx, _) = &*state;
+    let mut state_guard = shared_state.lock().await;
+    if !state_guard.keys.contains(&payload.key) {
+        return StatusCode::NOT_FOUND;
+    }
+    info!("Message TTL for key {} set to {:?} s", key_fingerprint(&payload.key), payload.ttl_secs);
+    match payload.ttl_secs.filter(|&ttl| ttl > 0) {
+        Some(ttl) => state_guard.key_ttl.insert(payload.key, ttl),
+        None => state_guard.key_ttl.remove(&payload.key),
+    };
+    ws_tx.send(WsNotification::KeyTtlUpdate(state_guard.key_ttl.clone())).ok();
+    StatusCode::OK
+}
+
+/// Предлагает собеседнику сменить общий ключ. Предложение шифруется текущим ключом.
+async fn rotate_key_handler(
+    State(state): State<Arc<WebState>>,
+    Json(payload): Json<RotateKeyPayload>,
+) -> impl IntoResponse {
+    let (shared_state, transmit_sender, ws_tx, _) = &*state;
+    let target_addr = match network::resolve_peer(&payload.target_addr).await {
+        Ok(Some(addr)) => addr,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Domain name could not be resolved").into_response(),
+        Err(e) => {
+            warn!("Failed to resolve host '{}': {}", payload.target_addr, e);
+            return (StatusCode::BAD_REQUEST, "Invalid target address or domain").into_response();
+        }
+    };
+    let new_key = payload.new_key.unwrap_or_else(keys::generate_key);
+    let activate_in_secs = payload.activate_in_secs.unwrap_or(rotation::DEFAULT_ACTIVATION_DELAY_SECS);
+    let activate_at = chrono::Utc::now() + chrono::Duration::seconds(activate_in_secs as i64);
+    let grace_secs = payload.grace_secs.unwrap_or(rotation::DEFAULT_GRACE_SECS);
+    let msg_id: u32 = rand::thread_rng().gen();
+    let sent_as = rotation::Proposal { peer: target_addr, msg_id, pattern: payload.pattern };
+    let proposal = match rotation::plan(&payload.key, new_key.clone(), sent_as, activate_at, grace_secs, true) {
+        Ok(proposal) => proposal,
+        Err(reason) => return (StatusCode::BAD_REQUEST, reason).into_response(),
+    };
+
+    {
+        let mut state_guard = shared_state.lock().await;
+        if !state_guard.keys.contains(&payload.key) {
+            return (StatusCode::NOT_FOUND, "Unknown key").into_response();
+        }
+        // Предложение должно уйти именно этим ключом, а не тем, что его уже сменил
+        if rotation::current_key(&state_guard, target_addr, &payload.key) != payload.key {
+            return (StatusCode::CONFLICT, "This key has already been replaced").into_response();
+        }
+        if !rotation::register(&mut state_guard, ws_tx, proposal.clone()) {
+            return (StatusCode::CONFLICT, "This rotation is already in progress").into_response();
+        }
+    }
+    // Предложение идёт в историю исходящих: ротация начнётся, только когда узел
+    // подтвердит согласие квитанцией о прочтении (см. `rotation::advance`)
+    let message = SendMessagePayload {
+        target_addr: target_addr.to_string(),
+        key: payload.key,
+        pattern: payload.pattern,
+        // Льготный период уже урезан в `plan`, отправляем итоговый
+        content: MessageContent::KeyRotation {
+            new_key,
+            activate_at,
+            grace_secs: (proposal.retire_at - proposal.activate_at).num_seconds() as u32,
+        },
+        redundancy: 0.0,
+        // Запись в истории нужна до решения узла, поэтому она не исчезает
+        ttl_secs: Some(0),
+    };
+    if !queue_message(shared_state, transmit_sender, ws_tx, message, target_addr, msg_id).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to queue message").into_response();
+    }
+    (StatusCode::OK, Json(proposal)).into_response()
+}
+
+/// Соглашается на ротацию, предложенную узлом, и сообщает ему об этом квитанцией
+/// о прочтении предложения: без неё узел ротацию не начнёт.
+async fn accept_rotation_handler(
+    State(state): State<Arc<WebState>>,
+    Path(rotation_id): Path<Uuid>,
+) -> Response {
+    let (shared_state, transmit_sender, ws_tx, _) = &*state;
+    let accepted = rotation::accept(&mut *shared_state.lock().await, &shared_state.history(), ws_tx, rotation_id, chrono::Utc::now());
+    let rotation = match accepted {
+        Ok(rotation) => rotation,
+        Err(DecisionError::NotFound) => return (StatusCode::NOT_FOUND, "No such pending rotation").into_response(),
+        Err(DecisionError::Expired) => return (StatusCode::GONE, "The rotation's activation time has passed").into_response(),
+    };
+    if transmit_sender.send(rotation::consent_receipt(&rotation)).await.is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    StatusCode::OK.into_response()
+}
+
+async fn decline_rotation_handler(
+    State(state): State<Arc<WebState>>,
+    Path(rotation_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let (shared_state, _, ws_tx, _) = &*state;
+    let declined = rotation::decline(&mut *shared_state.lock().await, &shared_state.history(), ws_tx, rotation_id);
+    match declined {
+        Ok(()) => StatusCode::OK,
+        Err(_) => StatusCode::NOT_FOUND,
+    }
+}
+
 async fn send_message_handler(
     State(state): State<Arc<WebState>>,
     Json(payload): Json<SendMessagePayload>,
 ) -> impl IntoResponse {
-    let (_, transmit_sender, _) = &*state;
+    let (shared_state, transmit_sender, ws_tx, _) = &*state;
+    if matches!(payload.content, MessageContent::KeyRotation { .. }) {
+        return (StatusCode::BAD_REQUEST, "Use /keys/rotate to rotate keys").into_response();
+    }
     
-    match lookup_host(&payload.target_addr).await {
-        Ok(mut addresses) => {
-            if let Some(target_addr) = addresses.next() {
-                let command = TransmitCommand::SendMessage {
-                    target_addr,
-                    key: payload.key,
-                    pattern: payload.pattern,
-                    content: payload.content,
-                };
-                if transmit_sender.send(command).await.is_err() {
+    // Из адресов узла берём первый, до которого есть маршрут (IPv4 или IPv6)
+    let resolved = network::resolve_peer(&payload.target_addr).await;
+    match resolved {
+        Ok(first_address) => {
+            if let Some(target_addr) = first_address {
+                if payload.content.is_action() {
+                    let msg_id: u32 = rand::thread_rng().gen();
+                    return send_action(shared_state, transmit_sender, ws_tx, payload, target_addr, msg_id).await;
+                }
+                let msg_id: u32 = rand::thread_rng().gen();
+                if !queue_message(shared_state, transmit_sender, ws_tx, payload, target_addr, msg_id).await {
                     return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to queue message").into_response();
                 }
-                (StatusCode::OK, "Message queued").into_response()
+                (StatusCode::OK, Json(SendMessageResponse { msg_id })).into_response()
             } else {
                 (StatusCode::BAD_REQUEST, "Domain name could not be resolved").into_response()
             }
@@ -166,31 +417,291 @@ async fn send_message_handler(
     }
 }
 
+/// Записывает сообщение в историю и ставит его в очередь передатчика. Общий путь
+/// для `POST /send`, предложений ротации и `Node::send_message`.
+/// `false` — передатчик уже остановлен.
+pub async fn queue_message(
+    shared_state: &SharedState,
+    transmit_sender: &mpsc::Sender<TransmitCommand>,
+    ws_tx: &broadcast::Sender<WsNotification>,
+    payload: SendMessagePayload,
+    target_addr: SocketAddr,
+    msg_id: u32,
+) -> bool {
+    // В истории файл хранится без содержимого, как и во входящих сообщениях, а предложение ротации — без ключа
+    let content_for_history = match &payload.content {
+        MessageContent::File(file) => MessageContent::File(FileContent {
+            filename: file.filename.clone(),
+            data: Vec::new(),
+            id: None,
+            status: None,
+        }),
+        MessageContent::KeyRotation { activate_at, grace_secs, .. } => MessageContent::KeyRotation {
+            new_key: String::new(),
+            activate_at: *activate_at,
+            grace_secs: *grace_secs,
+        },
+        other => other.clone(),
+    };
+    let mut outgoing = OutgoingMessage {
+        id: Uuid::new_v4(),
+        direction: MessageDirection::Outgoing,
+        msg_id,
+        timestamp: chrono::Utc::now(),
+        target: target_addr,
+        content: content_for_history,
+        sent_with_key: payload.key.clone(),
+        sent_with_pattern: payload.pattern,
+        status: MessageStatus::Queued,
+        edited: false,
+        deleted: false,
+        reactions: Vec::new(),
+        expires_at: None,
+    };
+    // Записываем сообщение в историю до постановки в очередь, чтобы передатчик мог обновить статус
+    let (key, compression, ttl_secs) = {
+        let state_guard = shared_state.lock().await;
+        // После активации ротации выбранный в UI старый ключ заменяется новым
+        let key = rotation::current_key(&state_guard, target_addr, &payload.key);
+        let ttl_secs = payload.ttl_secs.or_else(|| state_guard.key_ttl.get(&key).copied()).filter(|&ttl| ttl > 0);
+        // Своя копия исчезает вместе с копией получателя
+        outgoing.expires_at = expiry::expires_at(ttl_secs);
+        outgoing.sent_with_key = key.clone();
+        shared_state.history().outgoing.push(outgoing.clone());
+        let compression = state_guard.key_compression.get(&key).copied().unwrap_or_default();
+        (key, compression, ttl_secs)
+    };
+    let command = TransmitCommand::SendMessage {
+        msg_id,
+        target_addr,
+        key,
+        pattern: payload.pattern,
+        content: payload.content,
+        redundancy: payload.redundancy,
+        compression,
+        ttl_secs,
+    };
+    if transmit_sender.send(command).await.is_err() {
+        shared_state.history().outgoing.retain(|m| m.id != outgoing.id);
+        return false;
+    }
+    ws_tx.send(WsNotification::NewOutgoingMessage(outgoing)).ok();
+    true
+}
+
+/// Правки, удаления, реакции и индикатор набора не попадают в историю отдельной записью:
+/// применяем их к своей копии переписки сразу и отправляем собеседнику.
+async fn send_action(
+    shared_state: &SharedState,
+    transmit_sender: &mpsc::Sender<TransmitCommand>,
+    ws_tx: &broadcast::Sender<WsNotification>,
+    payload: SendMessagePayload,
+    target_addr: SocketAddr,
+    msg_id: u32,
+) -> Response {
+    let (key, compression) = {
+        let state_guard = shared_state.lock().await;
+        if let Some(update) = conversation::apply_action(&mut shared_state.history(), Author::Local, &payload.content) {
+            ws_tx.send(update).ok();
+        }
+        let key = rotation::current_key(&state_guard, target_addr, &payload.key);
+        let compression = state_guard.key_compression.get(&key).copied().unwrap_or_default();
+        (key, compression)
+    };
+    let command = TransmitCommand::SendMessage {
+        msg_id,
+        target_addr,
+        key,
+        pattern: payload.pattern,
+        content: payload.content,
+        redundancy: payload.redundancy,
+        compression,
+        // Действие живёт столько же, сколько сообщение, к которому относится
+        ttl_secs: None,
+    };
+    if transmit_sender.send(command).await.is_err() {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to queue message").into_response();
+    }
+    (StatusCode::OK, Json(SendMessageResponse { msg_id })).into_response()
+}
+
+/// Отменяет отправку. `id` — локальный идентификатор записи: `msg_id` уникален только вместе с получателем.
+async fn cancel_message_handler(
+    State(state): State<Arc<WebState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let (shared_state, transmit_sender, _, _) = &*state;
+    let command = {
+        let history = shared_state.history();
+        match history.outgoing.iter().find(|m| m.id == id) {
+            None => return (StatusCode::NOT_FOUND, "Unknown message").into_response(),
+            Some(message) if message.status != MessageStatus::Queued => {
+                return (StatusCode::CONFLICT, "Message is no longer in the transmit queue").into_response();
+            }
+            Some(message) => TransmitCommand::CancelMessage { target: message.target, msg_id: message.msg_id },
+        }
+    };
+    // Статус Cancelled выставит передатчик, если сообщение ещё в очереди
+    if transmit_sender.send(command).await.is_err() {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to cancel message").into_response();
+    }
+    StatusCode::ACCEPTED.into_response()
+}
+
+async fn mark_read_handler(
+    State(state): State<Arc<WebState>>,
+    Path(message_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let (shared_state, transmit_sender, _, _) = &*state;
+    let receipt = {
+        let mut history = shared_state.history();
+        let Some(message) = history.messages.iter_mut().find(|m| m.id == message_id) else {
+            return StatusCode::NOT_FOUND;
+        };
+        if message.read {
+            return StatusCode::OK;
+        }
+        message.read = true;
+        TransmitCommand::SendControl {
+            target_addr: message.sender,
+            key: message.decrypted_with_key.clone(),
+            pattern: message.decrypted_with_pattern,
+            frame: ControlFrame::Receipt { msg_id: message.msg_id, status: MessageStatus::Read },
+        }
+    };
+    if transmit_sender.send(receipt).await.is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    StatusCode::OK
+}
+
+async fn list_files_handler(State(state): State<Arc<WebState>>) -> Json<Vec<ReceivedFile>> {
+    let (shared_state, _, _, _) = &*state;
+    let mut files: Vec<ReceivedFile> = shared_state.lock().await.received_files.values().cloned().collect();
+    files.sort_by_key(|f| std::cmp::Reverse(f.received_at));
+    Json(files)
+}
+
+async fn delete_file_handler(
+    State(state): State<Arc<WebState>>,
+    Path(file_id): Path<Uuid>,
+) -> Response {
+    let (shared_state, _, ws_tx, _) = &*state;
+    match library::delete_file(shared_state, ws_tx, file_id).await {
+        Ok(true) => StatusCode::OK.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, "No such file").into_response(),
+        Err(e) => {
+            warn!("Failed to delete file {}: {}", file_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete file").into_response()
+        }
+    }
+}
+
 async fn download_file_handler(
     State(state): State<Arc<WebState>>,
     Path(file_id): Path<Uuid>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (shared_state, _, _, _) = &*state;
+    // Под блокировкой берём только метаданные, содержимое читаем с диска потоком
+    let (file, path) = {
+        let state_guard = shared_state.lock().await;
+        let file = state_guard.received_files.get(&file_id).cloned().ok_or(StatusCode::NOT_FOUND)?;
+        let path = state_guard.downloads_path.join(&file.filename);
+        (file, path)
+    };
+    let handle = tokio::fs::File::open(&path).await.map_err(|e| {
+        warn!("Failed to open received file {:?}: {}", path, e);
+        StatusCode::NOT_FOUND
+    })?;
+    let length = handle.metadata().await.map(|m| m.len()).unwrap_or(file.size);
+    let headers = [
+        (header::CONTENT_TYPE, file.mime.clone()),
+        (header::CONTENT_LENGTH, length.to_string()),
+        (header::CONTENT_DISPOSITION, files::content_disposition(&file.filename)),
+    ];
+    Ok((headers, Body::from_stream(ReaderStream::new(handle))))
+}
+
+async fn accept_file_handler(
+    State(state): State<Arc<WebState>>,
+    Path(file_id): Path<Uuid>,
+) -> Response {
+    let (shared_state, _, ws_tx, config) = &*state;
+    match quarantine::accept_file(shared_state, ws_tx, config, file_id).await {
+        Ok(_) => StatusCode::OK.into_response(),
+        Err(AcceptError::NotFound) => (StatusCode::NOT_FOUND, "No such pending file").into_response(),
+        Err(AcceptError::QuotaExceeded) => (StatusCode::INSUFFICIENT_STORAGE, "Download quota exceeded").into_response(),
+        Err(AcceptError::Io(e)) => {
+            warn!("Failed to save accepted file {}: {}", file_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save file").into_response()
+        }
+    }
+}
+
+async fn reject_file_handler(
+    State(state): State<Arc<WebState>>,
+    Path(file_id): Path<Uuid>,
 ) -> impl IntoResponse {
-    let (shared_state, _, _) = &*state;
-    let state_guard = shared_state.lock().await;
-    if let Some((filename, data)) = state_guard.received_files.get(&file_id) {
-        let headers = [
-            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
-            (
-                header::CONTENT_DISPOSITION,
-                format!("attachment; filename=\"{}\"", filename),
-            ),
-        ];
-        Ok((headers, data.clone()))
-    } else {
-        Err(StatusCode::NOT_FOUND)
+    let (shared_state, _, ws_tx, _) = &*state;
+    match quarantine::reject_file(shared_state, ws_tx, file_id).await {
+        Ok(()) => StatusCode::OK,
+        Err(_) => StatusCode::NOT_FOUND,
+    }
+}
+
+/// Метрики в текстовом формате Prometheus.
+async fn metrics_handler(State(state): State<Arc<WebState>>) -> impl IntoResponse {
+    let (shared_state, transmit_sender, ws_tx, _) = &*state;
+    let queues = metrics::QueueDepths {
+        transmit: transmit_sender.max_capacity() - transmit_sender.capacity(),
+        websocket: ws_tx.len(),
+    };
+    let stats = *shared_state.stats();
+    let body = metrics::render(&*shared_state.lock().await, &stats, queues);
+    ([(header::CONTENT_TYPE, metrics::CONTENT_TYPE)], body)
+}
+
+/// Журнал аудита с результатом проверки цепочки хэшей.
+async fn audit_handler(State(state): State<Arc<WebState>>, Query(query): Query<AuditQuery>) -> Response {
+    let (shared_state, _, _, _) = &*state;
+    let path = shared_state.lock().await.audit.path().to_path_buf();
+    let filter = AuditFilter { since_seq: query.since, event: query.event, limit: query.limit };
+    match tokio::task::spawn_blocking(move || audit::query(&path, &filter)).await {
+        Ok(Ok(page)) => Json(page).into_response(),
+        Ok(Err(e)) => {
+            warn!("Failed to read audit log: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read audit log").into_response()
+        }
+        Err(e) => {
+            warn!("Audit query task failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
     }
 }
 
+async fn get_file_policy_handler(State(state): State<Arc<WebState>>) -> Json<FilePolicy> {
+    let (shared_state, _, _, _) = &*state;
+    Json(shared_state.lock().await.file_policy.clone())
+}
+
+async fn set_file_policy_handler(
+    State(state): State<Arc<WebState>>,
+    Json(mut policy): Json<FilePolicy>,
+) -> impl IntoResponse {
+    let (shared_state, _, _, _) = &*state;
+    for extension in policy.allowed_extensions.iter_mut().chain(policy.denied_extensions.iter_mut()) {
+        *extension = extension.trim().trim_start_matches('.').to_ascii_lowercase();
+    }
+    info!("Updating file policy: {:?}", policy);
+    shared_state.lock().await.file_policy = policy;
+    StatusCode::OK
+}
+
 async fn set_noise_handler(
     State(state): State<Arc<WebState>>,
     Json(payload): Json<SetNoisePayload>,
 ) -> Response {
-    let (_, transmit_sender, _) = &*state;
+    let (_, transmit_sender, _, _) = &*state;
     info!("Setting noise level to: {:?}", payload.level);
     let command = TransmitCommand::SetNoiseLevel(payload.level);
     if transmit_sender.send(command).await.is_err() {
@@ -3,11 +3,18 @@ document.addEventListener('DOMContentLoaded', () => {
     const wsStatus = document.getElementById('ws-status');
     const addKeyForm = document.getElementById('add-key-form');
     const keyInput = document.getElementById('key-input');
+    const keyLabelInput = document.getElementById('key-label-input');
+    const keyEntropyWarning = document.getElementById('key-entropy-warning');
+    const keyExport = document.getElementById('key-export');
     const keyList = document.getElementById('key-list');
+    const rotationList = document.getElementById('rotation-list');
+    const rotationNotice = document.getElementById('rotation-notice');
     const sendMessageForm = document.getElementById('send-message-form');
     const targetAddrInput = document.getElementById('target-addr');
     const sendKeySelect = document.getElementById('send-key');
     const sendPatternSelect = document.getElementById('send-pattern');
+    const sendRedundancySelect = document.getElementById('send-redundancy');
+    const sendTtlSelect = document.getElementById('send-ttl');
     const messageTextInput = document.getElementById('message-text');
     const fileInput = document.getElementById('file-input');
     const fileNameDisplay = document.getElementById('file-name-display');
@@ -15,12 +22,66 @@ document.addEventListener('DOMContentLoaded', () => {
     const trafficFeed = document.getElementById('traffic-feed');
     const currentKeyDisplay = document.getElementById('current-key');
     const noiseLevelRadios = document.querySelectorAll('input[name="noise"]');
+    const filePolicyForm = document.getElementById('file-policy-form');
+    const libraryList = document.getElementById('library-list');
+    const replyIndicator = document.getElementById('reply-indicator');
+    const typingIndicator = document.getElementById('typing-indicator');
     
     // --- Элементы статистики ---
     const statSent = document.getElementById('stat-sent');
     const statNoiseSent = document.getElementById('stat-noise-sent');
     const statReceived = document.getElementById('stat-received');
     const statDecrypted = document.getElementById('stat-decrypted');
+    const statThroughput = document.getElementById('stat-throughput');
+    const statRtt = document.getElementById('stat-rtt');
+    const statRetransmissions = document.getElementById('stat-retransmissions');
+    const statNoiseReceived = document.getElementById('stat-noise-received');
+    const statQueues = document.getElementById('stat-queues');
+    const statStalls = document.getElementById('stat-stalls');
+    const statOversized = document.getElementById('stat-oversized');
+    const statKernelDrops = document.getElementById('stat-kernel-drops');
+
+    // Статусы исходящих сообщений и их отображение
+    const STATUS_MARKS = {
+        Queued: { mark: '🕓', title: 'Queued' },
+        Sent: { mark: '✓', title: 'Sent' },
+        Delivered: { mark: '✓✓', title: 'Delivered' },
+        Read: { mark: '✓✓', title: 'Read' },
+        Cancelled: { mark: '✖', title: 'Cancelled' },
+        Failed: { mark: '⚠', title: 'Failed: no acknowledgement from peer' },
+    };
+    // Статусы, которые пришли раньше, чем мы отрисовали сообщение
+    const pendingStatuses = new Map();
+    // Входящие сообщения, которые нужно подтвердить как прочитанные
+    const unreadMessages = new Set();
+    // Входящие файлы, ожидающие решения пользователя, по id
+    const pendingFiles = new Map();
+    // Принятые файлы по id
+    const libraryFiles = new Map();
+    // Сжатие по ключам; ключей без записи оно не касается
+    let keyCompression = {};
+    // TTL сообщений по ключам, в секундах
+    let keyTtl = {};
+    let currentKeys = [];
+    // Метки ключей
+    let keyLabels = {};
+    // Ниже этой оценки энтропии введённый вручную ключ считаем слабым
+    const MIN_KEY_ENTROPY_BITS = 80;
+    // Незавершённые ротации ключей
+    let rotations = [];
+    const ROTATION_NOTICE_MS = 15000;
+    const TTL_OPTIONS = [[0, 'Never disappear'], [30, '30 s'], [300, '5 min'], [3600, '1 h'], [86400, '1 day'], [604800, '1 week']];
+    // Сообщения ленты по id: на них ссылаются ответы, правки и реакции
+    const messagesById = new Map();
+    // Сообщение, на которое отвечаем следующим
+    let replyTarget = null;
+    // Печатающие собеседники: адрес → таймер, скрывающий индикатор
+    const typingPeers = new Map();
+    let lastTypingSent = 0;
+    const QUICK_REACTIONS = ['👍', '❤️', '😂'];
+    // Собеседник шлёт Typing не чаще раза в TYPING_THROTTLE_MS, индикатор живёт дольше
+    const TYPING_THROTTLE_MS = 3000;
+    const TYPING_TIMEOUT_MS = 5000;
 
     function connectWebSocket() {
         const ws = new WebSocket(`ws://${window.location.host}/ws`);
@@ -54,14 +115,29 @@ document.addEventListener('DOMContentLoaded', () => {
     function handleWsMessage(data) {
         switch (data.event) {
             case 'FullState':
+                pendingFiles.clear();
+                data.data.pending_files.forEach(file => pendingFiles.set(file.id, file));
+                libraryFiles.clear();
+                data.data.files.forEach(file => libraryFiles.set(file.id, file));
+                renderLibrary();
+                keyCompression = data.data.key_compression;
+                keyTtl = data.data.key_ttl;
+                keyLabels = data.data.key_labels;
+                rotations = data.data.rotations;
+                renderRotations();
                 renderKeys(data.data.keys);
-                renderMessages(data.data.messages);
+                renderMessages(data.data.messages, data.data.outgoing);
                 updateStats(data.data.stats);
                 break;
             case 'NewMessage':
+                showTyping(data.data.sender, false);
                 renderMessage(data.data, true);
                 clearFeedPlaceholder(messageFeed);
                 break;
+            case 'NewOutgoingMessage':
+                renderOutgoingMessage(data.data, true);
+                clearFeedPlaceholder(messageFeed);
+                break;
             case 'NoisePacket':
                 renderTraffic(data.data);
                  clearFeedPlaceholder(trafficFeed);
@@ -69,13 +145,75 @@ document.addEventListener('DOMContentLoaded', () => {
             case 'KeyUpdate':
                 renderKeys(data.data);
                 break;
+            case 'KeyCompressionUpdate':
+                keyCompression = data.data;
+                renderKeys(currentKeys);
+                break;
+            case 'KeyTtlUpdate':
+                keyTtl = data.data;
+                renderKeys(currentKeys);
+                break;
+            case 'KeyLabelsUpdate':
+                keyLabels = data.data;
+                renderKeys(currentKeys);
+                break;
+            case 'KeyRotationUpdate':
+                updateRotation(data.data);
+                break;
             case 'StatsUpdate':
                 updateStats(data.data);
                 break;
+            case 'MessageStatus':
+                updateMessageStatus(data.data.target, data.data.msg_id, data.data.status);
+                break;
+            case 'FileOffer':
+                offerFile(data.data);
+                break;
+            case 'FileResolved':
+                resolveFile(data.data);
+                break;
+            case 'FileStored':
+                libraryFiles.set(data.data.id, data.data);
+                renderLibrary();
+                break;
+            case 'FileDeleted':
+                pendingFiles.delete(data.data.id);
+                libraryFiles.delete(data.data.id);
+                renderLibrary();
+                updateFileActions({ id: data.data.id, status: 'Deleted' });
+                break;
+            case 'TransmitProgress':
+                updateTransmitProgress(data.data);
+                break;
+            case 'MessageEdited':
+                updateMessage(data.data.id, msg => {
+                    msg.content = data.data.content;
+                    msg.edited = true;
+                });
+                break;
+            case 'MessageDeleted':
+                updateMessage(data.data.id, msg => {
+                    msg.content = { type: 'Text', payload: '' };
+                    msg.deleted = true;
+                    msg.reactions = [];
+                });
+                break;
+            case 'MessageExpired':
+                removeMessage(data.data.id);
+                break;
+            case 'ReactionsUpdated':
+                updateMessage(data.data.id, msg => {
+                    msg.reactions = data.data.reactions;
+                });
+                break;
+            case 'Typing':
+                showTyping(data.data.peer, data.data.active);
+                break;
         }
     }
 
     function renderKeys(keys) {
+        currentKeys = keys;
         keyList.innerHTML = '';
         const currentSelectedKey = sendKeySelect.value;
         sendKeySelect.innerHTML = '<option value="" disabled selected>--Select a key--</option>';
@@ -88,7 +226,51 @@ document.addEventListener('DOMContentLoaded', () => {
         } else {
             keys.forEach(key => {
                 const li = document.createElement('li');
-                li.textContent = key;
+                const name = document.createElement('span');
+                name.className = 'key-name';
+                if (keyLabels[key]) {
+                    name.innerHTML = `<strong>${escapeHtml(keyLabels[key])}</strong><span class="key-secret">${escapeHtml(shortKey(key))}</span>`;
+                } else {
+                    name.textContent = key;
+                }
+                li.appendChild(name);
+                // Сжатие выдаёт длину содержимого, поэтому по умолчанию выключено
+                const compressionSelect = document.createElement('select');
+                compressionSelect.className = 'key-compression';
+                compressionSelect.title = 'Compression (may reveal content length patterns)';
+                ['Off', 'Deflate', 'Zstd'].forEach(mode => {
+                    const option = document.createElement('option');
+                    option.value = mode;
+                    option.textContent = mode === 'Off' ? 'No compression' : mode;
+                    compressionSelect.appendChild(option);
+                });
+                compressionSelect.value = keyCompression[key] || 'Off';
+                compressionSelect.onchange = () => setKeyCompression(key, compressionSelect.value);
+                li.appendChild(compressionSelect);
+                const ttlSelect = document.createElement('select');
+                ttlSelect.className = 'key-ttl';
+                ttlSelect.title = 'Messages sent with this key disappear after';
+                TTL_OPTIONS.forEach(([seconds, label]) => {
+                    const option = document.createElement('option');
+                    option.value = seconds;
+                    option.textContent = label;
+                    ttlSelect.appendChild(option);
+                });
+                ttlSelect.value = keyTtl[key] || 0;
+                ttlSelect.onchange = () => setKeyTtl(key, parseInt(ttlSelect.value, 10));
+                li.appendChild(ttlSelect);
+                const rotateBtn = document.createElement('button');
+                rotateBtn.textContent = '⟳';
+                rotateBtn.className = 'rotate-key';
+                rotateBtn.title = `Rotate key ${key} with a peer`;
+                rotateBtn.onclick = () => rotateKey(key);
+                li.appendChild(rotateBtn);
+                const exportBtn = document.createElement('button');
+                exportBtn.textContent = '⇪';
+                exportBtn.className = 'export-key';
+                exportBtn.title = `Export key ${keyLabels[key] || key} as text and QR code`;
+                exportBtn.onclick = () => exportKey(key);
+                li.appendChild(exportBtn);
                 const deleteBtn = document.createElement('button');
                 deleteBtn.textContent = '✖';
                 deleteBtn.className = 'delete-key';
@@ -99,7 +281,7 @@ document.addEventListener('DOMContentLoaded', () => {
 
                 const option = document.createElement('option');
                 option.value = key;
-                option.textContent = key;
+                option.textContent = keyLabels[key] ? `${keyLabels[key]} (${shortKey(key)})` : key;
                 sendKeySelect.appendChild(option);
             });
         }
@@ -111,14 +293,83 @@ document.addEventListener('DOMContentLoaded', () => {
         updateCurrentKeyDisplay();
     }
     
+    // Ключи показываем сокращённо: список ротаций не должен раскрывать их целиком
+    function shortKey(key) {
+        return key.length > 12 ? `${key.slice(0, 8)}…` : key;
+    }
+
+    function renderRotations() {
+        rotationList.innerHTML = '';
+        rotations.forEach(rotation => {
+            const li = document.createElement('li');
+            const when = ['Pending', 'Proposed'].includes(rotation.phase)
+                ? `switches at ${new Date(rotation.activate_at).toLocaleString()}`
+                : `old key dropped at ${new Date(rotation.retire_at).toLocaleString()}`;
+            li.innerHTML = `
+                <span class="rotation-phase">${rotation.phase}</span>
+                ${escapeHtml(shortKey(rotation.old_key))} → ${escapeHtml(shortKey(rotation.new_key))}
+                with ${escapeHtml(rotation.peer)} (${rotation.initiated_locally ? 'ours' : 'peer'}), ${when}
+            `;
+            // Ключ, предложенный узлом, начинает приниматься только с согласия пользователя
+            if (rotation.phase === 'Pending') {
+                const acceptBtn = document.createElement('button');
+                acceptBtn.textContent = 'Accept';
+                acceptBtn.onclick = () => apiFetch(`/keys/rotations/${rotation.id}/accept`, 'POST');
+                const declineBtn = document.createElement('button');
+                declineBtn.textContent = 'Decline';
+                declineBtn.onclick = () => apiFetch(`/keys/rotations/${rotation.id}/decline`, 'POST');
+                li.append(acceptBtn, declineBtn);
+            }
+            rotationList.appendChild(li);
+        });
+    }
+
+    function updateRotation(rotation) {
+        rotations = rotations.filter(r => r.id !== rotation.id);
+        if (!['Retired', 'Aborted'].includes(rotation.phase)) {
+            rotations.push(rotation);
+        }
+        renderRotations();
+
+        const messages = {
+            Pending: `${rotation.peer} proposes a new key from ${new Date(rotation.activate_at).toLocaleString()}; accept or decline it below.`,
+            Proposed: rotation.initiated_locally
+                ? `Proposed a new key to ${rotation.peer}.`
+                : `Accepted the new key from ${rotation.peer}; it will be used from ${new Date(rotation.activate_at).toLocaleString()}.`,
+            Active: `Now sending with the new key instead of ${shortKey(rotation.old_key)}.`,
+            Retired: `Grace period with ${rotation.peer} is over; key ${shortKey(rotation.old_key)} is no longer used with it.`,
+            Aborted: `Key rotation with ${rotation.peer} did not happen; still using ${shortKey(rotation.old_key)}.`,
+        };
+        rotationNotice.textContent = messages[rotation.phase];
+        rotationNotice.hidden = false;
+        clearTimeout(rotationNotice.hideTimer);
+        rotationNotice.hideTimer = setTimeout(() => { rotationNotice.hidden = true; }, ROTATION_NOTICE_MS);
+
+        // Выбранный для отправки этому узлу ключ заменяем новым, как это делает сервер
+        if (rotation.phase === 'Active' && sendKeySelect.value === rotation.old_key && targetAddrInput.value.trim() === rotation.peer) {
+            sendKeySelect.value = rotation.new_key;
+            updateCurrentKeyDisplay();
+        }
+    }
+
     function updateCurrentKeyDisplay() {
         currentKeyDisplay.textContent = sendKeySelect.value || 'None';
     }
 
-    function renderMessages(messages) {
+    function renderMessages(messages, outgoing) {
         messageFeed.innerHTML = '';
-        if (messages.length > 0) {
-            messages.forEach(msg => renderMessage(msg, false));
+        messagesById.clear();
+        // Общая лента: входящие и исходящие вперемешку, новые сверху
+        const history = [...messages, ...outgoing]
+            .sort((a, b) => new Date(b.timestamp) - new Date(a.timestamp));
+        if (history.length > 0) {
+            history.forEach(msg => {
+                if (msg.direction === 'Outgoing') {
+                    renderOutgoingMessage(msg, false);
+                } else {
+                    renderMessage(msg, false);
+                }
+            });
         } else {
             messageFeed.innerHTML = '<div class="feed-placeholder">Waiting for messages...</div>';
         }
@@ -127,18 +378,18 @@ document.addEventListener('DOMContentLoaded', () => {
     function renderMessage(msg, prepend = true) {
         const item = document.createElement('div');
         item.className = 'feed-item message';
+        item.dataset.id = msg.id;
+        messagesById.set(msg.id, msg);
 
         const timestamp = new Date(msg.timestamp).toLocaleTimeString();
-        let contentHtml = '';
+        let contentHtml = '<div class="message-content message-body"></div>';
         const content = msg.content.payload;
 
-        if (msg.content.type === 'Text') {
-            contentHtml = `<div class="message-content">${escapeHtml(content)}</div>`;
-        } else if (msg.content.type === 'File') {
+        if (msg.content.type === 'File') {
             contentHtml = `
-                <div class="message-content file-attachment">
-                    📎 File: <strong>${escapeHtml(content.filename)}</strong>
-                    <a href="/download/${content.id}" target="_blank" class="download-link">Download</a>
+                <div class="message-content file-attachment" data-file-id="${content.id || ''}">
+                    <span>📎 File: <strong>${escapeHtml(content.filename)}</strong></span>
+                    <span class="file-actions">${fileActionsHtml(content)}</span>
                 </div>
             `;
         }
@@ -149,15 +400,297 @@ document.addEventListener('DOMContentLoaded', () => {
                 From <span class="message-sender">${msg.sender}</span> 
                 (key: <span class="key-used">${escapeHtml(msg.decrypted_with_key)}</span>, 
                 pattern: <span class="pattern-used">${msg.decrypted_with_pattern}</span>)
+                ${expiryHtml(msg)}
             </div>
             ${contentHtml}
+            <div class="message-reactions"></div>
+            <div class="message-actions"></div>
+        `;
+        refreshMessage(item, msg);
+
+        if (prepend) {
+            messageFeed.insertBefore(item, messageFeed.firstChild);
+        } else {
+            messageFeed.appendChild(item);
+        }
+
+        if (!msg.read) {
+            unreadMessages.add(msg.id);
+            acknowledgeReadMessages();
+        }
+    }
+
+    function fileActionsHtml(file) {
+        if (!file.id || file.status === 'Rejected') {
+            return '<span class="file-state">Rejected</span>';
+        }
+        if (file.status === 'Deleted') {
+            return '<span class="file-state">Deleted</span>';
+        }
+        if (file.status === 'Accepted') {
+            return `<a href="/download/${file.id}" target="_blank" class="download-link">Download</a>`;
+        }
+        const pending = pendingFiles.get(file.id);
+        if (!pending) {
+            return '<span class="file-state">Quarantining…</span>';
+        }
+        return `
+            <span class="file-state">${formatBytes(pending.size)} from ${escapeHtml(pending.sender)},
+                sha256 <code title="${pending.sha256}">${pending.sha256.slice(0, 12)}…</code> — save to disk?</span>
+            <button class="file-accept" onclick="window.asemic.acceptFile('${file.id}')">Accept</button>
+            <button class="file-reject" onclick="window.asemic.rejectFile('${file.id}')">Reject</button>
+        `;
+    }
+
+    function updateFileActions(file) {
+        const attachment = messageFeed.querySelector(`.file-attachment[data-file-id="${file.id}"]`);
+        if (attachment) {
+            attachment.querySelector('.file-actions').innerHTML = fileActionsHtml(file);
+        }
+    }
+
+    function offerFile(info) {
+        pendingFiles.set(info.id, info);
+        updateFileActions({ id: info.id, status: 'Quarantined' });
+    }
+
+    function resolveFile({ id, status }) {
+        pendingFiles.delete(id);
+        updateFileActions({ id, status });
+    }
+
+    function renderLibrary() {
+        const files = [...libraryFiles.values()].sort((a, b) => new Date(b.received_at) - new Date(a.received_at));
+        libraryList.innerHTML = files.map(file => `
+            <tr>
+                <td><a href="/download/${file.id}" target="_blank">${escapeHtml(file.filename)}</a></td>
+                <td>${formatBytes(file.size)}</td>
+                <td>${escapeHtml(file.mime)}</td>
+                <td>${escapeHtml(file.sender)}</td>
+                <td>${new Date(file.received_at).toLocaleString()}</td>
+                <td><code title="${file.sha256}">${file.sha256.slice(0, 12)}…</code></td>
+                <td><button class="library-delete" onclick="window.asemic.deleteFile('${file.id}')">Delete</button></td>
+            </tr>
+        `).join('');
+    }
+
+    function renderOutgoingMessage(msg, prepend = true) {
+        const item = document.createElement('div');
+        item.className = 'feed-item message outgoing';
+        item.dataset.msgId = msg.msg_id;
+        item.dataset.target = msg.target;
+        item.dataset.id = msg.id;
+        messagesById.set(msg.id, msg);
+
+        const timestamp = new Date(msg.timestamp).toLocaleTimeString();
+        const content = msg.content.payload;
+        const body = msg.content.type === 'File'
+            ? `<div class="message-content">📎 File: <strong>${escapeHtml(content.filename)}</strong></div>`
+            : '<div class="message-content message-body"></div>';
+
+        item.innerHTML = `
+            <div class="message-meta">
+                <span class="timestamp">[${timestamp}]</span> 
+                To <span class="message-sender">${msg.target}</span> 
+                (key: <span class="key-used">${escapeHtml(msg.sent_with_key)}</span>, 
+                pattern: <span class="pattern-used">${msg.sent_with_pattern}</span>)
+                ${expiryHtml(msg)}
+                <span class="message-status"></span>
+                <button class="cancel-send" title="Cancel sending">Cancel</button>
+            </div>
+            ${body}
+            <div class="message-reactions"></div>
+            <div class="message-actions"></div>
+            <progress class="transmit-progress" value="0" max="1" hidden></progress>
         `;
+        item.querySelector('.cancel-send').onclick = () => cancelMessage(msg.id);
+        refreshMessage(item, msg);
 
         if (prepend) {
             messageFeed.insertBefore(item, messageFeed.firstChild);
         } else {
             messageFeed.appendChild(item);
         }
+        // Статус мог обновиться раньше, чем пришло само сообщение
+        const statusKey = `${msg.target}/${msg.msg_id}`;
+        updateMessageStatus(msg.target, msg.msg_id, pendingStatuses.get(statusKey) || msg.status);
+        pendingStatuses.delete(statusKey);
+    }
+
+    // Перерисовывает текст, реакции и кнопки действий сообщения
+    function refreshMessage(item, msg) {
+        const bodyEl = item.querySelector('.message-body');
+        if (bodyEl) {
+            bodyEl.innerHTML = messageBodyHtml(msg);
+        }
+
+        const reactionsEl = item.querySelector('.message-reactions');
+        reactionsEl.innerHTML = '';
+        const counts = new Map();
+        msg.reactions.forEach(r => counts.set(r.emoji, (counts.get(r.emoji) || 0) + 1));
+        counts.forEach((count, emoji) => {
+            const chip = document.createElement('button');
+            chip.className = 'reaction-chip';
+            chip.classList.toggle('own', hasOwnReaction(msg, emoji));
+            chip.textContent = count > 1 ? `${emoji} ${count}` : emoji;
+            chip.title = msg.reactions.filter(r => r.emoji === emoji).map(r => r.from || 'you').join(', ');
+            chip.onclick = () => toggleReaction(msg, emoji);
+            reactionsEl.appendChild(chip);
+        });
+
+        const actionsEl = item.querySelector('.message-actions');
+        actionsEl.innerHTML = '';
+        if (msg.deleted) {
+            return;
+        }
+        const addAction = (label, title, handler) => {
+            const button = document.createElement('button');
+            button.textContent = label;
+            button.title = title;
+            button.onclick = handler;
+            actionsEl.appendChild(button);
+        };
+        addAction('↩', 'Reply', () => startReply(msg));
+        QUICK_REACTIONS.forEach(emoji => addAction(emoji, `React with ${emoji}`, () => toggleReaction(msg, emoji)));
+        // Править и удалять можно только свои текстовые сообщения
+        if (msg.direction === 'Outgoing' && ['Text', 'Reply'].includes(msg.content.type)) {
+            addAction('✎', 'Edit', () => editMessage(msg));
+            addAction('🗑', 'Delete for everyone', () => deleteMessage(msg));
+        }
+    }
+
+    function messageBodyHtml(msg) {
+        if (msg.deleted) {
+            return '<span class="message-deleted">Message deleted</span>';
+        }
+        const content = msg.content.payload;
+        const edited = msg.edited ? ' <span class="message-edited">(edited)</span>' : '';
+        if (msg.content.type === 'Reply') {
+            return `${quoteHtml(content.reply_to, msg)}${escapeHtml(content.text)}${edited}`;
+        }
+        if (msg.content.type === 'KeyRotation') {
+            return `🔑 Proposed a new key from ${new Date(content.activate_at).toLocaleString()}`;
+        }
+        return `${escapeHtml(content)}${edited}`;
+    }
+
+    // Цитата сообщения, на которое отвечают; если его нет в ленте, показываем только номер
+    function quoteHtml(replyTo, reply) {
+        const peer = reply.sender || reply.target;
+        const quoted = [...messagesById.values()]
+            .find(m => m.msg_id === replyTo && (m.sender || m.target) === peer);
+        let text = `#${replyTo}`;
+        if (quoted && quoted.deleted) {
+            text = 'Message deleted';
+        } else if (quoted && quoted.content.type === 'File') {
+            text = `📎 ${quoted.content.payload.filename}`;
+        } else if (quoted) {
+            text = quoted.content.type === 'Reply' ? quoted.content.payload.text : quoted.content.payload;
+        }
+        return `<div class="message-quote">${escapeHtml(text)}</div>`;
+    }
+
+    function updateMessage(id, change) {
+        const msg = messagesById.get(id);
+        const item = messageFeed.querySelector(`[data-id="${id}"]`);
+        if (!msg || !item) {
+            return;
+        }
+        change(msg);
+        refreshMessage(item, msg);
+    }
+
+    function removeMessage(id) {
+        messagesById.delete(id);
+        unreadMessages.delete(id);
+        const item = messageFeed.querySelector(`[data-id="${id}"]`);
+        if (item) {
+            item.remove();
+        }
+    }
+
+    function expiryHtml(msg) {
+        if (!msg.expires_at) {
+            return '';
+        }
+        const expiresAt = new Date(msg.expires_at);
+        return `<span class="message-expiry" data-expires="${expiresAt.getTime()}"
+            title="Disappears at ${expiresAt.toLocaleString()}">⏳ ${formatRemaining(expiresAt.getTime())}</span>`;
+    }
+
+    function formatRemaining(expiresAt) {
+        const seconds = Math.max(0, Math.round((expiresAt - Date.now()) / 1000));
+        if (seconds < 60) return `${seconds}s`;
+        if (seconds < 3600) return `${Math.floor(seconds / 60)}m`;
+        if (seconds < 86400) return `${Math.floor(seconds / 3600)}h`;
+        return `${Math.floor(seconds / 86400)}d`;
+    }
+
+    // Обратный отсчёт; сами сообщения стирает сервер и присылает MessageExpired
+    function updateExpiryCountdowns() {
+        messageFeed.querySelectorAll('.message-expiry').forEach(el => {
+            el.textContent = `⏳ ${formatRemaining(Number(el.dataset.expires))}`;
+        });
+    }
+
+    function showTyping(peer, active) {
+        clearTimeout(typingPeers.get(peer));
+        typingPeers.delete(peer);
+        if (active) {
+            typingPeers.set(peer, setTimeout(() => showTyping(peer, false), TYPING_TIMEOUT_MS));
+        }
+        const peers = [...typingPeers.keys()];
+        typingIndicator.hidden = peers.length === 0;
+        typingIndicator.textContent = `${peers.join(', ')} ${peers.length > 1 ? 'are' : 'is'} typing…`;
+    }
+
+    // msg_id уникален только вместе с адресом получателя
+    function findOutgoing(target, msgId) {
+        return messageFeed.querySelector(`.outgoing[data-msg-id="${msgId}"][data-target="${target}"]`);
+    }
+
+    function updateMessageStatus(target, msgId, status) {
+        const item = findOutgoing(target, msgId);
+        if (!item) {
+            pendingStatuses.set(`${target}/${msgId}`, status);
+            return;
+        }
+        const statusEl = item.querySelector('.message-status');
+        const { mark, title } = STATUS_MARKS[status] || { mark: '?', title: status };
+        statusEl.textContent = mark;
+        statusEl.title = title;
+        statusEl.className = `message-status status-${status.toLowerCase()}`;
+        // Отменить можно только то, что ещё стоит в очереди передатчика
+        item.querySelector('.cancel-send').hidden = status !== 'Queued';
+        if (status !== 'Queued') {
+            item.querySelector('.transmit-progress').hidden = true;
+        }
+    }
+
+    function updateTransmitProgress({ target, msg_id, chunks_sent, total_chunks }) {
+        const item = findOutgoing(target, msg_id);
+        if (!item) {
+            return;
+        }
+        const progress = item.querySelector('.transmit-progress');
+        progress.max = total_chunks;
+        progress.value = chunks_sent;
+        progress.title = `${chunks_sent} / ${total_chunks} chunks`;
+        // Для однопакетных сообщений полоса прогресса не нужна
+        progress.hidden = chunks_sent >= total_chunks;
+    }
+
+    // Отправляем квитанции о прочтении, только когда вкладка видна пользователю
+    async function acknowledgeReadMessages() {
+        if (document.visibilityState !== 'visible') {
+            return;
+        }
+        for (const id of Array.from(unreadMessages)) {
+            unreadMessages.delete(id);
+            await fetch(`/messages/${id}/read`, { method: 'POST' }).catch(error => {
+                console.error(`Failed to acknowledge message ${id}:`, error);
+            });
+        }
     }
 
     function renderTraffic(packet) {
@@ -178,6 +711,15 @@ document.addEventListener('DOMContentLoaded', () => {
         statNoiseSent.textContent = stats.noise_packets_sent;
         statReceived.textContent = stats.packets_received;
         statDecrypted.textContent = stats.messages_decrypted;
+        statThroughput.textContent = `${(stats.throughput_bps / 1024).toFixed(1)} KB/s`;
+        statRtt.textContent = stats.rtt_ms > 0 ? `${stats.rtt_ms.toFixed(1)} ms` : '—';
+        statRetransmissions.textContent = stats.retransmissions;
+        statNoiseReceived.textContent = stats.noise_packets_received;
+        // Очереди расшифровки и сборки
+        statQueues.textContent = `${stats.decrypt_queue_depth} / ${stats.reassembly_queue_depth}`;
+        statStalls.textContent = stats.backpressure_stalls;
+        statOversized.textContent = `${stats.oversized_datagrams} / ${stats.truncated_datagrams}`;
+        statKernelDrops.textContent = stats.kernel_drops ?? '—';
     }
 
     // --- Функции для взаимодействия с API ---
@@ -201,18 +743,213 @@ document.addEventListener('DOMContentLoaded', () => {
         }
     }
 
-    async function addKey(key) {
-        await apiFetch('/keys', 'POST', { key });
+    async function addKey(key, label) {
+        await apiFetch('/keys', 'POST', { key, label });
+    }
+
+    async function generateKey() {
+        const response = await apiFetch('/keys/generate', 'POST', { label: keyLabelInput.value.trim() || null });
+        if (response) {
+            keyLabelInput.value = '';
+            showKeyExport(await response.json());
+        }
+    }
+
+    async function exportKey(key) {
+        const response = await apiFetch('/keys/export', 'POST', { key });
+        if (response) {
+            showKeyExport(await response.json());
+        }
+    }
+
+    async function importKey() {
+        const text = prompt('Paste an exported key (ASEMIC-KEY-V1:…):');
+        if (text && text.trim()) {
+            await apiFetch('/keys/import', 'POST', { text });
+        }
+    }
+
+    async function showKeyExport({ key, label, text }) {
+        document.getElementById('key-export-label').textContent = label || shortKey(key);
+        document.getElementById('key-export-text').value = text;
+        const qr = document.getElementById('key-export-qr');
+        qr.innerHTML = '';
+        keyExport.hidden = false;
+        const response = await apiFetch('/keys/export/qr', 'POST', { key });
+        if (response) {
+            qr.innerHTML = await response.text();
+        }
+    }
+
+    function hideKeyExport() {
+        keyExport.hidden = true;
+        // Не оставляем ключ в DOM дольше, чем нужно
+        document.getElementById('key-export-text').value = '';
+        document.getElementById('key-export-qr').innerHTML = '';
+    }
+
+    // Грубая оценка энтропии: длина × log2(алфавита) по встреченным классам символов,
+    // повторяющиеся символы считаются один раз. Для словарных паролей это завышение.
+    function estimateEntropyBits(key) {
+        let pool = 0;
+        if (/[a-z]/.test(key)) pool += 26;
+        if (/[A-Z]/.test(key)) pool += 26;
+        if (/[0-9]/.test(key)) pool += 10;
+        if (/[^a-zA-Z0-9]/.test(key)) pool += 33;
+        return new Set(key).size * Math.log2(Math.max(pool, 1));
+    }
+
+    function updateEntropyWarning() {
+        const key = keyInput.value.trim();
+        const bits = estimateEntropyBits(key);
+        keyEntropyWarning.hidden = !key || bits >= MIN_KEY_ENTROPY_BITS;
+        keyEntropyWarning.textContent = `Weak key: about ${Math.round(bits)} bits of entropy. Prefer "Generate random key".`;
     }
 
     async function removeKey(key) {
         await apiFetch('/keys', 'DELETE', { key });
     }
 
+    async function setKeyCompression(key, compression) {
+        await apiFetch('/keys/compression', 'POST', { key, compression });
+    }
+
+    async function setKeyTtl(key, ttlSecs) {
+        await apiFetch('/keys/ttl', 'POST', { key, ttl_secs: ttlSecs });
+    }
+
+    async function rotateKey(key) {
+        const targetAddr = prompt(`Propose a new key instead of "${shortKey(key)}" to peer (IP:port):`, targetAddrInput.value.trim());
+        if (!targetAddr) {
+            return;
+        }
+        await apiFetch('/keys/rotate', 'POST', { key, target_addr: targetAddr.trim(), pattern: sendPatternSelect.value });
+    }
+
     async function setNoiseLevel(level) {
         await apiFetch('/config/noise', 'POST', { level });
     }
 
+    async function cancelMessage(id) {
+        await apiFetch(`/send/${id}/cancel`, 'POST');
+    }
+
+    async function acceptFile(id) {
+        await apiFetch(`/files/${id}/accept`, 'POST');
+    }
+
+    async function rejectFile(id) {
+        await apiFetch(`/files/${id}/reject`, 'POST');
+    }
+
+    const MB = 1024 * 1024;
+    const splitList = value => value.split(',').map(item => item.trim()).filter(Boolean);
+
+    async function loadFilePolicy() {
+        const response = await apiFetch('/config/files', 'GET');
+        if (!response) return;
+        const policy = await response.json();
+        document.getElementById('policy-auto-accept').value = policy.auto_accept_from.join(', ');
+        document.getElementById('policy-auto-accept-max').value = Math.round(policy.auto_accept_max_bytes / MB);
+        document.getElementById('policy-max-size').value = Math.round(policy.max_file_bytes / MB);
+        document.getElementById('policy-allowed').value = policy.allowed_extensions.join(', ');
+        document.getElementById('policy-denied').value = policy.denied_extensions.join(', ');
+    }
+
+    async function saveFilePolicy() {
+        await apiFetch('/config/files', 'POST', {
+            auto_accept_from: splitList(document.getElementById('policy-auto-accept').value),
+            auto_accept_max_bytes: Number(document.getElementById('policy-auto-accept-max').value) * MB,
+            max_file_bytes: Number(document.getElementById('policy-max-size').value) * MB,
+            allowed_extensions: splitList(document.getElementById('policy-allowed').value),
+            denied_extensions: splitList(document.getElementById('policy-denied').value),
+        });
+    }
+
+    // Обработчики для кнопок, созданных через innerHTML
+    async function deleteFile(id) {
+        if (confirm('Delete this file from disk?')) {
+            await apiFetch(`/files/${id}`, 'DELETE');
+        }
+    }
+
+    window.asemic = { acceptFile, rejectFile, deleteFile };
+
+    // Действие над сообщением уходит тому же собеседнику, тем же ключом и паттерном
+    async function sendAction(msg, content) {
+        return await sendMessage({
+            target_addr: msg.sender || msg.target,
+            key: msg.decrypted_with_key || msg.sent_with_key,
+            pattern: msg.decrypted_with_pattern || msg.sent_with_pattern,
+            content,
+        });
+    }
+
+    // Своя реакция хранится без адреса отправителя
+    function hasOwnReaction(msg, emoji) {
+        return msg.reactions.some(r => r.emoji === emoji && !r.from);
+    }
+
+    async function toggleReaction(msg, emoji) {
+        await sendAction(msg, {
+            type: 'Reaction',
+            payload: { target: msg.msg_id, emoji, remove: hasOwnReaction(msg, emoji) }
+        });
+    }
+
+    async function editMessage(msg) {
+        const current = msg.content.type === 'Reply' ? msg.content.payload.text : msg.content.payload;
+        const text = prompt('Edit message:', current);
+        if (text === null || !text.trim() || text === current) {
+            return;
+        }
+        await sendAction(msg, { type: 'Edit', payload: { target: msg.msg_id, text } });
+    }
+
+    async function deleteMessage(msg) {
+        if (confirm('Delete this message for everyone?')) {
+            await sendAction(msg, { type: 'Delete', payload: { target: msg.msg_id } });
+        }
+    }
+
+    // Ответ отправляется через обычную форму: подставляем в неё собеседника и ключ
+    function startReply(msg) {
+        replyTarget = msg;
+        targetAddrInput.value = msg.sender || msg.target;
+        sendKeySelect.value = msg.decrypted_with_key || msg.sent_with_key;
+        sendPatternSelect.value = msg.decrypted_with_pattern || msg.sent_with_pattern;
+        updateCurrentKeyDisplay();
+        replyIndicator.querySelector('.reply-text').innerHTML = `Replying to ${quoteHtml(msg.msg_id, msg)}`;
+        replyIndicator.hidden = false;
+        messageTextInput.focus();
+    }
+
+    function cancelReply() {
+        replyTarget = null;
+        replyIndicator.hidden = true;
+    }
+
+    function notifyTyping() {
+        const targetAddr = targetAddrInput.value.trim();
+        const key = sendKeySelect.value;
+        const now = Date.now();
+        if (!targetAddr || !key || now - lastTypingSent < TYPING_THROTTLE_MS) {
+            return;
+        }
+        lastTypingSent = now;
+        // Ошибки не показываем: индикатор набора не стоит всплывающего окна
+        fetch('/send', {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({
+                target_addr: targetAddr,
+                key,
+                pattern: sendPatternSelect.value,
+                content: { type: 'Typing', payload: { active: true } }
+            })
+        }).catch(() => {});
+    }
+
     async function sendMessage(payload) {
         return await apiFetch('/send', 'POST', payload);
     }
@@ -222,12 +959,24 @@ document.addEventListener('DOMContentLoaded', () => {
     addKeyForm.addEventListener('submit', (e) => {
         e.preventDefault();
         const key = keyInput.value.trim();
-        if (key) {
-            addKey(key);
-            keyInput.value = '';
+        if (!key) {
+            return;
+        }
+        if (estimateEntropyBits(key) < MIN_KEY_ENTROPY_BITS
+            && !confirm('This key is easy to guess. Anyone who guesses it can read your messages. Add it anyway?')) {
+            return;
         }
+        addKey(key, keyLabelInput.value.trim() || null);
+        keyInput.value = '';
+        keyLabelInput.value = '';
+        updateEntropyWarning();
     });
 
+    keyInput.addEventListener('input', updateEntropyWarning);
+    document.getElementById('generate-key').addEventListener('click', generateKey);
+    document.getElementById('import-key').addEventListener('click', importKey);
+    document.getElementById('key-export-close').addEventListener('click', hideKeyExport);
+
     sendMessageForm.addEventListener('submit', async (e) => {
         e.preventDefault();
         const targetAddr = targetAddrInput.value.trim();
@@ -255,6 +1004,8 @@ document.addEventListener('DOMContentLoaded', () => {
                 alert(`Error reading file: ${error.message}`);
                 return;
             }
+        } else if (replyTarget) {
+            content = { type: 'Reply', payload: { reply_to: replyTarget.msg_id, text } };
         } else {
             // Иначе используем текст
             content = { type: 'Text', payload: text };
@@ -264,7 +1015,10 @@ document.addEventListener('DOMContentLoaded', () => {
             target_addr: targetAddr,
             key: key,
             pattern: pattern, // КЛЮЧЕВОЕ ИСПРАВЛЕНИЕ: Добавляем pattern в запрос
-            content: content
+            content: content,
+            redundancy: parseFloat(sendRedundancySelect.value),
+            // Пустое значение — взять TTL из настроек ключа
+            ttl_secs: sendTtlSelect.value === '' ? null : parseInt(sendTtlSelect.value, 10)
         };
 
         const response = await sendMessage(payload);
@@ -274,8 +1028,13 @@ document.addEventListener('DOMContentLoaded', () => {
             messageTextInput.value = '';
             fileInput.value = '';
             fileNameDisplay.textContent = '';
+            cancelReply();
+            lastTypingSent = 0;
         }
     });
+
+    messageTextInput.addEventListener('input', notifyTyping);
+    replyIndicator.querySelector('button').addEventListener('click', cancelReply);
     
     fileInput.addEventListener('change', () => {
         if (fileInput.files.length > 0) {
@@ -288,12 +1047,19 @@ document.addEventListener('DOMContentLoaded', () => {
     
     sendKeySelect.addEventListener('change', updateCurrentKeyDisplay);
 
+    document.addEventListener('visibilitychange', acknowledgeReadMessages);
+
     noiseLevelRadios.forEach(radio => {
         radio.addEventListener('change', (e) => {
             setNoiseLevel(e.target.value);
         });
     });
 
+    filePolicyForm.addEventListener('submit', (e) => {
+        e.preventDefault();
+        saveFilePolicy();
+    });
+
     // --- Утилиты ---
 
     function fileToBase64(file) {
@@ -310,6 +1076,12 @@ document.addEventListener('DOMContentLoaded', () => {
         });
     }
     
+    function formatBytes(bytes) {
+        if (bytes < 1024) return `${bytes} B`;
+        if (bytes < 1024 * 1024) return `${(bytes / 1024).toFixed(1)} KB`;
+        return `${(bytes / (1024 * 1024)).toFixed(1)} MB`;
+    }
+
     function clearFeedPlaceholder(feedElement) {
         const placeholder = feedElement.querySelector('.feed-placeholder');
         if (placeholder) {
@@ -328,4 +1100,6 @@ document.addEventListener('DOMContentLoaded', () => {
 
     // --- Запуск ---
     connectWebSocket();
+    setInterval(updateExpiryCountdowns, 1000);
+    loadFilePolicy();
 });
\ No newline at end of file